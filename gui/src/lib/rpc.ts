@@ -0,0 +1,80 @@
+// JSON-RPC 2.0 client for `rusty-dbg --gui <port>`
+
+export interface StopEvent {
+    status: "stopped" | "exited";
+    reason?: string;
+    pid: number;
+    tid?: number;
+    threads?: number[];
+    rip?: number;
+    location?: string;
+    breakpoint?: number;
+    signal?: string;
+    description?: string;
+    exitCode?: number;
+}
+
+export interface Register {
+    name: string;
+    value: string;
+}
+
+export interface Breakpoint {
+    id: number;
+    address: number;
+    location: string;
+    enabled: boolean;
+    temporary: boolean;
+    hits: number;
+    condition: string | null;
+}
+
+type Handler = (params: any) => void;
+
+export class RpcClient {
+    private socket: WebSocket;
+    private nextId = 1;
+    private pending = new Map<number, { resolve: (v: any) => void; reject: (e: Error) => void }>();
+    private handlers = new Map<string, Handler[]>();
+    readonly ready: Promise<void>;
+
+    constructor(url: string) {
+        this.socket = new WebSocket(url);
+        this.ready = new Promise((resolve, reject) => {
+            this.socket.addEventListener("open", () => resolve());
+            this.socket.addEventListener("error", () => reject(new Error(`cannot connect to ${url}`)));
+        });
+        this.socket.addEventListener("message", (event) => this.receive(JSON.parse(event.data)));
+        this.socket.addEventListener("close", () => this.emit("close", null));
+    }
+
+    call<T = any>(method: string, params?: object): Promise<T> {
+        const id = this.nextId++;
+        this.socket.send(JSON.stringify({ jsonrpc: "2.0", id, method, params }));
+        return new Promise((resolve, reject) => this.pending.set(id, { resolve, reject }));
+    }
+
+    // `running`, `stopped` and `close`
+    on(method: string, handler: Handler) {
+        this.handlers.set(method, [...(this.handlers.get(method) ?? []), handler]);
+    }
+
+    private receive(message: any) {
+        if (message.id == null) {
+            this.emit(message.method, message.params);
+            return;
+        }
+        const pending = this.pending.get(message.id);
+        if (!pending) return;
+        this.pending.delete(message.id);
+        if (message.error) {
+            pending.reject(new Error(message.error.message));
+        } else {
+            pending.resolve(message.result);
+        }
+    }
+
+    private emit(method: string, params: any) {
+        for (const handler of this.handlers.get(method) ?? []) handler(params);
+    }
+}
@@ -1,10 +1,19 @@
 use crate::commands::DebugCommand;
+use crate::core::breakpoint::BreakpointKind;
 use crate::core::breakpoint_helpers::*;
+use crate::core::expression;
+use crate::core::syscalls::syscall_name;
+use crate::core::watchpoint::WatchKind;
 use crate::core::Debugger;
 use anyhow::Result;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::io::{stdin, IsTerminal};
 #[derive(Clone)]
 pub struct BreakpointCommand;
 #[derive(Clone)]
+pub struct TemporaryBreakpointCommand;
+#[derive(Clone)]
 pub struct RemoveBreakpointCommand;
 
 impl DebugCommand for BreakpointCommand {
@@ -17,11 +26,40 @@ impl DebugCommand for BreakpointCommand {
     }
 
     fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
-        let arg = args
-            .get(0)
-            .ok_or_else(|| anyhow::anyhow!("Usage: bp <address>"))?;
-        let bp_addr = debugger.set_breakpoint_by_input(arg)?;
-        println!("breakpoint set at 0x{:x}", bp_addr);
+        let (location, condition) = parse_location(args, "bp")?;
+        for (id, bp_addr) in debugger.set_breakpoint_by_input(&location)? {
+            debugger.breakpoint.set_condition(id, condition.clone())?;
+            println!(
+                "breakpoint {} set at 0x{:x} ({})",
+                id,
+                bp_addr,
+                debugger.describe_location(bp_addr)
+            );
+        }
+        Ok(())
+    }
+}
+
+impl DebugCommand for TemporaryBreakpointCommand {
+    fn name(&self) -> &'static str {
+        "tbreak"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["tb"]
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let (location, condition) = parse_location(args, "tbreak")?;
+        for (id, bp_addr) in debugger.set_temporary_breakpoint_by_input(&location)? {
+            debugger.breakpoint.set_condition(id, condition.clone())?;
+            println!(
+                "temporary breakpoint {} set at 0x{:x} ({})",
+                id,
+                bp_addr,
+                debugger.describe_location(bp_addr)
+            );
+        }
         Ok(())
     }
 }
@@ -36,10 +74,11 @@ impl DebugCommand for RemoveBreakpointCommand {
     }
 
     fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
-        let addr_str = args
-            .get(0)
-            .ok_or_else(|| anyhow::anyhow!("Usage: rmb <address>"))?;
-        debugger.rm_breakpoint_by_input(addr_str)?;
+        if args.is_empty() {
+            anyhow::bail!("Usage: rmb <address>");
+        }
+        let addr_str = args.join(" ");
+        debugger.rm_breakpoint_by_input(&addr_str)?;
         println!("breakpoint removed at {}", addr_str);
         Ok(())
     }
@@ -58,9 +97,321 @@ impl DebugCommand for ShowBreakpointsCommand {
     }
 
     fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        println!(
+            "{:<4} {:<6} {:<4} {:<18} {:<6} What",
+            "Num", "Type", "Enb", "Address", "Hits"
+        );
         for entry in debugger.list_breakpoints() {
-            println!("0x{:x} (original byte: {:02x})", entry.0, entry.1);
+            let kind = match entry.kind {
+                BreakpointKind::Persistent => "keep",
+                BreakpointKind::Temporary | BreakpointKind::Internal => "del",
+            };
+            println!(
+                "{:<4} {:<6} {:<4} 0x{:016x} {:<6} {}",
+                entry.id,
+                kind,
+                if entry.enabled { "y" } else { "n" },
+                entry.addr,
+                entry.hit_count,
+                debugger.describe_location(entry.addr)
+            );
+            if let Some(condition) = &entry.condition {
+                println!("        stop only if {}", condition);
+            }
+            for command in &entry.commands {
+                println!("        {}", command);
+            }
+        }
+        for wp in debugger.watchpoints.iter() {
+            let kind = match wp.kind {
+                WatchKind::Execute => "hw",
+                WatchKind::Write => "watch",
+                WatchKind::Read => "rwatch",
+                WatchKind::ReadWrite => "awatch",
+            };
+            let what = match wp.kind {
+                WatchKind::Execute => debugger.describe_location(wp.addr),
+                _ => format!("{} bytes", wp.len),
+            };
+            println!(
+                "{:<4} {:<6} {:<4} 0x{:016x} {:<6} {}",
+                wp.id,
+                kind,
+                if wp.enabled { "y" } else { "n" },
+                wp.addr,
+                wp.hit_count,
+                what
+            );
+        }
+        for catch in &debugger.syscalls.catches {
+            let names: Vec<String> = catch
+                .numbers
+                .iter()
+                .map(|nr| syscall_name(*nr).map_or(nr.to_string(), str::to_string))
+                .collect();
+            let what = if names.is_empty() {
+                "any syscall".to_string()
+            } else {
+                format!("syscall {}", names.join(" "))
+            };
+            println!(
+                "{:<4} {:<6} {:<4} {:<18} {:<6} {}",
+                catch.id, "catch", "y", "", catch.hit_count, what
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct EnableBreakpointCommand;
+
+impl DebugCommand for EnableBreakpointCommand {
+    fn name(&self) -> &'static str {
+        "enable"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let id = parse_breakpoint_id(args, "Usage: enable <id>")?;
+        if debugger.watchpoints.slot_of(id).is_some() {
+            debugger.watchpoints.set_enabled(
+                id,
+                true,
+                debugger.target.as_mut(),
+                &debugger.process.thread_ids(),
+            )?;
+        } else {
+            debugger.breakpoint.enable(id, debugger.target.as_mut())?;
+        }
+        println!("breakpoint {} enabled", id);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct DisableBreakpointCommand;
+
+impl DebugCommand for DisableBreakpointCommand {
+    fn name(&self) -> &'static str {
+        "disable"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let id = parse_breakpoint_id(args, "Usage: disable <id>")?;
+        if debugger.watchpoints.slot_of(id).is_some() {
+            debugger.watchpoints.set_enabled(
+                id,
+                false,
+                debugger.target.as_mut(),
+                &debugger.process.thread_ids(),
+            )?;
+        } else {
+            debugger.breakpoint.disable(id, debugger.target.as_mut())?;
+        }
+        println!("breakpoint {} disabled", id);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct DeleteBreakpointCommand;
+
+impl DebugCommand for DeleteBreakpointCommand {
+    fn name(&self) -> &'static str {
+        "delete"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["del"]
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        if args.first() == Some(&"all") {
+            debugger.breakpoint.remove_all(debugger.target.as_mut())?;
+            debugger
+                .watchpoints
+                .remove_all(debugger.target.as_mut(), &debugger.process.thread_ids())?;
+            debugger.syscalls.catches.clear();
+            println!("all breakpoints deleted");
+            return Ok(());
         }
+        let id = parse_breakpoint_id(args, "Usage: delete <id|all>")?;
+        if debugger.watchpoints.slot_of(id).is_some() {
+            debugger.watchpoints.remove(
+                id,
+                debugger.target.as_mut(),
+                &debugger.process.thread_ids(),
+            )?;
+        } else if !debugger.syscalls.remove_catch(id) {
+            debugger
+                .breakpoint
+                .remove_by_id(id, debugger.target.as_mut())?;
+        }
+        println!("breakpoint {} deleted", id);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct ConditionCommand;
+
+impl DebugCommand for ConditionCommand {
+    fn name(&self) -> &'static str {
+        "condition"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let id = parse_breakpoint_id(args, "Usage: condition <id> [expression]")?;
+        let condition = if args.len() > 1 {
+            let condition = args[1..].join(" ");
+            expression::parse(&condition)?;
+            Some(condition)
+        } else {
+            None
+        };
+        debugger.breakpoint.set_condition(id, condition)?;
+        println!("condition of breakpoint {} updated", id);
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct CommandsCommand;
+
+impl DebugCommand for CommandsCommand {
+    fn name(&self) -> &'static str {
+        "commands"
+    }
+
+    // `commands [id] [cmd; cmd; ...]`, without a list the commands are read
+    // until `end`, `commands <id> end` clears them
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let (id, list) = match args.first().map(|arg| arg.parse::<usize>()) {
+            Some(Ok(id)) => (id, &args[1..]),
+            _ => (last_breakpoint_id(debugger)?, args),
+        };
+        debugger.breakpoint.get_by_id(id)?;
+        let commands = if list.is_empty() {
+            read_command_list(id)?
+        } else {
+            list.join(" ")
+                .split(';')
+                .map(str::trim)
+                .take_while(|c| *c != "end")
+                .filter(|c| !c.is_empty())
+                .map(String::from)
+                .collect()
+        };
+        debugger.breakpoint.set_commands(id, commands)?;
+        println!("commands of breakpoint {} updated", id);
         Ok(())
     }
 }
+
+fn last_breakpoint_id(debugger: &Debugger) -> Result<usize> {
+    debugger
+        .list_breakpoints()
+        .map(|bp| bp.id)
+        .max()
+        .ok_or_else(|| anyhow::anyhow!("Usage: commands [id] [command; ...]"))
+}
+
+fn read_command_list(id: usize) -> Result<Vec<String>> {
+    if !stdin().is_terminal() {
+        anyhow::bail!("Usage: commands [id] <command; ...>");
+    }
+    println!("Type commands for breakpoint {}, one per line.", id);
+    println!("End with a line saying just \"end\".");
+    let mut rl = DefaultEditor::new()?;
+    let mut commands = Vec::new();
+    loop {
+        match rl.readline(">") {
+            Ok(line) if line.trim() == "end" => break,
+            Ok(line) if line.trim().is_empty() => {}
+            Ok(line) => commands.push(line.trim().to_string()),
+            Err(ReadlineError::Interrupted) => anyhow::bail!("Quit"),
+            Err(_) => break,
+        }
+    }
+    Ok(commands)
+}
+
+// `<location> [if <expr>]`, both may contain spaces; the condition is
+// checked for syntax errors up front
+fn parse_location(args: &[&str], command: &str) -> Result<(String, Option<String>)> {
+    let pos = args.iter().position(|a| *a == "if").unwrap_or(args.len());
+    if pos == 0 {
+        anyhow::bail!(
+            "Usage: {} <address|function|file:line> [if <condition>]",
+            command
+        );
+    }
+    let location = args[..pos].join(" ");
+    match &args[pos..] {
+        [] => Ok((location, None)),
+        [_, expr @ ..] if !expr.is_empty() => {
+            let condition = expr.join(" ");
+            expression::parse(&condition)?;
+            Ok((location, Some(condition)))
+        }
+        _ => anyhow::bail!("Expected a condition after `if`"),
+    }
+}
+
+fn parse_breakpoint_id(args: &[&str], usage: &str) -> Result<usize> {
+    args.first()
+        .ok_or_else(|| anyhow::anyhow!("{}", usage))?
+        .parse::<usize>()
+        .map_err(|_| anyhow::anyhow!("{}", usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::{split_expressions, CommandRouter};
+    use crate::core::breakpoint_helpers::tests::build_main_c;
+    use crate::core::process_control::ProcessControl;
+    use nix::sys::wait::WaitStatus;
+    use std::rc::Rc;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn test_location_with_spaces() {
+        let (location, condition) = parse_location(&["main", "+", "4"], "bp").unwrap();
+        assert_eq!((location.as_str(), condition), ("main + 4", None));
+        let args = ["*0x10", "if", "rdi", "==", "1"];
+        let (location, condition) = parse_location(&args, "bp").unwrap();
+        assert_eq!(location, "*0x10");
+        assert_eq!(condition.as_deref(), Some("rdi == 1"));
+        assert!(parse_location(&["main", "if"], "bp").is_err());
+        assert!(parse_location(&["if", "rdi"], "bp").is_err());
+
+        let split = split_expressions(&["$rsp", "+", "8", "16"]).unwrap();
+        assert_eq!(split, ("$rsp + 8".to_string(), "16".to_string()));
+        let split = split_expressions(&["(u8*)$rsp", "1", "+", "2"]).unwrap();
+        assert_eq!(split, ("(u8*)$rsp".to_string(), "1 + 2".to_string()));
+        assert!(split_expressions(&["$rsp", "+"]).is_none());
+    }
+
+    #[test]
+    fn test_silent_commands_continue() {
+        let binary = build_main_c("commands");
+        let mut debugger = Debugger::new(binary.clone(), "".to_string(), Vec::new()).unwrap();
+        debugger.process.get_base_addr_from_map();
+        debugger.command_runner = Some(Rc::new(CommandRouter::new()));
+
+        let router = CommandRouter::new();
+        for line in ["b main", "commands 1 silent; c", "c"] {
+            router.execute(line, &mut debugger).unwrap();
+        }
+        debugger.wait_for_stop();
+
+        // the hit resumed the debugee, which ran until it exited
+        assert_eq!(debugger.breakpoint.get_by_id(1).unwrap().hit_count, 1);
+        assert!(matches!(
+            debugger.last_status,
+            Some(WaitStatus::Exited(_, 1))
+        ));
+        let _ = fs::remove_dir_all(Path::new(&binary).parent().unwrap());
+    }
+}
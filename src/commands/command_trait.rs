@@ -7,6 +7,10 @@ pub trait DebugCommand: DebugCommandClone {
         &[]
     }
     fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()>;
+    // whether the command sets the debugee running
+    fn resumes(&self) -> bool {
+        false
+    }
 }
 
 pub trait DebugCommandClone {
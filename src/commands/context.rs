@@ -0,0 +1,22 @@
+use crate::commands::DebugCommand;
+use crate::core::context::Context;
+use crate::core::Debugger;
+use anyhow::Result;
+
+#[derive(Clone)]
+pub struct ContextCommand;
+
+impl DebugCommand for ContextCommand {
+    fn name(&self) -> &'static str {
+        "context"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["ctx"]
+    }
+
+    fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        debugger.print_context();
+        Ok(())
+    }
+}
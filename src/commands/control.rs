@@ -12,18 +12,34 @@ pub struct ContinueCommand;
 #[derive(Clone)]
 pub struct StepOverCommand;
 
+#[derive(Clone)]
+pub struct RunCommand;
+
+#[derive(Clone)]
+pub struct StepLineCommand;
+
+#[derive(Clone)]
+pub struct NextLineCommand;
+
+#[derive(Clone)]
+pub struct FinishCommand;
+
 impl DebugCommand for SingleStepCommand {
     fn name(&self) -> &'static str {
-        "step"
+        "stepi"
     }
 
     fn aliases(&self) -> &[&'static str] {
-        &["s"]
+        &["si"]
     }
 
     fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
         debugger.single_step()
     }
+
+    fn resumes(&self) -> bool {
+        true
+    }
 }
 
 impl DebugCommand for ContinueCommand {
@@ -38,9 +54,70 @@ impl DebugCommand for ContinueCommand {
     fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
         debugger.cont()
     }
+
+    fn resumes(&self) -> bool {
+        true
+    }
 }
 
 impl DebugCommand for StepOverCommand {
+    fn name(&self) -> &'static str {
+        "nexti"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["ni"]
+    }
+
+    fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        debugger.step_over()
+    }
+
+    fn resumes(&self) -> bool {
+        true
+    }
+}
+
+impl DebugCommand for RunCommand {
+    fn name(&self) -> &'static str {
+        "run"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["r"]
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        if !args.is_empty() {
+            debugger.launch.args = args.iter().map(|a| a.to_string()).collect();
+        }
+        debugger.run()
+    }
+
+    fn resumes(&self) -> bool {
+        true
+    }
+}
+
+impl DebugCommand for StepLineCommand {
+    fn name(&self) -> &'static str {
+        "step"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["s"]
+    }
+
+    fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        debugger.step_line(false)
+    }
+
+    fn resumes(&self) -> bool {
+        true
+    }
+}
+
+impl DebugCommand for NextLineCommand {
     fn name(&self) -> &'static str {
         "next"
     }
@@ -50,6 +127,28 @@ impl DebugCommand for StepOverCommand {
     }
 
     fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
-        debugger.step_over()
+        debugger.step_line(true)
+    }
+
+    fn resumes(&self) -> bool {
+        true
+    }
+}
+
+impl DebugCommand for FinishCommand {
+    fn name(&self) -> &'static str {
+        "finish"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["step-out"]
+    }
+
+    fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        debugger.finish()
+    }
+
+    fn resumes(&self) -> bool {
+        true
     }
 }
@@ -1,7 +1,8 @@
-use crate::commands::DebugCommand;
+use crate::commands::{split_expressions, DebugCommand};
+use crate::core::expression;
 use crate::core::memory::Memory;
 use crate::core::Debugger;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 #[derive(Clone)]
 pub struct DumpHexCommand;
@@ -16,13 +17,19 @@ impl DebugCommand for DumpHexCommand {
     }
 
     fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
-        let addr = args
-            .get(0)
-            .ok_or_else(|| anyhow::anyhow!("Usage: dump <address> [size]"))?;
-        let size = args
-            .get(1)
-            .and_then(|s| s.parse::<usize>().ok())
-            .unwrap_or(128);
-        debugger.dump_hex(addr, size)
+        if args.is_empty() {
+            bail!("Usage: dump <address> [size]");
+        }
+        // `dump $rsp + 8` is one address, `dump $rsp + 8 16` has a size
+        let addr = args.join(" ");
+        if expression::parse(&addr).is_ok() {
+            return debugger.dump_hex(&addr, 128);
+        }
+        let Some((addr, size)) = split_expressions(args) else {
+            // reports the error of the address
+            return debugger.dump_hex(&addr, 128);
+        };
+        let size = debugger.parse_address(&size)?;
+        debugger.dump_hex(&addr, size as usize)
     }
 }
@@ -0,0 +1,26 @@
+use crate::commands::DebugCommand;
+use crate::core::gcore::CoreDump;
+use crate::core::Debugger;
+use anyhow::Result;
+
+#[derive(Clone)]
+pub struct GcoreCommand;
+
+impl DebugCommand for GcoreCommand {
+    fn name(&self) -> &'static str {
+        "gcore"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["generate-core-file"]
+    }
+
+    // `gcore [file]`, core.<pid> by default like gdb
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let path = match args.first() {
+            Some(path) => path.to_string(),
+            None => format!("core.{}", debugger.process.pid),
+        };
+        debugger.generate_core(&path)
+    }
+}
@@ -0,0 +1,63 @@
+use crate::commands::DebugCommand;
+use crate::core::context::Context;
+use crate::core::fork::ForkHandling;
+use crate::core::Debugger;
+use anyhow::Result;
+use nix::unistd::Pid;
+
+#[derive(Clone)]
+pub struct InferiorsCommand;
+
+#[derive(Clone)]
+pub struct InferiorCommand;
+
+#[derive(Clone)]
+pub struct DetachInferiorCommand;
+
+impl DebugCommand for InferiorsCommand {
+    fn name(&self) -> &'static str {
+        "inferiors"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["info-inferiors"]
+    }
+
+    fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        debugger.print_inferiors();
+        Ok(())
+    }
+}
+
+impl DebugCommand for InferiorCommand {
+    fn name(&self) -> &'static str {
+        "inferior"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let Some(pid) = args.first() else {
+            println!("Current process is {}", debugger.process.pid);
+            return Ok(());
+        };
+        let pid = pid
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Usage: inferior <pid>"))?;
+        debugger.switch_inferior(Pid::from_raw(pid))?;
+        debugger.print_context();
+        Ok(())
+    }
+}
+
+impl DebugCommand for DetachInferiorCommand {
+    fn name(&self) -> &'static str {
+        "detach-inferior"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let pid = args
+            .first()
+            .and_then(|pid| pid.parse().ok())
+            .ok_or_else(|| anyhow::anyhow!("Usage: detach-inferior <pid>"))?;
+        debugger.detach_inferior(Pid::from_raw(pid))
+    }
+}
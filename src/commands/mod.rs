@@ -1,30 +1,71 @@
 pub mod backtrace;
 pub mod breakpoint;
 pub mod command_trait;
+pub mod context;
 pub mod control;
 pub mod disassemble;
 pub mod dump_hex;
 pub mod exit;
+pub mod gcore;
+pub mod inferiors;
 pub mod offset;
 pub mod patch;
+pub mod print;
 pub mod registers;
+pub mod script;
 pub mod sections;
+pub mod settings;
+pub mod signals;
+pub mod source;
+pub mod syscalls;
+pub mod threads;
+pub mod watchpoint;
 
 use crate::commands::backtrace::BacktraceCommand;
+use crate::commands::breakpoint::CommandsCommand;
+use crate::commands::breakpoint::ConditionCommand;
+use crate::commands::breakpoint::DeleteBreakpointCommand;
+use crate::commands::breakpoint::DisableBreakpointCommand;
+use crate::commands::breakpoint::EnableBreakpointCommand;
 use crate::commands::breakpoint::ShowBreakpointsCommand;
+use crate::commands::context::ContextCommand;
 use crate::commands::control::ContinueCommand;
+use crate::commands::control::FinishCommand;
+use crate::commands::control::NextLineCommand;
+use crate::commands::control::RunCommand;
+use crate::commands::control::StepLineCommand;
 use crate::commands::control::StepOverCommand;
 use crate::commands::disassemble::DisassembleCommand;
 use crate::commands::exit::ExitCommand;
+use crate::commands::gcore::GcoreCommand;
+use crate::commands::inferiors::DetachInferiorCommand;
+use crate::commands::inferiors::InferiorCommand;
+use crate::commands::inferiors::InferiorsCommand;
 use crate::commands::offset::OffsetCommand;
 use crate::commands::patch::PatchCommand;
+use crate::commands::print::PrintCommand;
 use crate::commands::registers::GetAllRegistersCommand;
 use crate::commands::registers::GetRegisterCommand;
 use crate::commands::registers::SetRegisterCommand;
+use crate::commands::script::ScriptCommand;
 use crate::commands::sections::SectionsCommand;
-use crate::core::Debugger;
+use crate::commands::settings::SetCommand;
+use crate::commands::signals::HandleCommand;
+use crate::commands::source::SourceCommand;
+use crate::commands::syscalls::CatchCommand;
+use crate::commands::syscalls::StraceCommand;
+use crate::commands::threads::ThreadCommand;
+use crate::commands::threads::ThreadsCommand;
+use crate::commands::watchpoint::AccessWatchCommand;
+use crate::commands::watchpoint::HardwareBreakpointCommand;
+use crate::commands::watchpoint::ReadWatchCommand;
+use crate::commands::watchpoint::WatchCommand;
+use crate::core::expression;
+use crate::core::{CommandRunner, Debugger};
+use anyhow::{bail, Result};
 pub use breakpoint::BreakpointCommand;
 pub use breakpoint::RemoveBreakpointCommand;
+pub use breakpoint::TemporaryBreakpointCommand;
 pub use command_trait::DebugCommand;
 pub use control::SingleStepCommand;
 pub use dump_hex::DumpHexCommand;
@@ -43,21 +84,49 @@ impl CommandRouter {
 
         let all_commands: Vec<Box<dyn DebugCommand>> = vec![
             Box::new(BreakpointCommand),
+            Box::new(TemporaryBreakpointCommand),
             Box::new(RemoveBreakpointCommand),
             Box::new(DumpHexCommand),
             Box::new(SingleStepCommand),
             Box::new(ContinueCommand),
             Box::new(StepOverCommand),
+            Box::new(StepLineCommand),
+            Box::new(NextLineCommand),
+            Box::new(FinishCommand),
             Box::new(DisassembleCommand),
             Box::new(SectionsCommand),
             Box::new(OffsetCommand),
             Box::new(ShowBreakpointsCommand),
+            Box::new(EnableBreakpointCommand),
+            Box::new(DisableBreakpointCommand),
+            Box::new(DeleteBreakpointCommand),
+            Box::new(ConditionCommand),
+            Box::new(CommandsCommand),
+            Box::new(HardwareBreakpointCommand),
+            Box::new(WatchCommand),
+            Box::new(ReadWatchCommand),
+            Box::new(AccessWatchCommand),
             Box::new(ExitCommand),
             Box::new(BacktraceCommand),
+            Box::new(ContextCommand),
+            Box::new(ThreadsCommand),
+            Box::new(ThreadCommand),
+            Box::new(InferiorsCommand),
+            Box::new(InferiorCommand),
+            Box::new(DetachInferiorCommand),
             Box::new(SetRegisterCommand),
             Box::new(GetRegisterCommand),
             Box::new(GetAllRegistersCommand),
-            Box::new(PatchCommand)
+            Box::new(PatchCommand),
+            Box::new(PrintCommand),
+            Box::new(RunCommand),
+            Box::new(SetCommand),
+            Box::new(HandleCommand),
+            Box::new(CatchCommand),
+            Box::new(StraceCommand),
+            Box::new(GcoreCommand),
+            Box::new(ScriptCommand),
+            Box::new(SourceCommand),
         ];
 
         for cmd in all_commands {
@@ -86,4 +155,52 @@ impl CommandRouter {
             println!("Unknown command: {}", cmd);
         }
     }
+
+    // like handle, but the error goes to the caller
+    pub fn execute(&self, input: &str, dbg: &mut Debugger) -> Result<()> {
+        let mut parts = input.split_whitespace();
+        let Some(cmd) = parts.next() else {
+            return Ok(());
+        };
+        let args: Vec<&str> = parts.collect();
+        match self.commands.get(cmd) {
+            Some(command) => command.execute(&args, dbg),
+            None => bail!("Unknown command: {}", cmd),
+        }
+    }
+
+    // `c`, `next`, ... as opposed to commands that only inspect or modify
+    pub fn resumes(&self, input: &str) -> bool {
+        input
+            .split_whitespace()
+            .next()
+            .and_then(|cmd| self.commands.get(cmd))
+            .is_some_and(|command| command.resumes())
+    }
+}
+
+impl CommandRunner for CommandRouter {
+    fn execute(&self, line: &str, debugger: &mut Debugger) -> Result<()> {
+        CommandRouter::execute(self, line, debugger)
+    }
+
+    fn resumes(&self, line: &str) -> bool {
+        CommandRouter::resumes(self, line)
+    }
+}
+
+// `<expr> <expr>` where either side may contain spaces, split where both
+// halves parse with the first one as long as possible
+pub(crate) fn split_expressions(args: &[&str]) -> Option<(String, String)> {
+    (1..args.len()).rev().find_map(|i| {
+        let (first, second) = (args[..i].join(" "), args[i..].join(" "));
+        (expression::parse(&first).is_ok() && expression::parse(&second).is_ok())
+            .then_some((first, second))
+    })
+}
+
+impl Default for CommandRouter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
@@ -14,7 +14,7 @@ impl DebugCommand for OffsetCommand {
     }
 
     fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
-        debugger.print_offset();
+        debugger.print_offset()?;
         Ok(())
     }
 }
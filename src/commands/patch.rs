@@ -1,4 +1,4 @@
-use crate::commands::DebugCommand;
+use crate::commands::{split_expressions, DebugCommand};
 use crate::core::memory::Memory;
 use crate::core::Debugger;
 use anyhow::Result;
@@ -11,18 +11,10 @@ impl DebugCommand for PatchCommand {
         "patch"
     }
 
-    fn aliases(&self) -> &[&'static str] {
-        &["set"]
-    }
-
     fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
-        let addr_str = args
-            .get(0)
-            .ok_or_else(|| anyhow::anyhow!("Usage: patch <address> <value>"))?;
-        let value_str = args
-            .get(1)
+        let (addr_str, value_str) = split_expressions(args)
             .ok_or_else(|| anyhow::anyhow!("Usage: patch <address> <value>"))?;
-        debugger.patch(addr_str, value_str)?;
+        debugger.patch(&addr_str, &value_str)?;
         println!("Patched address {} with value {}", addr_str, value_str);
         Ok(())
     }
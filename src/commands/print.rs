@@ -0,0 +1,26 @@
+use crate::commands::DebugCommand;
+use crate::core::Debugger;
+use anyhow::{bail, Result};
+
+#[derive(Clone)]
+pub struct PrintCommand;
+
+impl DebugCommand for PrintCommand {
+    fn name(&self) -> &'static str {
+        "print"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["p"]
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        if args.is_empty() {
+            bail!("Usage: print <expr>");
+        }
+        // the whole line is one expression, spaces included
+        let value = debugger.parse_address(&args.join(" "))?;
+        println!("0x{:x} ({})", value, value as i64);
+        Ok(())
+    }
+}
@@ -18,17 +18,18 @@ impl DebugCommand for SetRegisterCommand {
     }
 
     fn aliases(&self) -> &[&'static str] {
-        &["set", "sr"]
+        &["sr"]
     }
 
     fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
         let reg = args
-            .get(0)
-            .ok_or_else(|| anyhow::anyhow!("Usage: set <reg> <value>"))?;
-        let value_str = args
-            .get(1)
-            .ok_or_else(|| anyhow::anyhow!("Usage: set <reg> <value>"))?;
-        debugger.set_register(reg, value_str)?;
+            .first()
+            .ok_or_else(|| anyhow::anyhow!("Usage: sr <reg> <value>"))?;
+        if args.len() < 2 {
+            anyhow::bail!("Usage: sr <reg> <value>");
+        }
+        let value_str = args[1..].join(" ");
+        debugger.set_register(reg, &value_str)?;
         println!("set {} to {}", reg, value_str);
         Ok(())
     }
@@ -45,7 +46,7 @@ impl DebugCommand for GetRegisterCommand {
 
     fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
         let reg = args
-            .get(0)
+            .first()
             .ok_or_else(|| anyhow::anyhow!("Usage: get <reg>"))?;
         let value = debugger.get_register_value(reg)?;
         println!("{}: 0x{:x}", reg, value);
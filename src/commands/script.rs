@@ -0,0 +1,69 @@
+use crate::commands::DebugCommand;
+use crate::core::script::{ScriptSession, Scripting};
+use crate::core::Debugger;
+use anyhow::{bail, Context, Result};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::fs;
+use std::io::{stdin, IsTerminal};
+
+#[derive(Clone)]
+pub struct ScriptCommand;
+
+impl DebugCommand for ScriptCommand {
+    fn name(&self) -> &'static str {
+        "script"
+    }
+
+    // `script <file>` runs a Rhai script, without a file statements are read
+    // until `end`
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        if let Some(path) = args.first() {
+            let source =
+                fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+            return debugger
+                .eval_script(&mut ScriptSession::default(), &source)
+                .map(|_| ());
+        }
+        if !stdin().is_terminal() {
+            bail!("Usage: script <file>");
+        }
+
+        let mut rl = DefaultEditor::new()?;
+        let mut session = ScriptSession::default();
+        let mut source = String::new();
+        println!("Rhai script mode, `end` or Ctrl-D to leave");
+        loop {
+            let prompt = if source.is_empty() {
+                "script> "
+            } else {
+                "...> "
+            };
+            let line = match rl.readline(prompt) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => {
+                    source.clear();
+                    continue;
+                }
+                Err(_) => break,
+            };
+            if source.is_empty() && line.trim() == "end" {
+                break;
+            }
+            let _ = rl.add_history_entry(&line);
+            source.push_str(&line);
+            source.push('\n');
+            // blocks continue on the next line
+            if source.matches('{').count() > source.matches('}').count() {
+                continue;
+            }
+            match debugger.eval_script(&mut session, &source) {
+                Ok(value) if !value.is_unit() => println!("{}", value),
+                Ok(_) => {}
+                Err(e) => println!("Error: {}", e),
+            }
+            source.clear();
+        }
+        Ok(())
+    }
+}
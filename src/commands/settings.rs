@@ -0,0 +1,123 @@
+use crate::commands::DebugCommand;
+use crate::core::context::SECTIONS;
+use crate::core::fork::FollowForkMode;
+use crate::core::registers::Registers;
+use crate::core::Debugger;
+use anyhow::{bail, Result};
+
+#[derive(Clone)]
+pub struct SetCommand;
+
+impl DebugCommand for SetCommand {
+    fn name(&self) -> &'static str {
+        "set"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let Some(setting) = args.first() else {
+            print_launch_config(debugger);
+            return Ok(());
+        };
+        let value = args.get(1..).unwrap_or(&[]);
+        if *setting == "context" {
+            return set_context(value, debugger);
+        }
+        if *setting == "follow-fork-mode" || *setting == "detach-on-fork" {
+            return set_fork_option(setting, value, debugger);
+        }
+        let launch = &mut debugger.launch;
+
+        match *setting {
+            "args" => launch.args = value.iter().map(|a| a.to_string()).collect(),
+            "env" => {
+                let Some((name, val)) = value.first().and_then(|v| v.split_once('=')) else {
+                    bail!("Usage: set env <name>=<value>");
+                };
+                launch.set_env(name, val);
+            }
+            "cwd" => launch.cwd = optional_path(value),
+            "stdin" => launch.stdin = optional_path(value),
+            "stdout" => launch.stdout = optional_path(value),
+            "stderr" => launch.stderr = optional_path(value),
+            "tty" => {
+                launch.stdin = optional_path(value);
+                launch.stdout = optional_path(value);
+                launch.stderr = optional_path(value);
+            }
+            reg => {
+                // `set <reg> <value>` predates the launch settings
+                let value_str = value
+                    .first()
+                    .ok_or_else(|| anyhow::anyhow!("Usage: set <setting|reg> <value>"))?;
+                debugger.set_register(reg, value_str)?;
+                println!("set {} to {}", reg, value_str);
+                return Ok(());
+            }
+        }
+        println!("{} will be used on the next `run`", setting);
+        Ok(())
+    }
+}
+
+// `set context <section|all> on|off`, no value lists the sections
+fn set_context(value: &[&str], debugger: &mut Debugger) -> Result<()> {
+    match value {
+        [] => {
+            for section in SECTIONS {
+                let state = if debugger.context.enabled(section) {
+                    "on"
+                } else {
+                    "off"
+                };
+                println!("{:<10} {}", section, state);
+            }
+            Ok(())
+        }
+        [section, "on"] => debugger.context.set(section, true),
+        [section, "off"] => debugger.context.set(section, false),
+        _ => bail!("Usage: set context <{}|all> on|off", SECTIONS.join("|")),
+    }
+}
+
+// takes effect at the next fork, no `run` needed
+fn set_fork_option(setting: &str, value: &[&str], debugger: &mut Debugger) -> Result<()> {
+    let forks = &mut debugger.forks;
+    match (setting, value) {
+        ("follow-fork-mode", ["parent"]) => forks.follow = FollowForkMode::Parent,
+        ("follow-fork-mode", ["child"]) => forks.follow = FollowForkMode::Child,
+        ("follow-fork-mode", _) => bail!("Usage: set follow-fork-mode parent|child"),
+        (_, ["on"]) => forks.detach_on_fork = true,
+        (_, ["off"]) => forks.detach_on_fork = false,
+        _ => bail!("Usage: set detach-on-fork on|off"),
+    }
+    Ok(())
+}
+
+// no value resets the setting to inherit from the debugger
+fn optional_path(value: &[&str]) -> Option<String> {
+    value.first().map(|v| v.to_string())
+}
+
+fn print_launch_config(debugger: &Debugger) {
+    let launch = &debugger.launch;
+    let or_inherited = |v: &Option<String>| v.clone().unwrap_or_else(|| "<inherited>".into());
+    println!("path:   {}", launch.path);
+    println!("args:   {}", launch.args.join(" "));
+    println!("cwd:    {}", or_inherited(&launch.cwd));
+    println!("stdin:  {}", or_inherited(&launch.stdin));
+    println!("stdout: {}", or_inherited(&launch.stdout));
+    println!("stderr: {}", or_inherited(&launch.stderr));
+    for (name, value) in &launch.env {
+        println!("env:    {}={}", name, value);
+    }
+    let forks = &debugger.forks;
+    let follow = match forks.follow {
+        FollowForkMode::Parent => "parent",
+        FollowForkMode::Child => "child",
+    };
+    println!("follow-fork-mode: {}", follow);
+    println!(
+        "detach-on-fork:   {}",
+        if forks.detach_on_fork { "on" } else { "off" }
+    );
+}
@@ -0,0 +1,36 @@
+use crate::commands::DebugCommand;
+use crate::core::signals::parse_signal;
+use crate::core::Debugger;
+use anyhow::Result;
+use nix::sys::signal::Signal;
+
+#[derive(Clone)]
+pub struct HandleCommand;
+
+impl DebugCommand for HandleCommand {
+    fn name(&self) -> &'static str {
+        "handle"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["info-signals"]
+    }
+
+    // `handle <SIG> [stop|nostop] [print|noprint] [pass|nopass]`, no
+    // keywords shows the policy, no signal the whole table
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let Some((name, keywords)) = args.split_first() else {
+            let all: Vec<Signal> = Signal::iterator()
+                .filter(|s| !matches!(s, Signal::SIGTRAP | Signal::SIGKILL))
+                .collect();
+            debugger.signals.print(&all);
+            return Ok(());
+        };
+        let signal = parse_signal(name)?;
+        if !keywords.is_empty() {
+            debugger.signals.set(signal, keywords)?;
+        }
+        debugger.signals.print(&[signal]);
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::commands::{CommandRouter, DebugCommand};
+use crate::core::process_control::ProcessControl;
+use crate::core::Debugger;
+use anyhow::{anyhow, bail, Context, Result};
+use std::cell::Cell;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+// files sourcing each other, or themselves, end here
+const MAX_DEPTH: usize = 16;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Clone)]
+pub struct SourceCommand;
+
+impl DebugCommand for SourceCommand {
+    fn name(&self) -> &'static str {
+        "source"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let path = args.first().context("Usage: source <file>")?;
+        source_file(path, debugger)
+    }
+}
+
+// runs the commands of a file as if typed at the prompt, each one waits for
+// the debugee to stop; `#` starts a comment and the first error ends the file
+pub fn source_file(path: &str, debugger: &mut Debugger) -> Result<()> {
+    let depth = DEPTH.get();
+    if depth >= MAX_DEPTH {
+        bail!("source nested more than {} levels deep", MAX_DEPTH);
+    }
+    DEPTH.set(depth + 1);
+    let result = run_file(path, debugger);
+    DEPTH.set(depth);
+    result
+}
+
+fn run_file(path: &str, debugger: &mut Debugger) -> Result<()> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    let router = CommandRouter::new();
+    let mut lines = text.lines().map(str::trim).enumerate();
+    while let Some((n, line)) = lines.next() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut line = line.to_string();
+        // a `commands` block is passed on as `commands <id> a; b; ...`
+        if is_commands_block(&line) {
+            let block: Vec<&str> = lines
+                .by_ref()
+                .map(|(_, l)| l)
+                .take_while(|l| *l != "end")
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .collect();
+            line = format!("{} {}", line, block.join("; "));
+        }
+        router
+            .execute(&line, debugger)
+            .map_err(|e| anyhow!("{}:{}: {}", path, n + 1, e))?;
+        debugger.wait_for_stop();
+    }
+    Ok(())
+}
+
+// `commands` or `commands <id>` with the list on the following lines
+fn is_commands_block(line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    words.next() == Some("commands")
+        && words.next().is_none_or(|id| id.parse::<usize>().is_ok())
+        && words.next().is_none()
+}
+
+// ~/.rustydbgrc, then ./.rustydbgrc unless that is the same file
+pub fn init_files() -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let home = env::var_os("HOME").map(|home| PathBuf::from(home).join(".rustydbgrc"));
+    for path in home.into_iter().chain([PathBuf::from(".rustydbgrc")]) {
+        let Ok(canonical) = path.canonicalize() else {
+            continue;
+        };
+        if !files.contains(&canonical) {
+            files.push(canonical);
+        }
+    }
+    files
+}
@@ -0,0 +1,60 @@
+use crate::commands::DebugCommand;
+use crate::core::syscalls::{parse_syscall, syscall_name};
+use crate::core::Debugger;
+use anyhow::{bail, Result};
+
+#[derive(Clone)]
+pub struct CatchCommand;
+
+#[derive(Clone)]
+pub struct StraceCommand;
+
+impl DebugCommand for CatchCommand {
+    fn name(&self) -> &'static str {
+        "catch"
+    }
+
+    // `catch syscall [name|number ...]`, stops on entry and on return
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let Some((&"syscall", names)) = args.split_first() else {
+            bail!("Usage: catch syscall [name|number ...]");
+        };
+        let numbers = names
+            .iter()
+            .map(|name| parse_syscall(name))
+            .collect::<Result<Vec<u64>>>()?;
+        let id = debugger.breakpoint.allocate_id();
+        if numbers.is_empty() {
+            println!("Catchpoint {} (any syscall)", id);
+        } else {
+            let names: Vec<String> = numbers
+                .iter()
+                .map(|nr| format!("'{}' [{}]", syscall_name(*nr).unwrap_or("?"), nr))
+                .collect();
+            println!("Catchpoint {} (syscall {})", id, names.join(" "));
+        }
+        debugger.syscalls.add_catch(id, numbers);
+        Ok(())
+    }
+}
+
+impl DebugCommand for StraceCommand {
+    fn name(&self) -> &'static str {
+        "strace"
+    }
+
+    // takes effect on the next resume
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        match args.first() {
+            None => {}
+            Some(&"on") => debugger.syscalls.trace = true,
+            Some(&"off") => debugger.syscalls.trace = false,
+            Some(_) => bail!("Usage: strace [on|off]"),
+        }
+        println!(
+            "syscall tracing is {}",
+            if debugger.syscalls.trace { "on" } else { "off" }
+        );
+        Ok(())
+    }
+}
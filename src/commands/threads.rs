@@ -0,0 +1,50 @@
+use crate::commands::DebugCommand;
+use crate::core::context::Context;
+use crate::core::threads::ThreadControl;
+use crate::core::Debugger;
+use anyhow::Result;
+use nix::unistd::Pid;
+
+#[derive(Clone)]
+pub struct ThreadsCommand;
+
+#[derive(Clone)]
+pub struct ThreadCommand;
+
+impl DebugCommand for ThreadsCommand {
+    fn name(&self) -> &'static str {
+        "threads"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["info-threads"]
+    }
+
+    fn execute(&self, _args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        debugger.print_threads();
+        Ok(())
+    }
+}
+
+impl DebugCommand for ThreadCommand {
+    fn name(&self) -> &'static str {
+        "thread"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["t"]
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        let Some(tid) = args.first() else {
+            println!("Current thread is {}", debugger.process.tid);
+            return Ok(());
+        };
+        let tid = tid
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Usage: thread <tid>"))?;
+        debugger.select_thread(Pid::from_raw(tid))?;
+        debugger.print_context();
+        Ok(())
+    }
+}
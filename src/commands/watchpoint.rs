@@ -0,0 +1,128 @@
+use crate::commands::DebugCommand;
+use crate::core::breakpoint_helpers::*;
+use crate::core::expression;
+use crate::core::watchpoint::WatchKind;
+use crate::core::Debugger;
+use anyhow::Result;
+
+#[derive(Clone)]
+pub struct HardwareBreakpointCommand;
+
+#[derive(Clone)]
+pub struct WatchCommand;
+
+#[derive(Clone)]
+pub struct ReadWatchCommand;
+
+#[derive(Clone)]
+pub struct AccessWatchCommand;
+
+impl DebugCommand for HardwareBreakpointCommand {
+    fn name(&self) -> &'static str {
+        "hbreak"
+    }
+
+    fn aliases(&self) -> &[&'static str] {
+        &["hb"]
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        if args.is_empty() {
+            anyhow::bail!("Usage: hbreak <address>");
+        }
+        debugger.ensure_live()?;
+        for addr in debugger.resolve_breakpoint_input(&args.join(" "))? {
+            let id = debugger.breakpoint.allocate_id();
+            debugger.watchpoints.set_watchpoint(
+                id,
+                addr,
+                1,
+                WatchKind::Execute,
+                debugger.target.as_mut(),
+                &debugger.process.thread_ids(),
+            )?;
+            println!(
+                "hardware breakpoint {} set at 0x{:x} ({})",
+                id,
+                addr,
+                debugger.describe_location(addr)
+            );
+        }
+        Ok(())
+    }
+}
+
+impl DebugCommand for WatchCommand {
+    fn name(&self) -> &'static str {
+        "watch"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        set_watchpoint(args, debugger, WatchKind::Write)
+    }
+}
+
+impl DebugCommand for ReadWatchCommand {
+    fn name(&self) -> &'static str {
+        "rwatch"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        set_watchpoint(args, debugger, WatchKind::Read)
+    }
+}
+
+impl DebugCommand for AccessWatchCommand {
+    fn name(&self) -> &'static str {
+        "awatch"
+    }
+
+    fn execute(&self, args: &[&str], debugger: &mut Debugger) -> Result<()> {
+        set_watchpoint(args, debugger, WatchKind::ReadWrite)
+    }
+}
+
+// `watch <address-expression> [1|2|4|8]`
+fn set_watchpoint(args: &[&str], debugger: &mut Debugger, kind: WatchKind) -> Result<()> {
+    if args.is_empty() {
+        anyhow::bail!("Usage: watch <address> [1|2|4|8]");
+    }
+    debugger.ensure_live()?;
+    let (expr_args, len) = match args.split_last() {
+        Some((last, rest)) if !rest.is_empty() && matches!(*last, "1" | "2" | "4" | "8") => {
+            (rest, last.parse::<usize>().ok())
+        }
+        _ => (args, None),
+    };
+    let expr = expr_args.join(" ");
+    let addr = expression::evaluate(&expr, debugger)?;
+    let len = len.unwrap_or_else(|| default_len(debugger, &expr, addr));
+
+    let id = debugger.breakpoint.allocate_id();
+    debugger.watchpoints.set_watchpoint(
+        id,
+        addr,
+        len,
+        kind,
+        debugger.target.as_mut(),
+        &debugger.process.thread_ids(),
+    )?;
+    println!(
+        "hardware watchpoint {} set at 0x{:x} ({} bytes, {:?})",
+        id, addr, len, kind
+    );
+    Ok(())
+}
+
+// size of the global if known, else the widest length the address is aligned to
+fn default_len(debugger: &Debugger, expr: &str, addr: u64) -> usize {
+    if let Some(global) = debugger.globals.iter().find(|g| g.name == expr.trim()) {
+        if matches!(global.size, 1 | 2 | 4 | 8) {
+            return global.size as usize;
+        }
+    }
+    [8, 4, 2, 1]
+        .into_iter()
+        .find(|len| addr.is_multiple_of(*len as u64))
+        .unwrap_or(1)
+}
@@ -1,62 +1,74 @@
 use crate::core::symbols::get_unwind_info;
 use crate::core::Debugger;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use log::debug;
-use nix::sys::ptrace;
-use nix::sys::ptrace::*;
+
+const MAX_FRAMES: usize = 64;
+
 pub trait Backtrace {
     fn backtrace(&self) -> Result<()>;
+    fn frames(&self) -> Result<Vec<u64>>;
 }
 
 impl Backtrace for Debugger {
     fn backtrace(&self) -> Result<()> {
-        let regs = getregs(self.process.pid)?;
+        for (i, pc) in self.frames()?.iter().enumerate() {
+            // return addresses point behind the call, describe the call itself
+            let call_site = if i == 0 { *pc } else { pc - 1 };
+            println!(
+                "#{:<2} 0x{:016x} in {}",
+                i,
+                pc,
+                self.describe_location(call_site)
+            );
+        }
+        Ok(())
+    }
+
+    // pc of every frame, innermost first, until the CFI runs out
+    fn frames(&self) -> Result<Vec<u64>> {
+        let regs = self.read_regs(self.process.tid)?;
         let mut rip = regs.rip;
         let mut rsp = regs.rsp;
         let mut rbp = regs.rbp;
-        let mut first = true;
+        let mut frames = vec![rip];
 
-        loop {
-            let mut func_offset = rip - self.process.base_addr;
-
-            let info = get_unwind_info(&self.path, func_offset)?;
+        while frames.len() < MAX_FRAMES {
+            let Some(func_offset) = rip.checked_sub(self.process.base_addr) else {
+                break;
+            };
+            let Ok(info) = get_unwind_info(&self.path, func_offset) else {
+                break;
+            };
             debug!("{:?}", info);
 
             let cfa_base = match info.cfa_register {
                 6 => rbp,
                 7 => rsp,
                 16 => rip,
-                other => panic!("unsupported cfa reg{}", other),
+                other => bail!("unsupported cfa reg {}", other),
             };
 
             let cfa = (cfa_base as i64 + info.cfa_offset) as u64;
             debug!("CFA: 0x{:016x}", cfa);
 
             let ret_addr_addr = (cfa as i64 + info.ra_offset) as u64;
-
-            let ret_addr =
-                ptrace::read(self.process.pid, ret_addr_addr as ptrace::AddressType)? as u64;
-
-            debug!(
-                "Return address (caller RIP): 0x{:016x}",
-                ret_addr - self.process.base_addr
-            );
-
-            func_offset = ret_addr - self.process.base_addr;
-            let name: String = self
-                .get_function_name(func_offset)
-                .unwrap_or_else(|| "_start".to_string());
-            if first != true {
-                print!("-> ");
+            let Ok(ret_addr) = self.read_word(ret_addr_addr) else {
+                break;
+            };
+            debug!("Return address (caller RIP): 0x{:016x}", ret_addr);
+            if ret_addr == 0 {
+                break;
             }
-            print!("{}", name);
+
+            frames.push(ret_addr);
             rip = ret_addr;
             rsp = cfa;
             if info.cfa_register == 6 {
                 let saved_rbp_addr = (cfa as i64 - 16) as u64;
-                rbp = ptrace::read(self.process.pid, saved_rbp_addr as ptrace::AddressType)? as u64;
+                rbp = self.read_word(saved_rbp_addr)?;
             }
-            first = false;
         }
+        Ok(frames)
     }
 }
@@ -1,90 +1,307 @@
-use anyhow::Result;
+use crate::core::target::Target;
+use anyhow::{bail, Result};
 use log::debug;
-use nix::libc;
-use nix::{sys::ptrace, unistd::Pid};
+use nix::unistd::Pid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakpointKind {
+    Persistent,
+    Temporary,
+    // set by the debugger itself while stepping, not listed to the user
+    Internal,
+}
+
+#[derive(Debug)]
+pub struct BreakpointEntry {
+    pub id: usize,
+    pub addr: u64,
+    pub original_byte: u8,
+    pub kind: BreakpointKind,
+    pub enabled: bool,
+    pub hit_count: u64,
+    pub condition: Option<String>,
+    // REPL commands run on each hit, `silent` first suppresses the stop report
+    pub commands: Vec<String>,
+}
 
 #[derive(Debug)]
 pub struct Breakpoint {
-    pub breakpoint: Vec<(u64, u8)>,
+    pub breakpoint: Vec<BreakpointEntry>,
+    // breakpoint the debugee is stopped on, its original byte is restored
+    // until we stepped past it
+    pub step_over: Option<u64>,
+    // thread that reported the hit and has to be stepped past it
+    pub step_over_thread: Option<Pid>,
+    next_id: usize,
 }
 
 impl Breakpoint {
     pub fn new() -> Self {
         Breakpoint {
             breakpoint: Vec::new(),
+            step_over: None,
+            step_over_thread: None,
+            next_id: 1,
         }
     }
 
-    pub fn set_breakpoint(&mut self, addr: u64, pid: Pid) -> Result<()> {
-        let aligned_addr = addr & !0x7;
-        let byte_offset = addr % 8;
+    pub fn set_breakpoint(&mut self, addr: u64, target: &mut dyn Target) -> Result<usize> {
+        self.insert_breakpoint(addr, target, BreakpointKind::Persistent)
+    }
 
-        let original_word = ptrace::read(pid, aligned_addr as *mut libc::c_void)? as u64;
+    pub fn set_temporary_breakpoint(
+        &mut self,
+        addr: u64,
+        target: &mut dyn Target,
+    ) -> Result<usize> {
+        self.insert_breakpoint(addr, target, BreakpointKind::Temporary)
+    }
 
-        let original_byte = ((original_word >> (byte_offset * 8)) & 0xFF) as u8;
+    pub fn set_internal_breakpoint(&mut self, addr: u64, target: &mut dyn Target) -> Result<usize> {
+        self.insert_breakpoint(addr, target, BreakpointKind::Internal)
+    }
 
+    fn insert_breakpoint(
+        &mut self,
+        addr: u64,
+        target: &mut dyn Target,
+        kind: BreakpointKind,
+    ) -> Result<usize> {
+        if self.is_breakpoint(addr) {
+            bail!("Breakpoint already exists at {:#x}", addr);
+        }
         debug!("[SET BP] Target addr:     {:#x}", addr);
-        debug!("[SET BP] Aligned addr:    {:#x}", aligned_addr);
-        debug!("[SET BP] Word read:       {:#018x}", original_word);
-        debug!("[SET BP] Byte offset:     {}", byte_offset);
-        debug!("[SET BP] Original byte:   {:#x}", original_byte);
+        let original_byte = write_byte(target, addr, 0xCC)?;
+
+        let id = if kind == BreakpointKind::Internal {
+            0
+        } else {
+            self.allocate_id()
+        };
+        self.breakpoint.push(BreakpointEntry {
+            id,
+            addr,
+            original_byte,
+            kind,
+            enabled: true,
+            hit_count: 0,
+            condition: None,
+            commands: Vec::new(),
+        });
+        debug!("[SET BP] Breakpoint {} set.\n", id);
+        Ok(id)
+    }
 
-        let patched_word =
-            (original_word & !(0xFF << (byte_offset * 8))) | ((0xCCu64) << (byte_offset * 8));
+    // breakpoint numbers are shared with the hardware watchpoints
+    pub fn allocate_id(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
 
-        debug!("[SET BP] Patched word:    {:#018x}", patched_word);
+    pub fn remove_breakpoint(&mut self, addr: u64, target: &mut dyn Target) -> Result<()> {
+        if let Some(pos) = self.breakpoint.iter().position(|bp| bp.addr == addr) {
+            debug!("[REMOVE BP] Target addr:     {:#x}", addr);
+            if self.is_inserted(&self.breakpoint[pos]) {
+                write_byte(target, addr, self.breakpoint[pos].original_byte)?;
+            }
+            if self.step_over == Some(addr) {
+                self.step_over = None;
+            }
+            self.breakpoint.remove(pos);
+        } else {
+            println!("No breakpoint found at {:#x}", addr);
+        }
+        Ok(())
+    }
 
-        ptrace::write(pid, aligned_addr as *mut libc::c_void, patched_word as i64)?;
+    pub fn remove_by_id(&mut self, id: usize, target: &mut dyn Target) -> Result<()> {
+        let addr = self.get_by_id(id)?.addr;
+        self.remove_breakpoint(addr, target)
+    }
 
-        self.breakpoint.push((addr, original_byte));
-        debug!("[SET BP] Breakpoint set.\n");
+    pub fn remove_all(&mut self, target: &mut dyn Target) -> Result<()> {
+        let addrs: Vec<u64> = self.user_breakpoints().map(|bp| bp.addr).collect();
+        for addr in addrs {
+            self.remove_breakpoint(addr, target)?;
+        }
         Ok(())
     }
 
-    pub fn remove_breakpoint(&mut self, addr: u64, pid: Pid) -> Result<()> {
-        if let Some(pos) = self.breakpoint.iter().position(|(a, _)| *a == addr) {
-            let (_, saved_byte) = self.breakpoint[pos];
-            let aligned_addr = addr & !0x7;
-            let byte_offset = addr % 8;
+    pub fn set_condition(&mut self, id: usize, condition: Option<String>) -> Result<()> {
+        self.get_by_id_mut(id)?.condition = condition;
+        Ok(())
+    }
 
-            let current_word = ptrace::read(pid, aligned_addr as *mut libc::c_void)? as u64;
+    pub fn set_commands(&mut self, id: usize, commands: Vec<String>) -> Result<()> {
+        self.get_by_id_mut(id)?.commands = commands;
+        Ok(())
+    }
 
-            debug!("[REMOVE BP] Target addr:     {:#x}", addr);
-            debug!("[REMOVE BP] Aligned addr:    {:#x}", aligned_addr);
-            debug!("[REMOVE BP] Word read:       {:#018x}", current_word);
-            debug!("[REMOVE BP] Byte offset:     {}", byte_offset);
-            debug!("[REMOVE BP] Saved byte:      {:#x}", saved_byte);
+    pub fn enable(&mut self, id: usize, target: &mut dyn Target) -> Result<()> {
+        let bp = self.get_by_id(id)?;
+        if !bp.enabled && self.step_over != Some(bp.addr) {
+            write_byte(target, bp.addr, 0xCC)?;
+        }
+        self.get_by_id_mut(id)?.enabled = true;
+        Ok(())
+    }
 
-            let restored_word = (current_word & !(0xFF << (byte_offset * 8)))
-                | ((saved_byte as u64) << (byte_offset * 8));
+    pub fn disable(&mut self, id: usize, target: &mut dyn Target) -> Result<()> {
+        let bp = self.get_by_id(id)?;
+        if self.is_inserted(bp) {
+            write_byte(target, bp.addr, bp.original_byte)?;
+        }
+        self.get_by_id_mut(id)?.enabled = false;
+        Ok(())
+    }
 
-            debug!("[REMOVE BP] Restored word:   {:#018x}", restored_word);
+    // re-inserts all enabled breakpoints into a freshly started process
+    pub fn rearm_all(&mut self, target: &mut dyn Target) {
+        self.step_over = None;
+        self.step_over_thread = None;
+        self.breakpoint
+            .retain(|bp| bp.kind != BreakpointKind::Internal);
+        for bp in self.breakpoint.iter_mut().filter(|bp| bp.enabled) {
+            match write_byte(target, bp.addr, 0xCC) {
+                Ok(original_byte) => bp.original_byte = original_byte,
+                Err(e) => {
+                    println!(
+                        "Failed to re-insert breakpoint {} at {:#x}: {}",
+                        bp.id, bp.addr, e
+                    );
+                    bp.enabled = false;
+                }
+            }
+        }
+    }
 
-            ptrace::write(pid, aligned_addr as *mut libc::c_void, restored_word as i64)?;
+    // moves the breakpoints along with the executable, e.g. from a process
+    // attached with ASLR to one launched without it; call before rearm_all
+    pub fn rebase(&mut self, old_base: u64, new_base: u64) {
+        for bp in self.breakpoint.iter_mut() {
+            bp.addr = bp.addr.wrapping_sub(old_base).wrapping_add(new_base);
+        }
+    }
 
-            self.breakpoint.remove(pos);
-        } else {
-            println!("No breakpoint found at {:#x}", addr);
+    // takes every inserted int3 out of the process, e.g. of a forked child
+    pub fn disarm_all(&self, target: &mut dyn Target) -> Result<()> {
+        for bp in self.breakpoint.iter().filter(|bp| self.is_inserted(bp)) {
+            write_byte(target, bp.addr, bp.original_byte)?;
         }
         Ok(())
     }
 
-    pub fn is_breakpoint(&self, addr: u64) -> bool {
-        self.breakpoint.iter().any(|(a, _)| *a == addr)
+    pub fn arm_all(&self, target: &mut dyn Target) -> Result<()> {
+        for bp in self.breakpoint.iter().filter(|bp| self.is_inserted(bp)) {
+            write_byte(target, bp.addr, 0xCC)?;
+        }
+        Ok(())
     }
 
-    pub fn show_breakpoints(&self) {
-        for bp in self.breakpoint.iter() {
-            println!("addr: {:#x}  original byte: {:x}", bp.0, bp.1);
+    // drops all breakpoints without touching memory, after exec replaced it
+    pub fn forget_all(&mut self) {
+        self.breakpoint.clear();
+        self.step_over = None;
+        self.step_over_thread = None;
+    }
+
+    // restores the original instruction but keeps the breakpoint around
+    pub fn disarm(&mut self, addr: u64, target: &mut dyn Target) -> Result<()> {
+        if let Some(bp) = self.get(addr) {
+            write_byte(target, addr, bp.original_byte)?;
         }
+        Ok(())
+    }
+
+    // disarms the breakpoint `tid` stopped on until it was stepped past
+    pub fn begin_step_over(&mut self, addr: u64, target: &mut dyn Target, tid: Pid) -> Result<()> {
+        self.disarm(addr, target)?;
+        self.step_over = Some(addr);
+        self.step_over_thread = Some(tid);
+        Ok(())
+    }
+
+    pub fn arm(&mut self, addr: u64, target: &mut dyn Target) -> Result<()> {
+        if self.get(addr).is_some_and(|bp| bp.enabled) {
+            write_byte(target, addr, 0xCC)?;
+        }
+        Ok(())
+    }
+
+    // whether 0xCC is currently written to memory for this breakpoint
+    fn is_inserted(&self, bp: &BreakpointEntry) -> bool {
+        bp.enabled && self.step_over != Some(bp.addr)
+    }
+
+    pub fn get(&self, addr: u64) -> Option<&BreakpointEntry> {
+        self.breakpoint.iter().find(|bp| bp.addr == addr)
+    }
+
+    pub fn get_mut(&mut self, addr: u64) -> Option<&mut BreakpointEntry> {
+        self.breakpoint.iter_mut().find(|bp| bp.addr == addr)
+    }
+
+    pub fn get_by_id(&self, id: usize) -> Result<&BreakpointEntry> {
+        self.breakpoint
+            .iter()
+            .find(|bp| bp.id == id)
+            .ok_or_else(|| anyhow::anyhow!("No breakpoint number {}", id))
+    }
+
+    fn get_by_id_mut(&mut self, id: usize) -> Result<&mut BreakpointEntry> {
+        self.breakpoint
+            .iter_mut()
+            .find(|bp| bp.id == id)
+            .ok_or_else(|| anyhow::anyhow!("No breakpoint number {}", id))
+    }
+
+    pub fn user_breakpoints(&self) -> impl Iterator<Item = &BreakpointEntry> {
+        self.breakpoint
+            .iter()
+            .filter(|bp| bp.kind != BreakpointKind::Internal)
+    }
+
+    pub fn is_breakpoint(&self, addr: u64) -> bool {
+        self.get(addr).is_some()
     }
 }
 
+impl Default for Breakpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// patches a single byte, returns the previous one. a target with its own
+// breakpoints (Z0 packets) gets those instead of the int3 write
+fn write_byte(target: &mut dyn Target, addr: u64, byte: u8) -> Result<u8> {
+    let mut original_byte = [0u8];
+    target.read_memory(addr, &mut original_byte)?;
+    debug!(
+        "[WRITE BYTE] {:#x}: {:#x} -> {:#x}",
+        addr, original_byte[0], byte
+    );
+    let handled = if byte == 0xCC {
+        target.insert_breakpoint(addr)?
+    } else {
+        target.remove_breakpoint(addr)?
+    };
+    if !handled {
+        target.write_memory(addr, &[byte])?;
+    }
+    Ok(original_byte[0])
+}
+
 #[cfg(test)]
 mod tests {
+    use crate::core::process_control::ProcessControl;
+    use crate::core::stepping::Stepping;
+    use crate::core::target::MockTarget;
     use crate::core::Debugger;
     use nix::libc;
     use nix::sys::ptrace;
+    use nix::unistd::Pid;
 
     #[test]
     fn test_breakpoint_on_ls() {
@@ -95,15 +312,15 @@ mod tests {
             "ls doesn't exist {}",
             ls_path
         );
-        let mut debugger = Debugger::new(ls_path.to_string(), "".to_string());
-        nix::sys::wait::waitpid(debugger.process.pid, None).unwrap();
+        let mut debugger = Debugger::new(ls_path.to_string(), "".to_string(), Vec::new()).unwrap();
         let addr: u64 = debugger.process.get_random_rw_memory().unwrap();
         println!("Found random address: {:x}", addr);
         let original_byte = ptrace::read(debugger.process.pid, addr as *mut libc::c_void).unwrap();
         println!("Original Byte: {:x}", original_byte);
         debugger
             .breakpoint
-            .set_breakpoint(addr, debugger.process.pid);
+            .set_breakpoint(addr, debugger.target.as_mut())
+            .unwrap();
         let patched_byte = ptrace::read(debugger.process.pid, addr as *mut libc::c_void).unwrap();
         println!("Patched Byte: {:x}", patched_byte);
         if original_byte == patched_byte {
@@ -113,4 +330,36 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_breakpoint_hits_on_mock_target() {
+        let tid = Pid::from_raw(1);
+        let target = MockTarget::with_code(tid, 0x1000, &[0x90; 16]);
+        let mut debugger = Debugger::with_mock(target, tid);
+        let id = debugger
+            .breakpoint
+            .set_breakpoint(0x1004, debugger.target.as_mut())
+            .unwrap();
+        debugger
+            .breakpoint
+            .set_breakpoint(0x100a, debugger.target.as_mut())
+            .unwrap();
+
+        debugger.cont().unwrap();
+        debugger.wait_for_stop();
+        assert_eq!(debugger.read_regs(tid).unwrap().rip, 0x1004);
+        assert_eq!(debugger.breakpoint.get(0x1004).unwrap().hit_count, 1);
+
+        // stepped off the first one, which is armed again
+        debugger.cont().unwrap();
+        debugger.wait_for_stop();
+        assert_eq!(debugger.read_regs(tid).unwrap().rip, 0x100a);
+        assert_eq!(debugger.read_word(0x1004).unwrap() & 0xff, 0xcc);
+
+        debugger
+            .breakpoint
+            .disable(id, debugger.target.as_mut())
+            .unwrap();
+        assert_eq!(debugger.read_word(0x1004).unwrap() & 0xff, 0x90);
+    }
 }
@@ -1,14 +1,26 @@
+use crate::core::breakpoint::BreakpointKind;
 use crate::core::*;
 use anyhow::{Result, bail};
 use log::debug;
 
 pub trait BreakpointHelper {
-    fn set_breakpoint_by_input(&mut self, input: &str) -> Result<u64>;
+    fn set_breakpoint_by_input(&mut self, input: &str) -> Result<Vec<(usize, u64)>>;
+    fn set_temporary_breakpoint_by_input(&mut self, input: &str) -> Result<Vec<(usize, u64)>>;
+    fn resolve_breakpoint_input(&self, input: &str) -> Result<Vec<u64>>;
+    fn resolve_source_line(&self, file: &str, line: u64) -> Result<Vec<u64>>;
     fn rm_breakpoint_by_input(&mut self, input: &str) -> Result<()>;
 }
 
 impl BreakpointHelper for Debugger {
-    fn set_breakpoint_by_input(&mut self, input: &str) -> Result<u64> {
+    fn set_breakpoint_by_input(&mut self, input: &str) -> Result<Vec<(usize, u64)>> {
+        self.set_breakpoints(input, BreakpointKind::Persistent)
+    }
+
+    fn set_temporary_breakpoint_by_input(&mut self, input: &str) -> Result<Vec<(usize, u64)>> {
+        self.set_breakpoints(input, BreakpointKind::Temporary)
+    }
+
+    fn resolve_breakpoint_input(&self, input: &str) -> Result<Vec<u64>> {
         let addr = if let Ok(addr) = self.parse_address(input) {
             addr
         } else if let Some(function) = self.functions.iter().find(|f| f.name == input) {
@@ -17,11 +29,44 @@ impl BreakpointHelper for Debugger {
                 input, function.offset
             );
             function.offset + self.process.base_addr
+        } else if let Some((file, line)) = input
+            .rsplit_once(':')
+            .and_then(|(file, line)| Some((file, line.parse::<u64>().ok()?)))
+        {
+            return self.resolve_source_line(file, line);
         } else {
             bail!("Invalid breakpoint input: {}", input);
         };
-        self.breakpoint.set_breakpoint(addr, self.process.pid)?;
-        Ok(addr)
+        Ok(vec![addr])
+    }
+
+    // one address per function or inlined copy containing the line, so a
+    // line that is split into several rows (loops) is only hit once per
+    // pass, while every inlined copy gets its own breakpoint
+    fn resolve_source_line(&self, file: &str, line: u64) -> Result<Vec<u64>> {
+        let offsets = self.dwarf.find_addresses(file, line);
+        if offsets.is_empty() {
+            bail!("No line {} in file \"{}\"", line, file);
+        }
+
+        let mut addrs: Vec<u64> = Vec::new();
+        let mut seen = Vec::new();
+        for offset in offsets {
+            let function = self
+                .functions
+                .iter()
+                .position(|f| f.offset <= offset && f.offset + f.size > offset);
+            let block = (function, self.dwarf.inlined_instance(offset));
+            if block != (None, None) {
+                if seen.contains(&block) {
+                    continue;
+                }
+                seen.push(block);
+            }
+            addrs.push(offset + self.process.base_addr);
+        }
+        debug!("{}:{} resolved to {:x?}", file, line, addrs);
+        Ok(addrs)
     }
 
     fn rm_breakpoint_by_input(&mut self, input: &str) -> Result<()> {
@@ -31,6 +76,90 @@ impl BreakpointHelper for Debugger {
             bail!("Invalid rm breakpoint input: {}", input);
         };
 
-        self.breakpoint.remove_breakpoint(addr, self.process.pid)
+        self.breakpoint
+            .remove_breakpoint(addr, self.target.as_mut())
+    }
+}
+
+impl Debugger {
+    // all locations of `input` or none of them
+    fn set_breakpoints(&mut self, input: &str, kind: BreakpointKind) -> Result<Vec<(usize, u64)>> {
+        self.ensure_live()?;
+        let mut set = Vec::new();
+        for addr in self.resolve_breakpoint_input(input)? {
+            let result = if kind == BreakpointKind::Temporary {
+                self.breakpoint
+                    .set_temporary_breakpoint(addr, self.target.as_mut())
+            } else {
+                self.breakpoint.set_breakpoint(addr, self.target.as_mut())
+            };
+            match result {
+                Ok(id) => set.push((id, addr)),
+                Err(e) => {
+                    for (id, _) in set {
+                        let _ = self.breakpoint.remove_by_id(id, self.target.as_mut());
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(set)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::{env, fs, path::Path, process::Command};
+
+    const MAIN_C: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-programm-c/main.c");
+
+    // test-programm-c/main.c at -O2, `third` is inlined twice into `second`
+    // and again into `first`
+    pub(crate) fn build_main_c(test: &str) -> String {
+        let dir = env::temp_dir().join(format!("rusty-dbg-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("printf.h"), "").unwrap();
+        let binary = dir.join("main");
+        let status = Command::new("cc")
+            .args(["-O2", "-g", "-I"])
+            .arg(&dir)
+            .arg("-o")
+            .arg(&binary)
+            .arg(MAIN_C)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        binary.display().to_string()
+    }
+
+    #[test]
+    fn test_source_line_in_inlined_copies() {
+        let binary = build_main_c("inlined");
+        let mut debugger = Debugger::new(binary.clone(), "".to_string(), Vec::new()).unwrap();
+        debugger.process.get_base_addr_from_map();
+        let addrs = debugger.resolve_source_line("main.c", 21).unwrap();
+        let in_function = |name: &str| {
+            let f = debugger.functions.iter().find(|f| f.name == name).unwrap();
+            let start = f.offset + debugger.process.base_addr;
+            let end = start + f.size;
+            addrs.iter().filter(|a| (start..end).contains(*a)).count()
+        };
+        assert_eq!(in_function("third"), 1);
+        assert_eq!(in_function("second"), 2);
+        assert_eq!(in_function("first"), 2);
+
+        // a location that cannot be set takes the others back out
+        debugger
+            .breakpoint
+            .set_breakpoint(addrs[2], debugger.target.as_mut())
+            .unwrap();
+        assert!(debugger.set_breakpoint_by_input("main.c:21").is_err());
+        assert_eq!(debugger.breakpoint.user_breakpoints().count(), 1);
+        for addr in &addrs[..2] {
+            assert!(!debugger.breakpoint.is_breakpoint(*addr));
+        }
+        debugger.process.kill();
+        let _ = fs::remove_dir_all(Path::new(&binary).parent().unwrap());
     }
 }
@@ -0,0 +1,269 @@
+use crate::core::backtrace::Backtrace;
+use crate::core::disassembler::Disassembler;
+use crate::core::registers::general_registers;
+use crate::core::Debugger;
+use anyhow::{bail, Result};
+use std::fs;
+use std::io::IsTerminal;
+
+pub const SECTIONS: [&str; 5] = ["regs", "disasm", "source", "stack", "backtrace"];
+
+const DISASM_BEFORE: usize = 3;
+const DISASM_AFTER: usize = 6;
+const SOURCE_LINES: u64 = 4;
+const STACK_SLOTS: u64 = 8;
+
+// which parts of the stop context are printed, `set context <section> on|off`
+#[derive(Debug, Clone)]
+pub struct ContextConfig {
+    pub regs: bool,
+    pub disasm: bool,
+    pub source: bool,
+    pub stack: bool,
+    pub backtrace: bool,
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        ContextConfig {
+            regs: true,
+            disasm: true,
+            source: true,
+            stack: true,
+            backtrace: true,
+        }
+    }
+}
+
+impl ContextConfig {
+    pub fn enabled(&self, section: &str) -> bool {
+        match section {
+            "regs" => self.regs,
+            "disasm" => self.disasm,
+            "source" => self.source,
+            "stack" => self.stack,
+            "backtrace" => self.backtrace,
+            _ => false,
+        }
+    }
+
+    // "all" toggles every section at once
+    pub fn set(&mut self, section: &str, on: bool) -> Result<()> {
+        match section {
+            "regs" => self.regs = on,
+            "disasm" => self.disasm = on,
+            "source" => self.source = on,
+            "stack" => self.stack = on,
+            "backtrace" => self.backtrace = on,
+            "all" => {
+                *self = ContextConfig {
+                    regs: on,
+                    disasm: on,
+                    source: on,
+                    stack: on,
+                    backtrace: on,
+                }
+            }
+            other => bail!(
+                "Unknown context section '{}', expected one of: all, {}",
+                other,
+                SECTIONS.join(", ")
+            ),
+        }
+        Ok(())
+    }
+}
+
+type SectionPrinter = fn(&mut Debugger) -> Result<()>;
+
+pub trait Context {
+    fn print_context(&mut self);
+}
+
+impl Context for Debugger {
+    // called on every stop, sections that fail are reported and skipped
+    fn print_context(&mut self) {
+        let config = self.context.clone();
+        let sections: [(bool, &str, SectionPrinter); 5] = [
+            (config.regs, "registers", Debugger::context_regs),
+            (config.disasm, "disassembly", Debugger::context_disasm),
+            (config.source, "source", Debugger::context_source),
+            (config.stack, "stack", Debugger::context_stack),
+            (config.backtrace, "backtrace", Debugger::context_backtrace),
+        ];
+        for (enabled, title, print) in sections {
+            if !enabled {
+                continue;
+            }
+            println!(
+                "{}",
+                paint(&format!("{:─^70}", format!(" {} ", title)), "1;34")
+            );
+            if let Err(e) = print(self) {
+                println!("<{} unavailable: {}>", title, e);
+            }
+        }
+        if sections.iter().any(|(enabled, _, _)| *enabled) {
+            println!("{}", paint(&"─".repeat(70), "1;34"));
+        }
+    }
+}
+
+impl Debugger {
+    fn context_regs(&mut self) -> Result<()> {
+        let regs = self.read_regs(self.process.tid)?;
+        let current = general_registers(&regs);
+        let cells: Vec<String> = current
+            .iter()
+            .map(|(name, value)| {
+                let changed = self
+                    .last_regs
+                    .iter()
+                    .any(|(n, old)| n == name && old != value);
+                let cell = format!("{:<6} 0x{:016x}", name, value);
+                if changed {
+                    paint(&cell, "1;31")
+                } else {
+                    cell
+                }
+            })
+            .collect();
+        for row in cells.chunks(3) {
+            println!("{}", row.join("   "));
+        }
+        self.last_regs = current.to_vec();
+        Ok(())
+    }
+
+    fn context_disasm(&mut self) -> Result<()> {
+        let rip = self.read_regs(self.process.tid)?.rip;
+        let offset = rip.wrapping_sub(self.process.base_addr);
+
+        // decoding has to start on an instruction boundary, the function
+        // start is the closest one we know of before rip
+        let start = self
+            .functions
+            .iter()
+            .find(|f| f.offset <= offset && f.offset + f.size > offset && offset - f.offset < 0x400)
+            .map(|f| f.offset + self.process.base_addr)
+            .unwrap_or(rip);
+        let insns = self.disassemble_range(start, (rip - start) as usize + 64)?;
+        let pos = insns.iter().position(|(addr, _)| *addr == rip).unwrap_or(0);
+
+        for (addr, text) in insns
+            .iter()
+            .skip(pos.saturating_sub(DISASM_BEFORE))
+            .take(DISASM_BEFORE.min(pos) + DISASM_AFTER)
+        {
+            let marker = if *addr == rip { "=>" } else { "  " };
+            let bp = if self.breakpoint.is_breakpoint(*addr) {
+                "*"
+            } else {
+                " "
+            };
+            let line = format!(
+                "{}{} 0x{:x} <{}>  {}",
+                marker,
+                bp,
+                addr,
+                self.short_location(*addr),
+                text
+            );
+            if *addr == rip {
+                println!("{}", paint(&line, "1;32"));
+            } else {
+                println!("{}", line);
+            }
+        }
+        Ok(())
+    }
+
+    fn context_source(&mut self) -> Result<()> {
+        let rip = self.read_regs(self.process.tid)?.rip;
+        let Some((file, line)) = self
+            .dwarf
+            .find_line(rip.wrapping_sub(self.process.base_addr))
+        else {
+            bail!("no line info for 0x{:x}", rip);
+        };
+        let source = fs::read_to_string(&file).map_err(|e| anyhow::anyhow!("{}: {}", file, e))?;
+
+        println!("{}:{}", file, line);
+        let first = line.saturating_sub(SOURCE_LINES).max(1);
+        for (number, text) in source
+            .lines()
+            .enumerate()
+            .map(|(i, text)| (i as u64 + 1, text))
+            .skip_while(|(number, _)| *number < first)
+            .take_while(|(number, _)| *number <= line + SOURCE_LINES)
+        {
+            if number == line {
+                println!("{}", paint(&format!("=> {:>4}  {}", number, text), "1;32"));
+            } else {
+                println!("   {:>4}  {}", number, text);
+            }
+        }
+        Ok(())
+    }
+
+    fn context_stack(&mut self) -> Result<()> {
+        let rsp = self.read_regs(self.process.tid)?.rsp;
+        for slot in 0..STACK_SLOTS {
+            let addr = rsp + slot * 8;
+            let value = self.read_word(addr)?;
+            println!(
+                "0x{:016x} rsp+0x{:02x}: 0x{:016x}{}",
+                addr,
+                slot * 8,
+                value,
+                self.telescope(value)
+            );
+        }
+        Ok(())
+    }
+
+    fn context_backtrace(&mut self) -> Result<()> {
+        self.backtrace()
+    }
+
+    // symbol for code pointers, the pointee for other readable pointers
+    fn telescope(&self, value: u64) -> String {
+        let offset = value.wrapping_sub(self.process.base_addr);
+        if self.get_function_name(offset).is_some() {
+            return format!(" -> {}", self.describe_location(value));
+        }
+        if let Some(global) = self
+            .globals
+            .iter()
+            .find(|g| g.offset <= offset && offset < g.offset + g.size.max(1))
+        {
+            return format!(" -> {}", global.name);
+        }
+        match self.read_word(value) {
+            Ok(pointee) if value != 0 => format!(" -> 0x{:x}", pointee),
+            _ => String::new(),
+        }
+    }
+
+    // "func+0x10" without the source location
+    fn short_location(&self, addr: u64) -> String {
+        let offset = addr.wrapping_sub(self.process.base_addr);
+        match self
+            .functions
+            .iter()
+            .find(|f| f.offset <= offset && f.offset + f.size > offset)
+        {
+            Some(f) => format!("{}+{}", f.name, offset - f.offset),
+            None => "??".to_string(),
+        }
+    }
+}
+
+// ANSI colors only when talking to a terminal
+fn paint(text: &str, color: &str) -> String {
+    if std::io::stdout().is_terminal() {
+        format!("\x1b[{}m{}\x1b[0m", color, text)
+    } else {
+        text.to_string()
+    }
+}
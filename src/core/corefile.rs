@@ -0,0 +1,297 @@
+use crate::core::context::Context;
+use crate::core::signals::signal_text;
+use crate::core::Debugger;
+use anyhow::{bail, Result};
+use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+use goblin::elf::Elf;
+use libc::user_regs_struct;
+use memmap2::Mmap;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub const NT_PRSTATUS: u32 = 1;
+pub const NT_FPREGSET: u32 = 2;
+pub const NT_PRPSINFO: u32 = 3;
+pub const NT_AUXV: u32 = 6;
+pub const NT_FILE: u32 = 0x4649_4c45;
+
+// size of struct elf_prstatus and offset of its pr_reg on x86_64
+pub const PRSTATUS_SIZE: usize = 336;
+pub const PRSTATUS_REGS: usize = 112;
+
+pub const AT_ENTRY: u64 = 9;
+
+#[derive(Debug, Clone)]
+pub struct CoreThread {
+    pub tid: Pid,
+    pub signal: i32,
+    pub regs: user_regs_struct,
+}
+
+// a file mapping from NT_FILE, offset in bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct MappedFile {
+    pub start: u64,
+    pub end: u64,
+    pub offset: u64,
+    pub path: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadSegment {
+    pub vaddr: u64,
+    pub memsz: u64,
+    pub offset: u64,
+    // 0 when the kernel did not dump the memory, e.g. unmodified code
+    pub filesz: u64,
+    pub flags: u32,
+}
+
+// post-mortem view of a process, memory comes from the PT_LOAD segments and
+// falls back to the mapped files for what was not dumped
+#[derive(Debug)]
+pub struct CoreFile {
+    pub path: String,
+    data: Mmap,
+    pub segments: Vec<LoadSegment>,
+    pub threads: Vec<CoreThread>,
+    pub files: Vec<MappedFile>,
+    pub auxv: Vec<(u64, u64)>,
+}
+
+impl CoreFile {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = File::open(path).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+        let data = unsafe { Mmap::map(&file)? };
+        let elf = Elf::parse(&data)?;
+        if elf.header.e_type != goblin::elf::header::ET_CORE {
+            bail!("{} is not a core file", path);
+        }
+
+        let segments = elf
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .map(|ph| LoadSegment {
+                vaddr: ph.p_vaddr,
+                memsz: ph.p_memsz,
+                offset: ph.p_offset,
+                filesz: ph.p_filesz,
+                flags: ph.p_flags,
+            })
+            .collect();
+
+        let mut threads = Vec::new();
+        let mut files = Vec::new();
+        let mut auxv = Vec::new();
+        if let Some(notes) = elf.iter_note_headers(&data) {
+            for note in notes {
+                let note = note?;
+                match note.n_type {
+                    NT_PRSTATUS => threads.extend(parse_prstatus(note.desc)),
+                    NT_FILE => files = parse_nt_file(note.desc),
+                    NT_AUXV => auxv = parse_auxv(note.desc),
+                    _ => {}
+                }
+            }
+        }
+        if threads.is_empty() {
+            bail!("{} has no NT_PRSTATUS note", path);
+        }
+
+        Ok(CoreFile {
+            path: path.to_string(),
+            data,
+            segments,
+            threads,
+            files,
+            auxv,
+        })
+    }
+
+    // the executable may live somewhere else than on the crashed machine
+    pub fn substitute_executable(&mut self, binary: &str) {
+        let name = Path::new(binary).file_name();
+        for file in &mut self.files {
+            if Path::new(&file.path).file_name() == name {
+                file.path = binary.to_string();
+            }
+        }
+    }
+
+    pub fn thread(&self, tid: Pid) -> Option<&CoreThread> {
+        self.threads.iter().find(|t| t.tid == tid)
+    }
+
+    pub fn auxv_value(&self, key: u64) -> Option<u64> {
+        self.auxv.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+    }
+
+    pub fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            done += self.read_piece(addr + done as u64, &mut buf[done..])?;
+        }
+        Ok(())
+    }
+
+    // copies what is contiguous at `at`, returns the number of bytes
+    fn read_piece(&self, at: u64, buf: &mut [u8]) -> Result<usize> {
+        if let Some(seg) = self
+            .segments
+            .iter()
+            .find(|s| s.vaddr <= at && at < s.vaddr + s.filesz)
+        {
+            let len = buf.len().min((seg.vaddr + seg.filesz - at) as usize);
+            let start = (seg.offset + at - seg.vaddr) as usize;
+            buf[..len].copy_from_slice(&self.data[start..start + len]);
+            return Ok(len);
+        }
+        if let Some(file) = self.files.iter().find(|f| f.start <= at && at < f.end) {
+            let len = buf.len().min((file.end - at) as usize);
+            let mut f = File::open(&file.path).map_err(|e| {
+                anyhow::anyhow!("Cannot access memory at 0x{:x}: {}: {}", at, file.path, e)
+            })?;
+            f.seek(SeekFrom::Start(file.offset + at - file.start))?;
+            f.read_exact(&mut buf[..len])
+                .map_err(|_| anyhow::anyhow!("Cannot access memory at 0x{:x}", at))?;
+            return Ok(len);
+        }
+        bail!("Cannot access memory at 0x{:x}", at)
+    }
+}
+
+impl Debugger {
+    // what gdb prints when opening a core
+    pub fn print_core_summary(&mut self) {
+        let Some(core) = self.target.core() else {
+            return;
+        };
+        println!(
+            "Core file {} of {}, {} thread(s)",
+            core.path,
+            self.path,
+            core.threads.len()
+        );
+        let signal = core.threads[0].signal;
+        match Signal::try_from(signal) {
+            Ok(signal) => println!(
+                "Program terminated with signal {}, {}.",
+                signal.as_str(),
+                signal_text(signal)
+            ),
+            Err(_) => println!("Program terminated with signal {}.", signal),
+        }
+        if let Ok(regs) = self.read_regs(self.process.tid) {
+            println!(
+                "#0  0x{:016x} in {}",
+                regs.rip,
+                self.describe_location(regs.rip)
+            );
+        }
+        self.print_context();
+    }
+}
+
+pub fn parse_prstatus(desc: &[u8]) -> Option<CoreThread> {
+    if desc.len() < PRSTATUS_REGS + std::mem::size_of::<user_regs_struct>() {
+        return None;
+    }
+    let signal = u16::from_le_bytes(desc[12..14].try_into().ok()?) as i32;
+    let tid = i32::from_le_bytes(desc[32..36].try_into().ok()?);
+    // pr_reg has the layout of user_regs_struct
+    let regs = unsafe {
+        std::ptr::read_unaligned(desc[PRSTATUS_REGS..].as_ptr() as *const user_regs_struct)
+    };
+    Some(CoreThread {
+        tid: Pid::from_raw(tid),
+        signal,
+        regs,
+    })
+}
+
+// count, page size, count * (start, end, page offset), then the file names
+pub fn parse_nt_file(desc: &[u8]) -> Vec<MappedFile> {
+    let word = |i: usize| {
+        desc.get(i * 8..i * 8 + 8)
+            .map(|b| u64::from_le_bytes(b.try_into().unwrap()))
+    };
+    let (Some(count), Some(page_size)) = (word(0), word(1)) else {
+        return Vec::new();
+    };
+    let count = count as usize;
+    let names_start = (2 + count * 3) * 8;
+    let Some(names) = desc.get(names_start..) else {
+        return Vec::new();
+    };
+    names
+        .split(|b| *b == 0)
+        .take(count)
+        .enumerate()
+        .filter_map(|(i, name)| {
+            Some(MappedFile {
+                start: word(2 + i * 3)?,
+                end: word(3 + i * 3)?,
+                offset: word(4 + i * 3)? * page_size,
+                path: String::from_utf8_lossy(name).into_owned(),
+            })
+        })
+        .collect()
+}
+
+pub fn parse_auxv(desc: &[u8]) -> Vec<(u64, u64)> {
+    desc.chunks_exact(16)
+        .map(|pair| {
+            (
+                u64::from_le_bytes(pair[..8].try_into().unwrap()),
+                u64::from_le_bytes(pair[8..].try_into().unwrap()),
+            )
+        })
+        .take_while(|(key, _)| *key != 0)
+        .collect()
+}
+
+// permissions of a PT_LOAD segment as (read, write, execute)
+pub fn segment_permissions(flags: u32) -> (bool, bool, bool) {
+    (flags & PF_R != 0, flags & PF_W != 0, flags & PF_X != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(values: &[u64]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_parse_nt_file() {
+        let mut desc = words(&[2, 0x1000, 0x400000, 0x401000, 0, 0x7f0000, 0x7f2000, 3]);
+        desc.extend_from_slice(b"/bin/app\0/lib/libc.so.6\0");
+        let files = parse_nt_file(&desc);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].path, "/bin/app");
+        assert_eq!(files[1].start, 0x7f0000);
+        assert_eq!(files[1].offset, 0x3000);
+    }
+
+    #[test]
+    fn test_parse_prstatus() {
+        let mut desc = vec![0u8; PRSTATUS_SIZE];
+        desc[12..14].copy_from_slice(&11u16.to_le_bytes());
+        desc[32..36].copy_from_slice(&1234i32.to_le_bytes());
+        // rip is the 17th register of pr_reg
+        desc[PRSTATUS_REGS + 16 * 8..PRSTATUS_REGS + 17 * 8]
+            .copy_from_slice(&0x401000u64.to_le_bytes());
+        let thread = parse_prstatus(&desc).unwrap();
+        assert_eq!(thread.tid, Pid::from_raw(1234));
+        assert_eq!(thread.signal, 11);
+        assert_eq!(thread.regs.rip, 0x401000);
+
+        let auxv = parse_auxv(&words(&[AT_ENTRY, 0x1040, 0, 0]));
+        assert_eq!(auxv, vec![(AT_ENTRY, 0x1040)]);
+    }
+}
@@ -1,11 +1,43 @@
 use crate::core::breakpoint::*;
+use crate::core::context::ContextConfig;
+use crate::core::corefile::{CoreFile, AT_ENTRY};
+use crate::core::error::DebuggerError;
+use crate::core::expression;
+use crate::core::fork::Forks;
+use crate::core::launch::LaunchConfig;
 use crate::core::process::*;
+use crate::core::remote::RemoteTarget;
+use crate::core::script::Scripts;
+use crate::core::signals::SignalTable;
+use crate::core::stepping::StepPlan;
 use crate::core::symbols::*;
-use anyhow::Result;
+use crate::core::syscalls::SyscallTracer;
+use crate::core::target::{PtraceTarget, Target};
+use crate::core::threads::ThreadControl;
+use crate::core::watchpoint::{WatchpointHit, Watchpoints};
+use anyhow::{bail, Result};
+use libc::user_regs_struct;
 use log::{debug, info};
-use nix::sys::ptrace::getregs;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use std::fmt;
+use std::fs;
 use std::path::Path;
-use std::process::Command;
+use std::rc::Rc;
+
+/// Runs command lines of the frontend for the engine, i.e. the command
+/// lists of breakpoints and `cmd()` in scripts.
+pub trait CommandRunner {
+    fn execute(&self, line: &str, debugger: &mut Debugger) -> Result<()>;
+    /// Whether the command resumes the debugee.
+    fn resumes(&self, line: &str) -> bool;
+}
+
+impl fmt::Debug for dyn CommandRunner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CommandRunner")
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum DebuggerState {
@@ -18,45 +50,233 @@ pub enum DebuggerState {
 pub struct Debugger {
     pub process: Process,
     pub breakpoint: Breakpoint,
+    pub watchpoints: Watchpoints,
     pub functions: Vec<FunctionInfo>,
+    pub globals: Vec<FunctionInfo>,
     pub state: DebuggerState,
     pub dwarf: DwarfContext,
     pub path: String,
+    pub launch: LaunchConfig,
+    pub step_plan: Option<StepPlan>,
+    pub context: ContextConfig,
+    // registers at the previous stop, to highlight changes
+    pub last_regs: Vec<(&'static str, u64)>,
+    pub forks: Forks,
+    pub signals: SignalTable,
+    pub syscalls: SyscallTracer,
+    // memory, registers and execution control, a core file in a post-mortem
+    // session, see from_core
+    pub target: Box<dyn Target>,
+    // what ended the last resume, for the stop replies of the gdbserver
+    pub last_status: Option<WaitStatus>,
+    pub last_watch_hit: Option<WatchpointHit>,
+    pub scripts: Scripts,
+    // set by the frontend, without it breakpoint commands are not run
+    pub command_runner: Option<Rc<dyn CommandRunner>>,
 }
 
 impl Debugger {
-    pub fn new(debugee_pid_path: String, debuger_name: String) -> Self {
-        let pid = get_pid_from_input(debugee_pid_path.clone());
+    pub fn new(
+        debugee_pid_path: String,
+        debuger_name: String,
+        program_args: Vec<String>,
+    ) -> Result<Self> {
+        let (process, launch) = attach_or_launch(debugee_pid_path, program_args)?;
+        Self::with_process(process, launch, debuger_name)
+    }
+
+    // starts the program with the given settings, e.g. a working directory
+    // that differs from the debugger's
+    pub fn launch(launch: LaunchConfig, debuger_name: String) -> Result<Self> {
+        let process = launch_process(&launch)?;
+        Self::with_process(process, launch, debuger_name)
+    }
+
+    // `--core <core> <binary>`, registers and memory come from the core until
+    // the program is started with `run`
+    pub fn from_core(core_path: &str, binary: &str, debuger_name: String) -> Result<Self> {
+        let mut core = CoreFile::open(core_path)?;
+        core.substitute_executable(binary);
+        let process = Process::from_core(&core);
+        let launch = LaunchConfig::new(binary.to_string(), Vec::new());
+        let mut debugger = Self::with_process(process, launch, debuger_name)?;
+        debugger.process.base_addr = core_base_addr(&core, binary);
+        debugger.target = Box::new(core);
+        Ok(debugger)
+    }
+
+    // `--remote <host:port|socket> <binary>`, a gdbserver or qemu gdbstub runs
+    // the program and symbols come from the local copy of the binary
+    pub fn connect_remote(address: &str, binary: &str, debuger_name: String) -> Result<Self> {
+        let mut target = RemoteTarget::connect(address)?;
+        let tids = target.thread_list().unwrap_or_default();
+        let process = Process::remote(target.pid, &tids);
+        let launch = LaunchConfig::new(binary.to_string(), Vec::new());
+        let mut debugger = Self::with_process(process, launch, debuger_name)?;
+        let at_entry = target
+            .auxv()
+            .iter()
+            .find(|(key, _)| *key == AT_ENTRY)
+            .map(|(_, value)| *value);
+        debugger.process.base_addr = entry_base_addr(at_entry, binary);
+        println!("Remote debugging using {}", address);
+        for (name, l_addr) in &target.libraries {
+            println!("  {:#x} {}", l_addr, name);
+        }
+        debugger.target = Box::new(target);
+        Ok(debugger)
+    }
 
-        Debugger {
-            process: Process::attach(pid),
+    pub(crate) fn with_process(
+        process: Process,
+        launch: LaunchConfig,
+        debuger_name: String,
+    ) -> Result<Self> {
+        let path = launch.path.clone();
+        let target = Box::new(PtraceTarget::new(process.pid));
+        let dwarf = DwarfContext::new(&path).map_err(|e| DebuggerError::Symbols {
+            path: path.clone(),
+            reason: e.to_string(),
+        })?;
+
+        Ok(Debugger {
+            process,
             breakpoint: Breakpoint::new(),
-            functions: FunctionInfo::new(&debugee_pid_path, debuger_name),
+            watchpoints: Watchpoints::new(),
+            functions: FunctionInfo::new(&path, debuger_name)?,
+            globals: FunctionInfo::globals(&path),
             state: DebuggerState::Interactive,
-            dwarf: DwarfContext::new(&debugee_pid_path).unwrap(),
-            path: debugee_pid_path,
+            dwarf,
+            path,
+            launch,
+            step_plan: None,
+            context: ContextConfig::default(),
+            last_regs: Vec::new(),
+            forks: Forks::new(),
+            signals: SignalTable::new(),
+            syscalls: SyscallTracer::new(),
+            target,
+            last_status: None,
+            last_watch_hit: None,
+            scripts: Scripts::default(),
+            command_runner: None,
+        })
+    }
+
+    // kills the current debugee and starts a fresh one from `self.launch`,
+    // breakpoints are moved to where the executable is loaded now
+    pub fn run(&mut self) -> Result<()> {
+        if self.target.is_remote() {
+            bail!("The remote target does not support run");
+        }
+        // a launch that fails leaves the current process alone
+        let process = Process::launch(&self.launch)?;
+        // the pid of a core file may belong to an unrelated process by now
+        if self.target.is_live() {
+            self.process.kill();
         }
+        self.forks.kill_held();
+        self.forks.vfork_parent = None;
+        self.step_plan = None;
+        self.last_regs.clear();
+        self.watchpoints.pending = None;
+        self.syscalls.pending.clear();
+        let old_base = self.process.base_addr;
+        self.process = process;
+        self.target = Box::new(PtraceTarget::new(self.process.pid));
+        self.process.get_base_addr_from_map();
+        self.breakpoint.rebase(old_base, self.process.base_addr);
+        self.watchpoints.rebase(old_base, self.process.base_addr);
+
+        self.breakpoint.rearm_all(self.target.as_mut());
+        if let Err(e) = self
+            .watchpoints
+            .apply(self.target.as_mut(), &self.process.thread_ids())
+        {
+            println!("Failed to re-insert hardware watchpoints: {}", e);
+        }
+
+        println!(
+            "Starting program: {} {}",
+            self.launch.path,
+            self.launch.args.join(" ")
+        );
+        self.resume_all_threads()?;
+        self.state = DebuggerState::AwaitingTrap;
+        Ok(())
     }
 
     pub fn print_functions(&self) {
         debug!("{:?}", self.functions);
     }
 
-    pub fn print_offset(&self) {
-        let regs = getregs(self.process.pid).unwrap();
+    pub fn print_offset(&self) -> Result<()> {
+        let regs = self.read_regs(self.process.tid)?;
         let func_offset = regs.rip - self.process.base_addr;
         println!("{}", func_offset);
+        Ok(())
     }
 
-    pub fn list_breakpoints(&self) -> &[(u64, u8)] {
-        &self.breakpoint.breakpoint
+    pub fn list_breakpoints(&self) -> impl Iterator<Item = &BreakpointEntry> {
+        self.breakpoint.user_breakpoints()
     }
 
-    pub fn exit(&self) -> Result<()> {
+    pub fn exit(&mut self) -> Result<()> {
         println!("Exiting the debugger...");
+        self.release();
         std::process::exit(0);
     }
 
+    // before the debugger exits, an attached process must not be left with
+    // our int3s in its code, a launched one is killed
+    pub fn release(&mut self) {
+        if self.process.threads.is_empty() || !self.target.is_live() || self.target.is_remote() {
+            return;
+        }
+        if !self.process.attached {
+            self.process.kill();
+            self.process.threads.clear();
+        } else if let Err(e) = self.detach() {
+            println!("Failed to detach from process {}: {}", self.process.pid, e);
+        }
+    }
+
+    // takes our breakpoints out and lets the debugee run on its own
+    pub fn detach(&mut self) -> Result<()> {
+        let tids = self.process.thread_ids();
+        self.breakpoint.remove_all(self.target.as_mut())?;
+        self.watchpoints.remove_all(self.target.as_mut(), &tids)?;
+        for tid in tids {
+            let signal = self
+                .process
+                .thread_mut(tid)
+                .and_then(|t| t.pending_signal.take());
+            let _ = self.target.detach(tid, signal);
+        }
+        self.process.threads.clear();
+        println!("Detached from process {}", self.process.pid);
+        Ok(())
+    }
+
+    // "func+0x10 at file.c:12" style description of an absolute address
+    pub fn describe_location(&self, addr: u64) -> String {
+        let offset = addr.wrapping_sub(self.process.base_addr);
+        let mut location = match self
+            .functions
+            .iter()
+            .find(|f| f.offset <= offset && f.offset + f.size > offset)
+        {
+            Some(f) if offset == f.offset => f.name.clone(),
+            Some(f) => format!("{}+0x{:x}", f.name, offset - f.offset),
+            None => "??".to_string(),
+        };
+        if let Some((file, line)) = self.dwarf.find_line(offset) {
+            let file = file.rsplit('/').next().unwrap_or(&file);
+            location.push_str(&format!(" at {}:{}", file, line));
+        }
+        location
+    }
+
     pub fn get_function_name(&self, target_addr: u64) -> Option<String> {
         self.functions
             .iter()
@@ -66,34 +286,112 @@ impl Debugger {
 
     #[allow(dead_code)]
     fn print_file_and_line(&self) {
-        let regs = getregs(self.process.pid).unwrap();
+        let Ok(regs) = self.read_regs(self.process.tid) else {
+            return;
+        };
         let rip = regs.rip;
         self.dwarf.get_line_and_file(rip - self.process.base_addr);
     }
 
-    pub fn parse_address(&self, input: &str) -> Result<u64> {
-        let trimmed = input.trim();
-
-        if let Some(stripped) = trimmed.strip_prefix("0x") {
-            u64::from_str_radix(stripped, 16)
-                .map_err(|e| anyhow::anyhow!("invalid hex address: {}", e))
-        } else {
-            u64::from_str_radix(trimmed, 10)
-                .map_err(|e| anyhow::anyhow!("invalid dec address: {}", e))
+    // registers and memory go through the target so a core file or a remote
+    // stub can stand in for the traced process
+    pub fn read_regs(&self, tid: Pid) -> Result<user_regs_struct> {
+        self.target.read_regs(tid)
+    }
+
+    pub fn write_regs(&mut self, tid: Pid, regs: user_regs_struct) -> Result<()> {
+        self.target.write_regs(tid, regs)
+    }
+
+    pub fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        self.target.read_memory(addr, buf)
+    }
+
+    pub fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        self.target.write_memory(addr, data)
+    }
+
+    pub fn read_word(&self, addr: u64) -> Result<u64> {
+        let mut word = [0u8; 8];
+        self.target.read_memory(addr, &mut word)?;
+        Ok(u64::from_le_bytes(word))
+    }
+
+    // resuming needs a live process
+    pub fn ensure_live(&self) -> Result<()> {
+        if !self.target.is_live() {
+            bail!(DebuggerError::NotStarted);
+        }
+        if self.process.threads.is_empty() {
+            bail!(DebuggerError::NotRunning);
         }
+        Ok(())
+    }
+
+    // addresses and values of commands are full expressions, e.g.
+    // `main+0x14`, `$rsp+0x20` or `*(u32*)($rbp-8)`
+    pub fn parse_address(&self, input: &str) -> Result<u64> {
+        expression::evaluate(input, self)
+    }
+}
+
+// the executable is mapped at its lowest NT_FILE entry, AT_ENTRY tells for
+// cores without one
+fn core_base_addr(core: &CoreFile, binary: &str) -> u64 {
+    if let Some(start) = core
+        .files
+        .iter()
+        .filter(|f| f.path == binary)
+        .map(|f| f.start)
+        .min()
+    {
+        return start;
+    }
+    entry_base_addr(core.auxv_value(AT_ENTRY), binary)
+}
+
+// load bias of a PIE, AT_ENTRY minus the entry point in the ELF header
+fn entry_base_addr(at_entry: Option<u64>, binary: &str) -> u64 {
+    let entry = fs::read(binary)
+        .ok()
+        .and_then(|data| goblin::elf::Elf::parse(&data).ok().map(|elf| elf.entry));
+    match (at_entry, entry) {
+        (Some(at_entry), Some(entry)) => at_entry.saturating_sub(entry),
+        _ => 0,
     }
 }
 
-fn get_pid_from_input(input: String) -> i32 {
-    if Path::new(&format!("/proc/{}", input)).is_dir() {
+// returns the traced process and the config to relaunch its executable with
+fn attach_or_launch(input: String, program_args: Vec<String>) -> Result<(Process, LaunchConfig)> {
+    if let Some(pid) = input
+        .parse::<i32>()
+        .ok()
+        .filter(|_| Path::new(&format!("/proc/{}", input)).is_dir())
+    {
         info!("{} is a pid", input);
-        input.parse().expect("Failed to parse PID")
+        let exe =
+            fs::read_link(format!("/proc/{}/exe", pid)).map_err(|e| DebuggerError::Attach {
+                pid: Pid::from_raw(pid),
+                source: nix::Error::from_raw(e.raw_os_error().unwrap_or(0)),
+            })?;
+        let launch = LaunchConfig::new(exe.to_string_lossy().into_owned(), program_args);
+        Ok((Process::attach(pid)?, launch))
     } else if Path::new(&input).is_file() {
         info!("{} is a file", input);
         info!("Executing {}", input);
-        let child = Command::new(input).spawn().unwrap();
-        child.id() as i32
+        let launch = LaunchConfig::new(input, program_args);
+        Ok((launch_process(&launch)?, launch))
     } else {
-        panic!("provided pid|path not valid");
+        bail!(DebuggerError::InvalidTarget(input));
     }
 }
+
+fn launch_process(launch: &LaunchConfig) -> Result<Process> {
+    Process::launch(launch).map_err(|e| {
+        DebuggerError::Launch {
+            path: launch.path.clone(),
+            reason: e.to_string(),
+        }
+        .into()
+    })
+}
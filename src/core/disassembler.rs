@@ -1,45 +1,70 @@
-use crate::core::memory::read_process_memory;
 use crate::core::Debugger;
 use anyhow::Result;
 use capstone::prelude::*;
 use log::debug;
-use nix::sys::ptrace::getregs;
 
 pub trait Disassembler {
     fn disassemble(&self) -> Result<()>;
+    fn disassemble_range(&self, start: u64, len: usize) -> Result<Vec<(u64, String)>>;
+    fn disassemble_bytes(&self, start: u64, len: usize) -> Result<Vec<(u64, Vec<u8>, String)>>;
 }
 
 impl Disassembler for Debugger {
     fn disassemble(&self) -> Result<()> {
+        let regs = self.read_regs(self.process.tid)?;
+
+        for (addr, text) in self.disassemble_range(regs.rip, 64)? {
+            println!("0x{:x}: {}", addr, text);
+            self.dwarf.get_line_and_file(addr - self.process.base_addr);
+        }
+        Ok(())
+    }
+
+    fn disassemble_range(&self, start: u64, len: usize) -> Result<Vec<(u64, String)>> {
+        Ok(self
+            .disassemble_bytes(start, len)?
+            .into_iter()
+            .map(|(addr, _, text)| (addr, text))
+            .collect())
+    }
+
+    // decodes `len` bytes at `start` with the breakpoint bytes patched out,
+    // along with the bytes of each instruction
+    fn disassemble_bytes(&self, start: u64, len: usize) -> Result<Vec<(u64, Vec<u8>, String)>> {
         let cs = Capstone::new()
             .x86()
             .mode(arch::x86::ArchMode::Mode64)
             .syntax(arch::x86::ArchSyntax::Intel)
             .detail(true)
-            .build()
-            .expect("Failed to create Capstone object");
+            .build()?;
 
-        let regs = getregs(self.process.pid).unwrap();
-
-        let rip = regs.rip;
-
-        let num_bytes = 64;
-        let mut code = vec![0u8; num_bytes];
-        read_process_memory(self.process.pid, rip as usize, &mut code)?;
+        let mut code = vec![0u8; len];
+        self.read_memory(start, &mut code)?;
+        for bp in &self.breakpoint.breakpoint {
+            if let Some(byte) = bp
+                .addr
+                .checked_sub(start)
+                .and_then(|i| code.get_mut(i as usize))
+            {
+                *byte = bp.original_byte;
+            }
+        }
         debug!("{:?}", code);
 
-        let insns = cs.disasm_all(&code, rip)?;
-
-        for i in insns.iter() {
-            println!(
-                "0x{:x}: {}\t{}",
-                i.address(),
-                i.mnemonic().unwrap_or(""),
-                i.op_str().unwrap_or("")
-            );
-            self.dwarf
-                .get_line_and_file(i.address() - self.process.base_addr);
-        }
-        Ok(())
+        let insns = cs.disasm_all(&code, start)?;
+        Ok(insns
+            .iter()
+            .map(|i| {
+                (
+                    i.address(),
+                    i.bytes().to_vec(),
+                    format!(
+                        "{}\t{}",
+                        i.mnemonic().unwrap_or(""),
+                        i.op_str().unwrap_or("")
+                    ),
+                )
+            })
+            .collect())
     }
 }
@@ -0,0 +1,36 @@
+use nix::unistd::Pid;
+use thiserror::Error;
+
+/// Failures of the engine that a caller may want to tell apart.
+///
+/// Functions return `anyhow::Result`, these are the root causes that can be
+/// matched with `err.downcast_ref::<DebuggerError>()`. Everything else, e.g.
+/// a failed ptrace request, is a plain error with a message.
+#[derive(Debug, Error)]
+pub enum DebuggerError {
+    /// The argument is neither the pid of a running process nor a file.
+    #[error("{0} is neither a running pid nor an executable")]
+    InvalidTarget(String),
+    /// ptrace could not attach to a thread of the process.
+    #[error("Failed to attach to {pid}: {source}")]
+    Attach {
+        pid: Pid,
+        #[source]
+        source: nix::Error,
+    },
+    /// The executable could not be started under ptrace.
+    #[error("Failed to launch {path}: {reason}")]
+    Launch { path: String, reason: String },
+    /// The symbols or the DWARF of an executable could not be read.
+    #[error("Failed to read symbols of {path}: {reason}")]
+    Symbols { path: String, reason: String },
+    /// `/proc/<pid>/maps` could not be read or parsed.
+    #[error("Failed to read the memory maps of {pid}: {reason}")]
+    Maps { pid: Pid, reason: String },
+    /// A core file session, the program has to be started with `run` first.
+    #[error("The program is not being run, use `run` to start it")]
+    NotStarted,
+    /// The program exited or was killed.
+    #[error("The program is not being run")]
+    NotRunning,
+}
@@ -0,0 +1,496 @@
+use crate::core::registers::Registers;
+use crate::core::Debugger;
+use anyhow::{bail, Result};
+
+// what an expression can look at, implemented by the Debugger
+pub trait ExpressionContext {
+    fn register(&self, name: &str) -> Result<u64>;
+    fn symbol(&self, name: &str) -> Option<u64>;
+    fn read_memory(&self, addr: u64, size: usize) -> Result<u64>;
+}
+
+const REGISTERS: &[&str] = &[
+    "rip", "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rsp", "rbp", "r8", "r9", "r10", "r11", "r12",
+    "r13", "r14", "r15", "eflags",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(u64),
+    Register(String),
+    Symbol(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    // `*expr`, size in bytes comes from a pointer cast, 8 otherwise
+    Deref(Box<Expr>),
+    PointerCast(usize, Box<Expr>),
+    Cast(usize, bool, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    LogicalNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(u64),
+    Ident(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+// longest operators first so `<<` wins over `<`
+const OPERATORS: &[&str] = &[
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "&", "^",
+    "|", "~", "!",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let literal: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(parse_number(&literal)?));
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' || c == '.' {
+            let start = i;
+            i += 1;
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '_' | '.' | '@'))
+            {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) else {
+                bail!("Unexpected character '{}' in expression", c);
+            };
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+fn parse_number(literal: &str) -> Result<u64> {
+    if let Some(hex) = literal
+        .strip_prefix("0x")
+        .or_else(|| literal.strip_prefix("0X"))
+    {
+        u64::from_str_radix(hex, 16).map_err(|e| anyhow::anyhow!("invalid hex number: {}", e))
+    } else {
+        literal
+            .parse::<u64>()
+            .map_err(|e| anyhow::anyhow!("invalid dec number: {}", e))
+    }
+}
+
+// (size, signed) of the supported cast types
+fn cast_type(name: &str) -> Option<(usize, bool)> {
+    match name {
+        "u8" | "uchar" => Some((1, false)),
+        "u16" | "ushort" => Some((2, false)),
+        "u32" | "uint" => Some((4, false)),
+        "u64" | "ulong" => Some((8, false)),
+        "i8" | "char" => Some((1, true)),
+        "i16" | "short" => Some((2, true)),
+        "i32" | "int" => Some((4, true)),
+        "i64" | "long" => Some((8, true)),
+        _ => None,
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+// precedence climbing, lowest binding level first, same levels as C
+const BINARY_LEVELS: &[&[(&str, BinaryOp)]] = &[
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+    &[
+        ("<", BinaryOp::Lt),
+        ("<=", BinaryOp::Le),
+        (">", BinaryOp::Gt),
+        (">=", BinaryOp::Ge),
+    ],
+    &[("<<", BinaryOp::Shl), (">>", BinaryOp::Shr)],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+    &[
+        ("*", BinaryOp::Mul),
+        ("/", BinaryOp::Div),
+        ("%", BinaryOp::Rem),
+    ],
+];
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, token: Token) -> Result<()> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            other => bail!("Expected {:?}, found {:?}", token, other),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr> {
+        if level == BINARY_LEVELS.len() {
+            return self.unary();
+        }
+        let mut lhs = self.binary(level + 1)?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let Some((_, bin_op)) = BINARY_LEVELS[level].iter().find(|(s, _)| s == op) else {
+                break;
+            };
+            let bin_op = *bin_op;
+            self.pos += 1;
+            let rhs = self.binary(level + 1)?;
+            lhs = Expr::Binary(bin_op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Op("-")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.unary()?)))
+            }
+            Some(Token::Op("~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(UnaryOp::Not, Box::new(self.unary()?)))
+            }
+            Some(Token::Op("!")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(UnaryOp::LogicalNot, Box::new(self.unary()?)))
+            }
+            Some(Token::Op("*")) => {
+                self.pos += 1;
+                Ok(Expr::Deref(Box::new(self.unary()?)))
+            }
+            // symbols already evaluate to their address, `&counter` is accepted
+            // for familiarity
+            Some(Token::Op("&")) => {
+                self.pos += 1;
+                self.unary()
+            }
+            Some(Token::LParen) => {
+                if let Some(cast) = self.cast()? {
+                    return Ok(cast);
+                }
+                self.primary()
+            }
+            _ => self.primary(),
+        }
+    }
+
+    // `(u32*)expr` or `(u32)expr`, None if the parenthesis is no cast
+    fn cast(&mut self) -> Result<Option<Expr>> {
+        let Some(Token::Ident(name)) = self.tokens.get(self.pos + 1) else {
+            return Ok(None);
+        };
+        let Some((size, signed)) = cast_type(name) else {
+            return Ok(None);
+        };
+        let pointer = self.tokens.get(self.pos + 2) == Some(&Token::Op("*"));
+        let close = self.pos + if pointer { 3 } else { 2 };
+        if self.tokens.get(close) != Some(&Token::RParen) {
+            return Ok(None);
+        }
+        self.pos = close + 1;
+        let inner = Box::new(self.unary()?);
+        Ok(Some(if pointer {
+            Expr::PointerCast(size, inner)
+        } else {
+            Expr::Cast(size, signed, inner)
+        }))
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) => {
+                if let Some(reg) = name.strip_prefix('$') {
+                    Ok(Expr::Register(reg.to_string()))
+                } else if REGISTERS.contains(&name.as_str()) {
+                    Ok(Expr::Register(name))
+                } else {
+                    Ok(Expr::Symbol(name))
+                }
+            }
+            Some(Token::LParen) => {
+                let expr = self.binary(0)?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            other => bail!("Unexpected token in expression: {:?}", other),
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Expr> {
+    let tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        bail!("Empty expression");
+    }
+    let mut parser = Parser { tokens, pos: 0 };
+    let expr = parser.binary(0)?;
+    if let Some(token) = parser.peek() {
+        bail!("Unexpected trailing token in expression: {:?}", token);
+    }
+    Ok(expr)
+}
+
+// bytes accessed through the expression when used as an address, taken
+// from a pointer cast like `(u16*)$rsp`
+pub fn access_size(expr: &Expr) -> Option<usize> {
+    match expr {
+        Expr::PointerCast(size, _) => Some(*size),
+        _ => None,
+    }
+}
+
+pub fn evaluate(input: &str, ctx: &dyn ExpressionContext) -> Result<u64> {
+    eval(&parse(input)?, ctx)
+}
+
+fn truncate(value: u64, size: usize, signed: bool) -> u64 {
+    if size >= 8 {
+        return value;
+    }
+    let bits = size * 8;
+    let masked = value & ((1u64 << bits) - 1);
+    if signed && (masked >> (bits - 1)) & 1 == 1 {
+        masked | !((1u64 << bits) - 1)
+    } else {
+        masked
+    }
+}
+
+pub fn eval(expr: &Expr, ctx: &dyn ExpressionContext) -> Result<u64> {
+    Ok(match expr {
+        Expr::Number(n) => *n,
+        Expr::Register(name) => ctx.register(name)?,
+        Expr::Symbol(name) => ctx
+            .symbol(name)
+            .ok_or_else(|| anyhow::anyhow!("No symbol \"{}\"", name))?,
+        Expr::Unary(op, inner) => {
+            let v = eval(inner, ctx)?;
+            match op {
+                UnaryOp::Neg => v.wrapping_neg(),
+                UnaryOp::Not => !v,
+                UnaryOp::LogicalNot => (v == 0) as u64,
+            }
+        }
+        Expr::Binary(op, lhs, rhs) => {
+            let l = eval(lhs, ctx)?;
+            // short circuit so `ptr && *ptr` does not fault
+            match op {
+                BinaryOp::And if l == 0 => return Ok(0),
+                BinaryOp::Or if l != 0 => return Ok(1),
+                _ => {}
+            }
+            let r = eval(rhs, ctx)?;
+            match op {
+                BinaryOp::Mul => l.wrapping_mul(r),
+                BinaryOp::Div | BinaryOp::Rem if r == 0 => bail!("Division by zero"),
+                BinaryOp::Div => l / r,
+                BinaryOp::Rem => l % r,
+                BinaryOp::Add => l.wrapping_add(r),
+                BinaryOp::Sub => l.wrapping_sub(r),
+                BinaryOp::Shl => l.checked_shl(r as u32).unwrap_or(0),
+                BinaryOp::Shr => l.checked_shr(r as u32).unwrap_or(0),
+                BinaryOp::Lt => (l < r) as u64,
+                BinaryOp::Le => (l <= r) as u64,
+                BinaryOp::Gt => (l > r) as u64,
+                BinaryOp::Ge => (l >= r) as u64,
+                BinaryOp::Eq => (l == r) as u64,
+                BinaryOp::Ne => (l != r) as u64,
+                BinaryOp::BitAnd => l & r,
+                BinaryOp::BitXor => l ^ r,
+                BinaryOp::BitOr => l | r,
+                BinaryOp::And | BinaryOp::Or => (r != 0) as u64,
+            }
+        }
+        Expr::Deref(inner) => {
+            let size = match inner.as_ref() {
+                Expr::PointerCast(size, _) => *size,
+                _ => 8,
+            };
+            ctx.read_memory(eval(inner, ctx)?, size)?
+        }
+        Expr::PointerCast(_, inner) => eval(inner, ctx)?,
+        Expr::Cast(size, signed, inner) => truncate(eval(inner, ctx)?, *size, *signed),
+    })
+}
+
+impl ExpressionContext for Debugger {
+    fn register(&self, name: &str) -> Result<u64> {
+        self.get_register_value(name)
+    }
+
+    fn symbol(&self, name: &str) -> Option<u64> {
+        self.functions
+            .iter()
+            .chain(self.globals.iter())
+            .find(|f| f.name == name)
+            .map(|f| f.offset + self.process.base_addr)
+    }
+
+    fn read_memory(&self, addr: u64, size: usize) -> Result<u64> {
+        let mut buf = [0u8; 8];
+        Debugger::read_memory(self, addr, &mut buf[..size])?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct MockContext {
+        registers: HashMap<&'static str, u64>,
+        memory: HashMap<u64, u8>,
+    }
+
+    impl ExpressionContext for MockContext {
+        fn register(&self, name: &str) -> Result<u64> {
+            self.registers
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("Unkown Register: {}", name))
+        }
+
+        fn symbol(&self, name: &str) -> Option<u64> {
+            (name == "main").then_some(0x1000)
+        }
+
+        fn read_memory(&self, addr: u64, size: usize) -> Result<u64> {
+            let mut value = 0u64;
+            for i in 0..size as u64 {
+                let byte = self.memory.get(&(addr + i)).copied().unwrap_or(0);
+                value |= (byte as u64) << (i * 8);
+            }
+            Ok(value)
+        }
+    }
+
+    fn ctx() -> MockContext {
+        let mut memory = HashMap::new();
+        for (i, byte) in 0x1122334455667788u64.to_le_bytes().iter().enumerate() {
+            memory.insert(0x7ff8 + i as u64, *byte);
+        }
+        MockContext {
+            registers: HashMap::from([("rdi", 0x2a), ("rsp", 0x7ff0), ("rbp", 0x8000)]),
+            memory,
+        }
+    }
+
+    #[test]
+    fn test_arithmetic_and_precedence() {
+        let ctx = ctx();
+        assert_eq!(evaluate("1 + 2 * 3", &ctx).unwrap(), 7);
+        assert_eq!(evaluate("(1 + 2) * 3", &ctx).unwrap(), 9);
+        assert_eq!(evaluate("0x10 >> 2 | 1", &ctx).unwrap(), 5);
+        assert_eq!(evaluate("~0 & 0xff ^ 0xf", &ctx).unwrap(), 0xf0);
+        assert_eq!(evaluate("-1", &ctx).unwrap(), u64::MAX);
+        assert!(evaluate("1 / 0", &ctx).is_err());
+    }
+
+    #[test]
+    fn test_registers_symbols_and_comparisons() {
+        let ctx = ctx();
+        assert_eq!(evaluate("rdi == 0x2a", &ctx).unwrap(), 1);
+        assert_eq!(evaluate("$rdi != 42", &ctx).unwrap(), 0);
+        assert_eq!(evaluate("main+0x14", &ctx).unwrap(), 0x1014);
+        assert_eq!(evaluate("&main", &ctx).unwrap(), 0x1000);
+        assert_eq!(evaluate("$rsp+0x20", &ctx).unwrap(), 0x8010);
+        assert_eq!(evaluate("rdi > 1 && !(rdi >= 100)", &ctx).unwrap(), 1);
+        assert!(evaluate("nosuchsymbol", &ctx).is_err());
+    }
+
+    #[test]
+    fn test_dereference_and_casts() {
+        let ctx = ctx();
+        assert_eq!(evaluate("*($rbp-8)", &ctx).unwrap(), 0x1122334455667788);
+        assert_eq!(evaluate("*(u32*)(rsp+8)", &ctx).unwrap(), 0x55667788);
+        assert_eq!(evaluate("*(u32*)(rsp+8) > 100", &ctx).unwrap(), 1);
+        assert_eq!(evaluate("*(u8*)0x7fff", &ctx).unwrap(), 0x11);
+        assert_eq!(evaluate("(u16)0x12345", &ctx).unwrap(), 0x2345);
+        assert_eq!(evaluate("(i8)0xff", &ctx).unwrap(), u64::MAX);
+        // a parenthesised symbol is not mistaken for a cast
+        assert_eq!(evaluate("(main)", &ctx).unwrap(), 0x1000);
+    }
+
+    #[test]
+    fn test_access_size() {
+        assert_eq!(access_size(&parse("(u16*)($rsp+2)").unwrap()), Some(2));
+        assert_eq!(access_size(&parse("$rsp+2").unwrap()), None);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let ctx = ctx();
+        assert!(evaluate("", &ctx).is_err());
+        assert!(evaluate("1 +", &ctx).is_err());
+        assert!(evaluate("(1", &ctx).is_err());
+        assert!(evaluate("1 2", &ctx).is_err());
+        assert!(evaluate("rdi # 1", &ctx).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::core::process::Process;
+use crate::core::stepping::Stepping;
+use crate::core::symbols::*;
+use crate::core::target::{PtraceTarget, Target};
+use crate::core::threads::ThreadControl;
+use crate::core::*;
+use anyhow::{bail, Result};
+use log::info;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use std::fs;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FollowForkMode {
+    Parent,
+    Child,
+}
+
+#[derive(Debug)]
+pub struct Forks {
+    pub follow: FollowForkMode,
+    pub detach_on_fork: bool,
+    // processes kept stopped because detach-on-fork is off, see `inferior`
+    pub held: Vec<HeldProcess>,
+    // new processes whose initial stop arrived before the fork event
+    pub early_stops: Vec<Pid>,
+    // vfork parent we stopped following, it shares the breakpoints with the
+    // child until that execs
+    pub vfork_parent: Option<Vec<Pid>>,
+}
+
+#[derive(Debug)]
+pub struct HeldProcess {
+    pub process: Process,
+    // the breakpoint it stopped on and the thread to step off it
+    step_over: Option<(u64, Option<Pid>)>,
+}
+
+impl Forks {
+    pub fn new() -> Self {
+        Forks {
+            follow: FollowForkMode::Parent,
+            detach_on_fork: true,
+            held: Vec::new(),
+            early_stops: Vec::new(),
+            vfork_parent: None,
+        }
+    }
+
+    // kills everything kept around from earlier forks, used on `run`
+    pub fn kill_held(&mut self) {
+        for held in self.held.drain(..) {
+            held.process.kill();
+        }
+    }
+}
+
+impl Default for Forks {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub trait ForkHandling {
+    fn handle_ptrace_event(&mut self, tid: Pid, event: i32) -> Result<()>;
+    fn handle_fork(&mut self, parent_tid: Pid, vfork: bool) -> Result<()>;
+    fn handle_exec(&mut self) -> Result<()>;
+    fn print_inferiors(&self);
+    fn switch_inferior(&mut self, pid: Pid) -> Result<()>;
+    fn detach_inferior(&mut self, pid: Pid) -> Result<()>;
+}
+
+impl ForkHandling for Debugger {
+    // fork, vfork, exec and vfork-done stops, the debugee keeps running
+    // afterwards
+    fn handle_ptrace_event(&mut self, tid: Pid, event: i32) -> Result<()> {
+        match event {
+            libc::PTRACE_EVENT_FORK => self.handle_fork(tid, false),
+            libc::PTRACE_EVENT_VFORK => self.handle_fork(tid, true),
+            libc::PTRACE_EVENT_EXEC => self.handle_exec(),
+            libc::PTRACE_EVENT_VFORK_DONE => {
+                // the child gave the address space back, see handle_fork
+                self.breakpoint.arm_all(self.target.as_mut())?;
+                self.resume_thread(tid, None)
+            }
+            _ => self.resume_thread(tid, None),
+        }
+    }
+
+    fn handle_fork(&mut self, parent_tid: Pid, vfork: bool) -> Result<()> {
+        let child = Pid::from_raw(self.target.event_message(parent_tid)? as i32);
+        let kind = if vfork { "vfork" } else { "fork" };
+
+        // the child is traced from the start, wait for its initial stop
+        // before touching it
+        if let Some(pos) = self.forks.early_stops.iter().position(|p| *p == child) {
+            self.forks.early_stops.remove(pos);
+        } else {
+            match waitpid(child, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Stopped(_, Signal::SIGSTOP) => {}
+                other => bail!("New process {} did not stop: {:?}", child, other),
+            }
+        }
+
+        match self.forks.follow {
+            FollowForkMode::Parent => {
+                if self.forks.detach_on_fork {
+                    // the copied memory still contains our int3s, a vfork
+                    // child shares it with the parent so they are taken out
+                    // until the parent runs again
+                    let mut child_target = PtraceTarget::new(child);
+                    if vfork {
+                        self.breakpoint.disarm_all(self.target.as_mut())?;
+                    } else {
+                        self.breakpoint.disarm_all(&mut child_target)?;
+                    }
+                    child_target.detach(child, None)?;
+                    println!("[Detaching after {} from child process {}]", kind, child);
+                } else {
+                    let mut process = Process::adopt(child)?;
+                    process.base_addr = self.process.base_addr;
+                    process.attached = self.process.attached;
+                    self.hold(process, vfork, None)?;
+                    println!("[New process {} is held stopped after {}]", child, kind);
+                }
+                self.resume_thread(parent_tid, None)
+            }
+            FollowForkMode::Child => {
+                println!(
+                    "[Attaching after process {} {} to child process {}]",
+                    self.process.pid, kind, child
+                );
+                self.cancel_step_plan();
+                self.stop_all_threads();
+                let base_addr = self.process.base_addr;
+                let attached = self.process.attached;
+                let parent = std::mem::replace(&mut self.process, Process::adopt(child)?);
+                self.target = Box::new(PtraceTarget::new(child));
+                self.process.base_addr = base_addr;
+                self.process.attached = attached;
+                let parent_tids: Vec<Pid> = parent.threads.iter().map(|t| t.tid).collect();
+
+                if !self.forks.detach_on_fork {
+                    self.hold(parent, vfork, None)?;
+                } else if vfork {
+                    // detached once the child execs, see handle_exec
+                    self.forks.vfork_parent = Some(parent_tids);
+                } else {
+                    let mut parent_target = PtraceTarget::new(parent.pid);
+                    self.breakpoint.disarm_all(&mut parent_target)?;
+                    for tid in parent_tids {
+                        let _ = parent_target.detach(tid, None);
+                    }
+                }
+
+                if let Err(e) = self.watchpoints.apply(self.target.as_mut(), &[child]) {
+                    println!("Failed to set hardware watchpoints in {}: {}", child, e);
+                }
+                self.resume_all_threads()
+            }
+        }
+    }
+
+    // the image was replaced, reload everything derived from the executable
+    fn handle_exec(&mut self) -> Result<()> {
+        let pid = self.process.pid;
+        self.cancel_step_plan();
+
+        // a vfork parent got its own address space back
+        if let Some(parent_tids) = self.forks.vfork_parent.take() {
+            let mut parent_target = PtraceTarget::new(parent_tids[0]);
+            self.breakpoint.disarm_all(&mut parent_target)?;
+            for tid in parent_tids {
+                let _ = parent_target.detach(tid, None);
+            }
+        }
+
+        // exec leaves only the thread group leader
+        self.process.threads = vec![process::Thread::new(pid)];
+        self.process.tid = pid;
+
+        let path = fs::read_link(format!("/proc/{}/exe", pid))?
+            .to_string_lossy()
+            .into_owned();
+        println!("process {} is executing new program: {}", pid, path);
+        let same_image = path == self.path;
+        self.load_symbols(&path)?;
+        self.process.maps = map::Map::new(pid).map_err(|e| anyhow::anyhow!("{}", e))?;
+        let old_base = self.process.base_addr;
+        self.process.get_base_addr_from_map();
+        info!("new base addr: {:#x}", self.process.base_addr);
+
+        // breakpoints only hold addresses, they are kept and moved along when
+        // the same executable is started again
+        if same_image {
+            self.breakpoint.rebase(old_base, self.process.base_addr);
+            self.watchpoints.rebase(old_base, self.process.base_addr);
+        } else {
+            self.forget_breakpoints();
+        }
+        self.breakpoint.rearm_all(self.target.as_mut());
+        if let Err(e) = self.watchpoints.apply(self.target.as_mut(), &[pid]) {
+            println!("Failed to re-insert hardware watchpoints: {}", e);
+        }
+        self.resume_thread(pid, None)
+    }
+
+    fn print_inferiors(&self) {
+        let current = Some(&self.process).filter(|p| !p.threads.is_empty());
+        let held = self.forks.held.iter().map(|h| &h.process);
+        for process in current.into_iter().chain(held) {
+            let marker = if process.pid == self.process.pid {
+                "*"
+            } else {
+                " "
+            };
+            let path = fs::read_link(format!("/proc/{}/exe", process.pid))
+                .map_or_else(|e| format!("<{}>", e), |p| p.display().to_string());
+            println!("{} Process {:<8} {}", marker, process.pid, path);
+        }
+    }
+
+    // makes a held process the debugee, the previous one is held instead
+    fn switch_inferior(&mut self, pid: Pid) -> Result<()> {
+        let pos = self.held_position(pid)?;
+        self.cancel_step_plan();
+        let held = self.forks.held.remove(pos);
+        let previous = std::mem::replace(&mut self.process, held.process);
+        self.target = Box::new(PtraceTarget::new(pid));
+        let step_over = self.breakpoint.step_over.take();
+        let step_over = step_over.map(|addr| (addr, self.breakpoint.step_over_thread.take()));
+        if !previous.threads.is_empty() {
+            self.hold(previous, false, step_over)?;
+        }
+        if let Some((addr, tid)) = held.step_over {
+            self.breakpoint.step_over = Some(addr);
+            self.breakpoint.step_over_thread = tid;
+        }
+        self.last_regs.clear();
+        self.watchpoints.pending = None;
+        self.syscalls.pending.clear();
+
+        // the other process may have exec'd a different program meanwhile
+        let path = fs::read_link(format!("/proc/{}/exe", pid))?
+            .to_string_lossy()
+            .into_owned();
+        if fs::canonicalize(&self.path).map_or(true, |p| p.as_os_str() != path.as_str()) {
+            self.load_symbols(&path)?;
+            self.forget_breakpoints();
+        }
+        self.process.maps = map::Map::new(pid).map_err(|e| anyhow::anyhow!("{}", e))?;
+        self.breakpoint.arm_all(self.target.as_mut())?;
+        if let Err(e) = self
+            .watchpoints
+            .apply(self.target.as_mut(), &self.process.thread_ids())
+        {
+            println!("Failed to set hardware watchpoints in {}: {}", pid, e);
+        }
+        println!("[Switching to process {} ({})]", pid, path);
+        Ok(())
+    }
+
+    fn detach_inferior(&mut self, pid: Pid) -> Result<()> {
+        let pos = self.held_position(pid)?;
+        let mut process = self.forks.held.remove(pos).process;
+        // a vfork child still shares the int3s of its parent
+        let mut target = PtraceTarget::new(pid);
+        self.breakpoint.disarm_all(&mut target)?;
+        for thread in process.threads.iter_mut() {
+            let _ = target.detach(thread.tid, thread.pending_signal.take());
+        }
+        println!("Detached from process {}", pid);
+        Ok(())
+    }
+}
+
+impl Debugger {
+    // keeps a stopped process for `inferior`, without our int3s and debug
+    // registers unless it shares the memory of a vfork
+    fn hold(
+        &mut self,
+        mut process: Process,
+        vfork: bool,
+        step_over: Option<(u64, Option<Pid>)>,
+    ) -> Result<()> {
+        if !vfork {
+            self.breakpoint
+                .disarm_all(&mut PtraceTarget::new(process.pid))?;
+        }
+        let tids = process.thread_ids();
+        watchpoint::Watchpoints::new().apply(&mut PtraceTarget::new(process.pid), &tids)?;
+        for thread in process.threads.iter_mut() {
+            thread.running = false;
+        }
+        self.forks.held.push(HeldProcess { process, step_over });
+        Ok(())
+    }
+
+    fn held_position(&self, pid: Pid) -> Result<usize> {
+        self.forks
+            .held
+            .iter()
+            .position(|h| h.process.pid == pid)
+            .ok_or_else(|| anyhow::anyhow!("No held process {}", pid))
+    }
+
+    // breakpoints of another executable mean nothing in this one
+    fn forget_breakpoints(&mut self) {
+        let removed = self.breakpoint.user_breakpoints().count() + self.watchpoints.iter().count();
+        self.breakpoint.forget_all();
+        self.watchpoints = watchpoint::Watchpoints::new();
+        if removed > 0 {
+            println!("Deleted {} breakpoint(s) of the previous program", removed);
+        }
+    }
+
+    pub fn load_symbols(&mut self, path: &str) -> Result<()> {
+        self.dwarf = DwarfContext::new(path).map_err(|e| anyhow::anyhow!("{}", e))?;
+        self.functions = FunctionInfo::new(&path.to_string(), String::new())?;
+        self.globals = FunctionInfo::globals(path);
+        self.path = path.to_string();
+        Ok(())
+    }
+}
+
+// whether a new tid belongs to the process or is a forked child
+pub fn is_thread_of(tid: Pid, pid: Pid) -> bool {
+    let Ok(status) = fs::read_to_string(format!("/proc/{}/status", tid)) else {
+        return true;
+    };
+    status
+        .lines()
+        .find_map(|l| l.strip_prefix("Tgid:"))
+        .and_then(|tgid| tgid.trim().parse::<i32>().ok())
+        .is_none_or(|tgid| tgid == pid.as_raw())
+}
@@ -0,0 +1,323 @@
+use crate::core::corefile::{
+    NT_AUXV, NT_FILE, NT_FPREGSET, NT_PRPSINFO, NT_PRSTATUS, PRSTATUS_REGS, PRSTATUS_SIZE,
+};
+use crate::core::map::Map;
+use crate::core::memory::read_process_memory;
+use crate::core::Debugger;
+use anyhow::{bail, Result};
+use libc::{user_fpregs_struct, user_regs_struct};
+use nix::unistd::Pid;
+use object::elf::{ELFOSABI_NONE, EM_X86_64, ET_CORE, PF_R, PF_W, PF_X, PT_LOAD, PT_NOTE};
+use object::write::elf::{FileHeader, ProgramHeader, Writer};
+use object::Endianness;
+use std::fs;
+use std::path::Path;
+
+// size of struct elf_prpsinfo on x86_64
+const PRPSINFO_SIZE: usize = 136;
+const PAGE_SIZE: u64 = 0x1000;
+
+pub struct CoreNote {
+    pub n_type: u32,
+    pub desc: Vec<u8>,
+}
+
+// `data` is shorter than memsz when part of the mapping was unreadable
+pub struct CoreSegment {
+    pub vaddr: u64,
+    pub memsz: u64,
+    pub flags: u32,
+    pub data: Vec<u8>,
+}
+
+pub trait CoreDump {
+    fn generate_core(&mut self, path: &str) -> Result<()>;
+}
+
+impl CoreDump for Debugger {
+    // what the kernel would have written, taken from the stopped process
+    fn generate_core(&mut self, path: &str) -> Result<()> {
+        self.ensure_live()?;
+        if self.target.is_remote() {
+            bail!("gcore is not supported on a remote target");
+        }
+        let pid = self.process.pid;
+        // libraries got mapped since the process was launched
+        self.process.maps = Map::new(pid).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let mut notes = vec![CoreNote {
+            n_type: NT_PRPSINFO,
+            desc: prpsinfo(pid, &self.path),
+        }];
+        // the selected thread comes first, readers start there
+        let mut tids: Vec<Pid> = self.process.threads.iter().map(|t| t.tid).collect();
+        tids.sort_by_key(|tid| *tid != self.process.tid);
+        for tid in tids {
+            let regs = self.read_regs(tid)?;
+            let signal = self.target.siginfo(tid).map_or(0, |info| info.si_signo);
+            notes.push(CoreNote {
+                n_type: NT_PRSTATUS,
+                desc: prstatus(tid, signal, &regs),
+            });
+            notes.push(CoreNote {
+                n_type: NT_FPREGSET,
+                desc: fpregs(tid)?,
+            });
+        }
+        if let Ok(auxv) = fs::read(format!("/proc/{}/auxv", pid)) {
+            notes.push(CoreNote {
+                n_type: NT_AUXV,
+                desc: auxv,
+            });
+        }
+        notes.push(CoreNote {
+            n_type: NT_FILE,
+            desc: nt_file(&self.process.maps),
+        });
+
+        // the dump shows the original code, not our int3s
+        self.breakpoint.disarm_all(self.target.as_mut())?;
+        let segments: Vec<CoreSegment> = self
+            .process
+            .maps
+            .iter()
+            .filter(|map| map.read)
+            .map(|map| {
+                let mut data = vec![0u8; (map.addr_end - map.addr_start) as usize];
+                // e.g. [vvar] can not be read, it is left out like gdb does
+                let len = read_process_memory(pid, map.addr_start as usize, &mut data).unwrap_or(0);
+                data.truncate(len);
+                CoreSegment {
+                    vaddr: map.addr_start,
+                    memsz: map.addr_end - map.addr_start,
+                    flags: segment_flags(map),
+                    data,
+                }
+            })
+            .collect();
+        self.breakpoint.arm_all(self.target.as_mut())?;
+
+        fs::write(path, build_core(&notes, &segments)?)
+            .map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+        println!(
+            "Saved corefile {} ({} segments, {} thread(s))",
+            path,
+            segments.len(),
+            self.process.threads.len()
+        );
+        Ok(())
+    }
+}
+
+// ELF header, PT_NOTE, one PT_LOAD per segment, then the notes and the memory
+pub fn build_core(notes: &[CoreNote], segments: &[CoreSegment]) -> Result<Vec<u8>> {
+    let note_data: Vec<u8> = notes.iter().flat_map(encode_note).collect();
+
+    let mut buffer = Vec::new();
+    let mut writer = Writer::new(Endianness::Little, true, &mut buffer);
+    writer.reserve_file_header();
+    writer.reserve_program_headers(segments.len() as u32 + 1);
+    let note_offset = writer.reserve(note_data.len(), 4);
+    let offsets: Vec<usize> = segments
+        .iter()
+        .map(|seg| {
+            let align = if seg.data.is_empty() {
+                1
+            } else {
+                PAGE_SIZE as usize
+            };
+            writer.reserve(seg.data.len(), align)
+        })
+        .collect();
+
+    writer.write_file_header(&FileHeader {
+        os_abi: ELFOSABI_NONE,
+        abi_version: 0,
+        e_type: ET_CORE,
+        e_machine: EM_X86_64,
+        e_entry: 0,
+        e_flags: 0,
+    })?;
+    writer.write_align_program_headers();
+    writer.write_program_header(&ProgramHeader {
+        p_type: PT_NOTE,
+        p_flags: 0,
+        p_offset: note_offset as u64,
+        p_vaddr: 0,
+        p_paddr: 0,
+        p_filesz: note_data.len() as u64,
+        p_memsz: 0,
+        p_align: 4,
+    });
+    for (seg, offset) in segments.iter().zip(&offsets) {
+        writer.write_program_header(&ProgramHeader {
+            p_type: PT_LOAD,
+            p_flags: seg.flags,
+            p_offset: *offset as u64,
+            p_vaddr: seg.vaddr,
+            p_paddr: 0,
+            p_filesz: seg.data.len() as u64,
+            p_memsz: seg.memsz,
+            p_align: PAGE_SIZE,
+        });
+    }
+
+    writer.write_align(4);
+    writer.write(&note_data);
+    for seg in segments.iter().filter(|seg| !seg.data.is_empty()) {
+        writer.write_align(PAGE_SIZE as usize);
+        writer.write(&seg.data);
+    }
+    Ok(buffer)
+}
+
+// namesz, descsz, type, "CORE" and the descriptor, both padded to 4 bytes
+fn encode_note(note: &CoreNote) -> Vec<u8> {
+    let name = b"CORE\0";
+    let mut out = Vec::new();
+    out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(note.desc.len() as u32).to_le_bytes());
+    out.extend_from_slice(&note.n_type.to_le_bytes());
+    out.extend_from_slice(name);
+    out.resize(out.len().next_multiple_of(4), 0);
+    out.extend_from_slice(&note.desc);
+    out.resize(out.len().next_multiple_of(4), 0);
+    out
+}
+
+pub fn prstatus(tid: Pid, signal: i32, regs: &user_regs_struct) -> Vec<u8> {
+    let mut desc = vec![0u8; PRSTATUS_SIZE];
+    desc[0..4].copy_from_slice(&signal.to_le_bytes());
+    desc[12..14].copy_from_slice(&(signal as u16).to_le_bytes());
+    desc[32..36].copy_from_slice(&tid.as_raw().to_le_bytes());
+    let size = std::mem::size_of::<user_regs_struct>();
+    let bytes = unsafe { std::slice::from_raw_parts(regs as *const _ as *const u8, size) };
+    desc[PRSTATUS_REGS..PRSTATUS_REGS + size].copy_from_slice(bytes);
+    // pr_fpvalid, an NT_FPREGSET follows
+    desc[PRSTATUS_REGS + size..PRSTATUS_REGS + size + 4].copy_from_slice(&1i32.to_le_bytes());
+    desc
+}
+
+// the fxsave area, nix has no wrapper for PTRACE_GETFPREGS
+pub fn fpregs(tid: Pid) -> Result<Vec<u8>> {
+    let mut regs = std::mem::MaybeUninit::<user_fpregs_struct>::zeroed();
+    let result = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETFPREGS,
+            tid.as_raw(),
+            std::ptr::null_mut::<libc::c_void>(),
+            regs.as_mut_ptr(),
+        )
+    };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let size = std::mem::size_of::<user_fpregs_struct>();
+    let bytes = unsafe { std::slice::from_raw_parts(regs.as_ptr() as *const u8, size) };
+    Ok(bytes.to_vec())
+}
+
+// state, pid, short name and command line, shown as "generated by" in gdb
+fn prpsinfo(pid: Pid, path: &str) -> Vec<u8> {
+    let mut desc = vec![0u8; PRPSINFO_SIZE];
+    desc[1] = b'T';
+    desc[24..28].copy_from_slice(&pid.as_raw().to_le_bytes());
+    let name = Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = name.as_bytes();
+    let len = name.len().min(15);
+    desc[40..40 + len].copy_from_slice(&name[..len]);
+    let args = fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+    let args: Vec<u8> = args
+        .strip_suffix(&[0])
+        .unwrap_or(&args)
+        .iter()
+        .map(|b| if *b == 0 { b' ' } else { *b })
+        .collect();
+    let len = args.len().min(79);
+    desc[56..56 + len].copy_from_slice(&args[..len]);
+    desc
+}
+
+// the layout parse_nt_file reads, offsets in pages
+fn nt_file(maps: &[Map]) -> Vec<u8> {
+    let files: Vec<&Map> = maps
+        .iter()
+        .filter(|m| m.file_path.starts_with('/'))
+        .collect();
+    let mut desc = Vec::new();
+    desc.extend_from_slice(&(files.len() as u64).to_le_bytes());
+    desc.extend_from_slice(&PAGE_SIZE.to_le_bytes());
+    for map in &files {
+        desc.extend_from_slice(&map.addr_start.to_le_bytes());
+        desc.extend_from_slice(&map.addr_end.to_le_bytes());
+        desc.extend_from_slice(&(map.offset / PAGE_SIZE).to_le_bytes());
+    }
+    for map in &files {
+        desc.extend_from_slice(map.file_path.as_bytes());
+        desc.push(0);
+    }
+    desc
+}
+
+fn segment_flags(map: &Map) -> u32 {
+    let mut flags = 0;
+    if map.read {
+        flags |= PF_R;
+    }
+    if map.write {
+        flags |= PF_W;
+    }
+    if map.execute {
+        flags |= PF_X;
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::corefile::CoreFile;
+
+    #[test]
+    fn test_core_round_trip() {
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401000;
+        regs.rsp = 0x7ffe0100;
+        let notes = vec![CoreNote {
+            n_type: NT_PRSTATUS,
+            desc: prstatus(Pid::from_raw(42), 11, &regs),
+        }];
+        let segments = vec![
+            CoreSegment {
+                vaddr: 0x7ffe0000,
+                memsz: 0x1000,
+                flags: PF_R | PF_W,
+                data: (0..0x1000).map(|i| i as u8).collect(),
+            },
+            CoreSegment {
+                vaddr: 0x600000,
+                memsz: 0x1000,
+                flags: PF_R,
+                data: Vec::new(),
+            },
+        ];
+        let path = std::env::temp_dir().join(format!("rusty-dbg-gcore-{}", std::process::id()));
+        fs::write(&path, build_core(&notes, &segments).unwrap()).unwrap();
+        let core = CoreFile::open(path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+
+        let core = core.unwrap();
+        assert_eq!(core.threads[0].tid, Pid::from_raw(42));
+        assert_eq!(core.threads[0].signal, 11);
+        assert_eq!(core.threads[0].regs.rip, 0x401000);
+        assert_eq!(core.segments.len(), 2);
+        assert_eq!(core.segments[1].filesz, 0);
+        let mut buf = [0u8; 4];
+        core.read_memory(0x7ffe0102, &mut buf).unwrap();
+        assert_eq!(buf, [2, 3, 4, 5]);
+        assert!(core.read_memory(0x600000, &mut buf).is_err());
+    }
+}
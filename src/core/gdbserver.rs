@@ -0,0 +1,540 @@
+use crate::core::gcore::fpregs;
+use crate::core::process_control::ProcessControl;
+use crate::core::rsp::*;
+use crate::core::stepping::Stepping;
+use crate::core::threads::ThreadControl;
+use crate::core::watchpoint::WatchKind;
+use crate::core::{Debugger, DebuggerState};
+use anyhow::{bail, Result};
+use log::debug;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use std::fs;
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const PACKET_SIZE: usize = 0x4000;
+
+// (name, bits, type) in `g` packet order, see regs_to_bytes
+const CORE_REGS: [(&str, u32, &str); 24] = [
+    ("rax", 64, "int64"),
+    ("rbx", 64, "int64"),
+    ("rcx", 64, "int64"),
+    ("rdx", 64, "int64"),
+    ("rsi", 64, "int64"),
+    ("rdi", 64, "int64"),
+    ("rbp", 64, "data_ptr"),
+    ("rsp", 64, "data_ptr"),
+    ("r8", 64, "int64"),
+    ("r9", 64, "int64"),
+    ("r10", 64, "int64"),
+    ("r11", 64, "int64"),
+    ("r12", 64, "int64"),
+    ("r13", 64, "int64"),
+    ("r14", 64, "int64"),
+    ("r15", 64, "int64"),
+    ("rip", 64, "code_ptr"),
+    ("eflags", 32, "int32"),
+    ("cs", 32, "int32"),
+    ("ss", 32, "int32"),
+    ("ds", 32, "int32"),
+    ("es", 32, "int32"),
+    ("fs", 32, "int32"),
+    ("gs", 32, "int32"),
+];
+
+const X87_CONTROL: [&str; 8] = [
+    "fctrl", "fstat", "ftag", "fiseg", "fioff", "foseg", "fooff", "fop",
+];
+
+pub trait GdbServer {
+    fn serve_gdb(&mut self, address: &str) -> Result<()>;
+}
+
+impl GdbServer for Debugger {
+    // `--gdbserver :1234`, waits for one gdb to connect and serves it until it
+    // kills or detaches from the debugee
+    fn serve_gdb(&mut self, address: &str) -> Result<()> {
+        self.context.set("all", false)?;
+        let (stream, fd): (Box<dyn Stream>, RawFd) = if address.contains('/') {
+            let listener = UnixListener::bind(address)?;
+            println!("Listening on {}", address);
+            let (stream, _) = listener.accept()?;
+            let fd = stream.as_raw_fd();
+            (Box::new(stream), fd)
+        } else {
+            let address = match address.strip_prefix(':') {
+                Some(port) => format!("0.0.0.0:{}", port),
+                None => address.to_string(),
+            };
+            let listener = TcpListener::bind(&address)?;
+            println!("Listening on {}", address);
+            let (stream, peer) = listener.accept()?;
+            stream.set_nodelay(true)?;
+            println!("Remote debugging from host {}", peer.ip());
+            let fd = stream.as_raw_fd();
+            (Box::new(stream), fd)
+        };
+        let mut server = Server {
+            debugger: self,
+            conn: RspConnection::new(stream),
+            fd,
+            general_thread: None,
+            cont_thread: None,
+            done: false,
+        };
+        while !server.done {
+            let packet = match server.conn.receive() {
+                Ok(packet) => packet,
+                Err(e) => {
+                    println!("{}", e);
+                    break;
+                }
+            };
+            match packet.as_slice() {
+                // the debugee is stopped already
+                [0x03] => continue,
+                // acked with the old setting
+                b"QStartNoAckMode" => {
+                    server.conn.send_str("OK")?;
+                    server.conn.no_ack = true;
+                    continue;
+                }
+                _ => {}
+            }
+            let reply = server.handle(&packet).unwrap_or_else(|e| {
+                // failed reads are routine, e.g. while gdb unwinds
+                debug!("{}: {}", String::from_utf8_lossy(&packet), e);
+                b"E01".to_vec()
+            });
+            if !server.done || !reply.is_empty() {
+                server.conn.send(&reply)?;
+            }
+        }
+        if !self.process.threads.is_empty() {
+            self.process.kill();
+            self.process.threads.clear();
+        }
+        Ok(())
+    }
+}
+
+struct Server<'a> {
+    debugger: &'a mut Debugger,
+    conn: RspConnection,
+    fd: RawFd,
+    // threads picked with Hg for registers and Hc for resumes
+    general_thread: Option<Pid>,
+    cont_thread: Option<Pid>,
+    done: bool,
+}
+
+impl Server<'_> {
+    fn handle(&mut self, packet: &[u8]) -> Result<Vec<u8>> {
+        // X and qXfer carry binary data, everything else is text
+        if let Some(rest) = packet.strip_prefix(b"X") {
+            let colon = rest.iter().position(|b| *b == b':').unwrap_or(rest.len());
+            let (addr, _) = parse_addr_len(&String::from_utf8_lossy(&rest[..colon]))?;
+            let data = rest.get(colon + 1..).unwrap_or_default();
+            self.debugger.write_memory(addr, data)?;
+            return Ok(b"OK".to_vec());
+        }
+        if let Some(request) = packet.strip_prefix(b"qXfer:") {
+            return self.read_xfer(&String::from_utf8_lossy(request));
+        }
+        let packet = String::from_utf8_lossy(packet).into_owned();
+        let d = &mut *self.debugger;
+        let reply = match packet.as_str() {
+            p if p.starts_with("qSupported") => format!(
+                "PacketSize={:x};QStartNoAckMode+;qXfer:features:read+;qXfer:auxv:read+;\
+                 qXfer:exec-file:read+;swbreak+;hwbreak+;vContSupported+",
+                PACKET_SIZE
+            ),
+            "?" => self.stop_reply(),
+            "qAttached" => {
+                // launched processes are our children
+                let ppid = fs::read_to_string(format!("/proc/{}/stat", d.process.pid))
+                    .ok()
+                    .and_then(|stat| {
+                        let fields = stat.rsplit(')').next()?.to_string();
+                        fields.split_whitespace().nth(1)?.parse::<u32>().ok()
+                    });
+                if ppid == Some(std::process::id()) {
+                    "0"
+                } else {
+                    "1"
+                }
+                .to_string()
+            }
+            "qC" => format!("QC{:x}", d.process.tid.as_raw()),
+            "qfThreadInfo" => {
+                let tids: Vec<String> = d
+                    .process
+                    .threads
+                    .iter()
+                    .map(|t| format!("{:x}", t.tid.as_raw()))
+                    .collect();
+                format!("m{}", tids.join(","))
+            }
+            "qsThreadInfo" => "l".to_string(),
+            "qSymbol::" => "OK".to_string(),
+            "vCont?" => "vCont;c;C;s;S".to_string(),
+            "g" => {
+                let tid = self.general_thread.unwrap_or(d.process.tid);
+                let mut bytes = vec![0u8; REGISTER_FILE_BYTES];
+                regs_to_bytes(&d.read_regs(tid)?, &mut bytes);
+                fxsave_to_bytes(&fpregs(tid)?, &mut bytes);
+                to_hex(&bytes)
+            }
+            p if p.starts_with('G') => {
+                // only the general registers are written back
+                let tid = self.general_thread.unwrap_or(d.process.tid);
+                let regs = regs_from_bytes(&from_hex(&p[1..])?)?;
+                d.write_regs(tid, regs)?;
+                "OK".to_string()
+            }
+            p if p.starts_with('m') => {
+                let (addr, len) = parse_addr_len(&p[1..])?;
+                let mut buf = vec![0u8; len.min(PACKET_SIZE / 2)];
+                d.read_memory(addr, &mut buf)?;
+                // gdb must not see its own breakpoints
+                for bp in &d.breakpoint.breakpoint {
+                    if let Some(byte) = bp
+                        .addr
+                        .checked_sub(addr)
+                        .and_then(|i| buf.get_mut(i as usize))
+                    {
+                        *byte = bp.original_byte;
+                    }
+                }
+                to_hex(&buf)
+            }
+            p if p.starts_with('M') => {
+                let (location, data) = p[1..].split_once(':').unwrap_or((&p[1..], ""));
+                let (addr, _) = parse_addr_len(location)?;
+                d.write_memory(addr, &from_hex(data)?)?;
+                "OK".to_string()
+            }
+            p if p.starts_with('H') => match (p.get(1..2), p.get(2..)) {
+                (Some(op), Some(thread)) => {
+                    let tid = parse_thread(thread)?;
+                    match op {
+                        "g" => self.general_thread = tid,
+                        _ => self.cont_thread = tid,
+                    }
+                    "OK".to_string()
+                }
+                _ => "E01".to_string(),
+            },
+            p if p.starts_with('T') => match parse_thread(&p[1..])? {
+                Some(tid) if d.process.thread(tid).is_some() => "OK".to_string(),
+                _ => "E01".to_string(),
+            },
+            p if p.starts_with('Z') || p.starts_with('z') => self.breakpoint_packet(p)?,
+            p if p.starts_with("vCont;") => self.vcont(&p[6..])?,
+            p if p.starts_with('c') => self.resume(None, None)?,
+            p if p.starts_with('s') => self.resume(Some(self.step_thread()), None)?,
+            p if p.starts_with('C') => self.resume(None, parse_signal(&p[1..]))?,
+            p if p.starts_with('S') => {
+                let tid = self.step_thread();
+                self.resume(Some(tid), parse_signal(&p[1..]))?
+            }
+            p if p.starts_with('D') => {
+                d.detach()?;
+                self.done = true;
+                "OK".to_string()
+            }
+            p if p.starts_with("vKill") => {
+                self.done = true;
+                "OK".to_string()
+            }
+            // no reply to a kill
+            "k" => {
+                self.done = true;
+                String::new()
+            }
+            _ => String::new(),
+        };
+        Ok(reply.into_bytes())
+    }
+
+    fn step_thread(&self) -> Pid {
+        self.cont_thread.unwrap_or(self.debugger.process.tid)
+    }
+
+    // features:target.xml, auxv and exec-file, read in pieces of `len`
+    fn read_xfer(&mut self, request: &str) -> Result<Vec<u8>> {
+        let parts: Vec<&str> = request.splitn(4, ':').collect();
+        let [object, "read", annex, range] = parts[..] else {
+            return Ok(Vec::new());
+        };
+        let d = &*self.debugger;
+        let data = match (object, annex) {
+            ("features", "target.xml") => target_xml().into_bytes(),
+            ("auxv", _) => fs::read(format!("/proc/{}/auxv", d.process.pid))?,
+            ("exec-file", _) => d.path.clone().into_bytes(),
+            _ => bail!("Unknown qXfer object {}", object),
+        };
+        let (offset, len) = parse_addr_len(range)?;
+        let start = (offset as usize).min(data.len());
+        let end = (start + len.min(PACKET_SIZE / 2)).min(data.len());
+        // the payload is binary, only `$#}*` get escaped by send
+        let mut reply = vec![if end < data.len() { b'm' } else { b'l' }];
+        reply.extend_from_slice(&data[start..end]);
+        Ok(reply)
+    }
+
+    fn breakpoint_packet(&mut self, packet: &str) -> Result<String> {
+        let insert = packet.starts_with('Z');
+        let mut fields = packet[1..].split(',');
+        let kind = fields.next().unwrap_or("");
+        let addr = u64::from_str_radix(fields.next().unwrap_or(""), 16)?;
+        let len = usize::from_str_radix(fields.next().unwrap_or("1"), 16)?;
+        let d = &mut *self.debugger;
+        let watch_kind = match kind {
+            "0" => {
+                if insert && !d.breakpoint.is_breakpoint(addr) {
+                    d.breakpoint.set_breakpoint(addr, d.target.as_mut())?;
+                } else if !insert && d.breakpoint.is_breakpoint(addr) {
+                    d.breakpoint.remove_breakpoint(addr, d.target.as_mut())?;
+                }
+                return Ok("OK".to_string());
+            }
+            "1" => WatchKind::Execute,
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::ReadWrite,
+            _ => return Ok(String::new()),
+        };
+        let tids = d.process.thread_ids();
+        if insert {
+            let id = d.breakpoint.allocate_id();
+            d.watchpoints
+                .set_watchpoint(id, addr, len, watch_kind, d.target.as_mut(), &tids)?;
+        } else {
+            let id = d
+                .watchpoints
+                .iter()
+                .find(|w| w.addr == addr && w.kind == watch_kind)
+                .map(|w| w.id);
+            if let Some(id) = id {
+                d.watchpoints.remove(id, d.target.as_mut(), &tids)?;
+            }
+        }
+        Ok("OK".to_string())
+    }
+
+    // all-stop: a step action steps its thread alone, otherwise everything
+    // continues
+    fn vcont(&mut self, actions: &str) -> Result<String> {
+        let mut cont = None;
+        for action in actions.split(';') {
+            let (code, thread) = action.split_once(':').unwrap_or((action, "-1"));
+            let tid = parse_thread(thread)?;
+            let signal = parse_signal(code.get(1..).unwrap_or(""));
+            match code.chars().next() {
+                Some('s' | 'S') => {
+                    let tid = tid.unwrap_or(self.debugger.process.tid);
+                    return self.resume(Some(tid), signal);
+                }
+                Some('c' | 'C') if cont.is_none() => cont = Some((tid, signal)),
+                _ => {}
+            }
+        }
+        let (tid, signal) = cont.unwrap_or((None, None));
+        if let Some(tid) = tid {
+            self.cont_thread = Some(tid);
+        }
+        self.resume(None, signal)
+    }
+
+    // runs the debugee through the engine until it stops for gdb, a step
+    // with `step` set and a continue of all threads otherwise
+    fn resume(&mut self, step: Option<Pid>, signal: Option<Signal>) -> Result<String> {
+        let d = &mut *self.debugger;
+        d.ensure_live()?;
+        d.last_status = None;
+        d.last_watch_hit = None;
+        // gdb decides which signal is passed, not the signal table
+        let signal_thread = step.or(self.cont_thread).unwrap_or(d.process.tid);
+        if let Some(thread) = d.process.thread_mut(signal_thread) {
+            thread.pending_signal = if step.is_some() { None } else { signal };
+        }
+        match step {
+            Some(tid) => {
+                d.process.tid = tid;
+                if d.step_over_breakpoint()? {
+                    return Ok(self.stop_reply());
+                }
+                d.process.add_thread(tid).stepping = true;
+                d.resume_thread(tid, signal)?;
+            }
+            None => d.cont()?,
+        }
+        d.state = DebuggerState::AwaitingTrap;
+
+        let done = Arc::new(AtomicBool::new(false));
+        let watcher = watch_interrupt(self.fd, d.process.pid, done.clone());
+        d.wait_for_stop();
+        done.store(true, Ordering::Relaxed);
+        let _ = watcher.join();
+        if matches!(d.state, DebuggerState::Exit) {
+            bail!("Lost the debugee");
+        }
+        Ok(self.stop_reply())
+    }
+
+    fn stop_reply(&self) -> String {
+        let d = &*self.debugger;
+        if d.process.threads.is_empty() {
+            return match d.last_status {
+                Some(WaitStatus::Signaled(_, signal, _)) => {
+                    format!("X{:02x}", to_gdb_signal(signal))
+                }
+                Some(WaitStatus::Exited(_, code)) => format!("W{:02x}", code as u8),
+                _ => "W00".to_string(),
+            };
+        }
+        let tid = d.process.tid;
+        let signal = match d.last_status {
+            Some(WaitStatus::Stopped(_, signal)) => signal,
+            _ => Signal::SIGTRAP,
+        };
+        let mut reply = format!("T{:02x}thread:{:x};", to_gdb_signal(signal), tid.as_raw());
+        match &d.last_watch_hit {
+            Some(hit) => match hit.kind {
+                WatchKind::Execute => reply += "hwbreak:;",
+                WatchKind::Write => reply += &format!("watch:{:x};", hit.addr),
+                WatchKind::Read => reply += &format!("rwatch:{:x};", hit.addr),
+                WatchKind::ReadWrite => reply += &format!("awatch:{:x};", hit.addr),
+            },
+            // the engine has already moved rip back onto the breakpoint
+            None if signal == Signal::SIGTRAP => {
+                let rip = d.read_regs(tid).map_or(0, |regs| regs.rip);
+                if d.breakpoint.get(rip).is_some_and(|bp| bp.enabled) {
+                    reply += "swbreak:;";
+                }
+            }
+            None => {}
+        }
+        reply
+    }
+}
+
+// gdb sends a bare 0x03 to interrupt, it is picked off the socket while the
+// debugee runs and turned into a SIGINT
+fn watch_interrupt(fd: RawFd, pid: Pid, done: Arc<AtomicBool>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        while !done.load(Ordering::Relaxed) {
+            let mut poll = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            if unsafe { libc::poll(&mut poll, 1, 50) } <= 0 {
+                continue;
+            }
+            let mut byte = 0u8;
+            let peeked = unsafe {
+                libc::recv(
+                    fd,
+                    &mut byte as *mut u8 as *mut libc::c_void,
+                    1,
+                    libc::MSG_PEEK,
+                )
+            };
+            if peeked == 1 && byte == 0x03 {
+                unsafe { libc::recv(fd, &mut byte as *mut u8 as *mut libc::c_void, 1, 0) };
+                let _ = kill(pid, Signal::SIGINT);
+            } else {
+                // anything else is read after the stop, poll would not block
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+    })
+}
+
+// "addr,len" in hex
+fn parse_addr_len(text: &str) -> Result<(u64, usize)> {
+    let (addr, len) = text.split_once(',').unwrap_or((text, "0"));
+    Ok((
+        u64::from_str_radix(addr, 16)?,
+        usize::from_str_radix(len, 16)?,
+    ))
+}
+
+// "-1" and "0" mean any thread
+fn parse_thread(text: &str) -> Result<Option<Pid>> {
+    match text {
+        "-1" | "0" | "" => Ok(None),
+        id => Ok(Some(Pid::from_raw(i32::from_str_radix(id, 16)?))),
+    }
+}
+
+fn parse_signal(text: &str) -> Option<Signal> {
+    let number = u8::from_str_radix(text.get(..2)?, 16).ok()?;
+    from_gdb_signal(number)
+}
+
+// the amd64 linux description gdb expects, in `g` packet order
+fn target_xml() -> String {
+    let reg = |name: &str, bits: u32, ty: &str, group: &str| {
+        format!(
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\" group=\"{}\"/>",
+            name, bits, ty, group
+        )
+    };
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target><architecture>i386:x86-64</architecture><osabi>GNU/Linux</osabi>\
+         <feature name=\"org.gnu.gdb.i386.core\">",
+    );
+    for (name, bits, ty) in CORE_REGS {
+        xml += &reg(name, bits, ty, "general");
+    }
+    for i in 0..8 {
+        xml += &reg(&format!("st{}", i), 80, "i387_ext", "float");
+    }
+    for name in X87_CONTROL {
+        xml += &reg(name, 32, "int", "float");
+    }
+    xml += "</feature><feature name=\"org.gnu.gdb.i386.sse\">";
+    for i in 0..16 {
+        xml += &reg(&format!("xmm{}", i), 128, "uint128", "vector");
+    }
+    xml += &reg("mxcsr", 32, "int", "vector");
+    xml += "</feature><feature name=\"org.gnu.gdb.i386.linux\">";
+    xml += &reg("orig_rax", 64, "int", "system");
+    xml += "</feature><feature name=\"org.gnu.gdb.i386.segments\">";
+    xml += &reg("fs_base", 64, "int", "system");
+    xml += &reg("gs_base", 64, "int", "system");
+    xml += "</feature></target>";
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_description() {
+        let xml = target_xml();
+        // 24 general, 8 st, 8 x87 control, 16 xmm, mxcsr, orig_rax, fs/gs_base
+        assert_eq!(xml.matches("<reg ").count(), 60);
+        let bits: u32 = xml
+            .split("bitsize=\"")
+            .skip(1)
+            .map(|rest| rest.split('"').next().unwrap().parse::<u32>().unwrap())
+            .sum();
+        assert_eq!(bits as usize / 8, REGISTER_FILE_BYTES);
+        assert_eq!(parse_addr_len("401000,10").unwrap(), (0x401000, 0x10));
+        assert_eq!(parse_thread("-1").unwrap(), None);
+        assert_eq!(parse_signal("0a"), Some(Signal::SIGBUS));
+    }
+}
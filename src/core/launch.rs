@@ -0,0 +1,140 @@
+use anyhow::Result;
+use nix::sys::ptrace;
+use std::ffi::CString;
+use std::os::unix::ffi::OsStringExt;
+
+// everything needed to (re)start the debugee, configured via cli and `set`
+#[derive(Debug, Clone, Default)]
+pub struct LaunchConfig {
+    pub path: String,
+    pub args: Vec<String>,
+    pub env: Vec<(String, String)>,
+    pub cwd: Option<String>,
+    pub stdin: Option<String>,
+    pub stdout: Option<String>,
+    pub stderr: Option<String>,
+}
+
+// all allocations happen before fork, the child only does raw syscalls
+pub struct PreparedLaunch {
+    path: CString,
+    _argv: Vec<CString>,
+    _envp: Vec<CString>,
+    argv_ptrs: Vec<*const libc::c_char>,
+    envp_ptrs: Vec<*const libc::c_char>,
+    cwd: Option<CString>,
+    stdin: Option<CString>,
+    stdout: Option<CString>,
+    stderr: Option<CString>,
+}
+
+impl LaunchConfig {
+    pub fn new(path: String, args: Vec<String>) -> Self {
+        LaunchConfig {
+            path,
+            args,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_env(&mut self, name: &str, value: &str) {
+        self.env.retain(|(n, _)| n != name);
+        self.env.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn prepare(&self) -> Result<PreparedLaunch> {
+        // the child changes into `cwd` before exec, a relative path would
+        // then be looked up from there
+        let path = std::path::absolute(&self.path)?;
+        let path = CString::new(path.into_os_string().into_vec())?;
+
+        let mut argv = vec![path.clone()];
+        for arg in &self.args {
+            argv.push(CString::new(arg.as_str())?);
+        }
+
+        let mut envp = Vec::new();
+        for (name, value) in std::env::vars() {
+            if !self.env.iter().any(|(n, _)| *n == name) {
+                envp.push(CString::new(format!("{}={}", name, value))?);
+            }
+        }
+        for (name, value) in &self.env {
+            envp.push(CString::new(format!("{}={}", name, value))?);
+        }
+
+        let to_cstring = |s: &Option<String>| -> Result<Option<CString>> {
+            Ok(match s {
+                Some(s) => Some(CString::new(s.as_str())?),
+                None => None,
+            })
+        };
+
+        // the pointers stay valid as the CString buffers live on the heap
+        let null_terminated = |strings: &[CString]| -> Vec<*const libc::c_char> {
+            let mut ptrs: Vec<_> = strings.iter().map(|s| s.as_ptr()).collect();
+            ptrs.push(std::ptr::null());
+            ptrs
+        };
+
+        Ok(PreparedLaunch {
+            path,
+            argv_ptrs: null_terminated(&argv),
+            envp_ptrs: null_terminated(&envp),
+            _argv: argv,
+            _envp: envp,
+            cwd: to_cstring(&self.cwd)?,
+            stdin: to_cstring(&self.stdin)?,
+            stdout: to_cstring(&self.stdout)?,
+            stderr: to_cstring(&self.stderr)?,
+        })
+    }
+}
+
+impl PreparedLaunch {
+    // runs in the forked child, never returns
+    pub fn exec(&self) -> ! {
+        unsafe {
+            // keep addresses stable between runs so breakpoints can be re-armed
+            libc::personality(libc::ADDR_NO_RANDOMIZE as libc::c_ulong);
+
+            if let Some(cwd) = &self.cwd {
+                if libc::chdir(cwd.as_ptr()) != 0 {
+                    libc::_exit(126);
+                }
+            }
+
+            let redirects = [
+                (&self.stdin, libc::STDIN_FILENO, libc::O_RDONLY),
+                (
+                    &self.stdout,
+                    libc::STDOUT_FILENO,
+                    libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+                ),
+                (
+                    &self.stderr,
+                    libc::STDERR_FILENO,
+                    libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC,
+                ),
+            ];
+            for (file, target_fd, flags) in redirects {
+                if let Some(file) = file {
+                    let fd = libc::open(file.as_ptr(), flags, 0o644);
+                    if fd < 0 || libc::dup2(fd, target_fd) < 0 {
+                        libc::_exit(126);
+                    }
+                    libc::close(fd);
+                }
+            }
+
+            if ptrace::traceme().is_ok() {
+                libc::execve(
+                    self.path.as_ptr(),
+                    self.argv_ptrs.as_ptr(),
+                    self.envp_ptrs.as_ptr(),
+                );
+            }
+            libc::_exit(127);
+        }
+    }
+}
@@ -14,6 +14,7 @@ pub struct Map {
     pub execute: bool,
     pub shared: bool,
     pub private: bool,
+    pub offset: u64,
     pub file_path: String,
 }
 
@@ -59,6 +60,7 @@ impl Map {
                 _ => (),
             }
         }
+        let offset = u64::from_str_radix(parts[2], 16)?;
         let file_path = parts.last().unwrap_or(&"");
 
         Ok(Map {
@@ -69,6 +71,7 @@ impl Map {
             execute,
             shared,
             private,
+            offset,
             file_path: file_path.to_string(),
         })
     }
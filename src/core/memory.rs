@@ -1,34 +1,39 @@
+use crate::core::expression;
 use crate::core::Debugger;
 use anyhow::Result;
 use libc::{iovec, pid_t, process_vm_readv};
-use nix::sys::ptrace;
 use nix::unistd::Pid;
 use std::io::Error;
 
 pub trait Memory {
-    fn patch(&self, addr_str: &str, value_str: &str) -> Result<()>;
+    fn patch(&mut self, addr_str: &str, value_str: &str) -> Result<()>;
+    #[allow(dead_code)]
     fn get_address_value(&self, addr_str: &str) -> Result<i64>;
     fn dump_hex(&mut self, addr_str: &str, size: usize) -> Result<()>;
+    fn read_mapped(&self, addr: u64, len: usize) -> Vec<u8>;
 }
 
 impl Memory for Debugger {
-    fn patch(&self, addr_str: &str, value_str: &str) -> Result<()> {
-        let addr = self.parse_address(addr_str)?;
+    // writes 8 bytes unless the address is cast, `patch (u8*)$rsp 0x41`
+    fn patch(&mut self, addr_str: &str, value_str: &str) -> Result<()> {
+        self.ensure_live()?;
+        let addr_expr = expression::parse(addr_str)?;
+        let addr = expression::eval(&addr_expr, self)?;
         let value = self.parse_address(value_str)?;
+        let size = expression::access_size(&addr_expr).unwrap_or(8);
 
-        ptrace::write(self.process.pid, addr as ptrace::AddressType, value as i64)?;
-        Ok(())
+        self.write_memory(addr, &value.to_le_bytes()[..size])
     }
 
     fn get_address_value(&self, addr_str: &str) -> Result<i64> {
         let addr = self.parse_address(addr_str)?;
-        Ok(ptrace::read(self.process.pid, addr as ptrace::AddressType)?)
+        Ok(self.read_word(addr)? as i64)
     }
 
     fn dump_hex(&mut self, addr_str: &str, size: usize) -> Result<()> {
         let addr = self.parse_address(addr_str)?;
         let mut buf = vec![0u8; size];
-        read_process_memory(self.process.pid, addr as usize, &mut buf)?;
+        self.read_memory(addr, &mut buf)?;
 
         for (i, chunk) in buf.chunks(16).enumerate() {
             print!("0x{:08X}: ", addr as usize + i * 16);
@@ -53,6 +58,25 @@ impl Memory for Debugger {
         }
         Ok(())
     }
+
+    // reads up to the first page that is not mapped
+    fn read_mapped(&self, addr: u64, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        let mut read = 0;
+        while read < len {
+            let page_end = ((addr + read as u64) | 0xfff) + 1;
+            let chunk = ((page_end - addr) as usize).min(len) - read;
+            if self
+                .read_memory(addr + read as u64, &mut data[read..read + chunk])
+                .is_err()
+            {
+                break;
+            }
+            read += chunk;
+        }
+        data.truncate(read);
+        data
+    }
 }
 
 pub fn read_process_memory(pid: Pid, addr: usize, buf: &mut [u8]) -> Result<usize> {
@@ -1,14 +1,31 @@
 pub mod backtrace;
 pub mod breakpoint;
+pub mod breakpoint_helpers;
+pub mod context;
+pub mod corefile;
 pub mod debugger;
 pub mod disassembler;
+pub mod error;
+pub mod expression;
+pub mod fork;
+pub mod gcore;
+pub mod gdbserver;
+pub mod launch;
 pub mod map;
 pub mod memory;
 pub mod process;
 pub mod process_control;
 pub mod registers;
+pub mod remote;
+pub mod rsp;
+pub mod script;
+pub mod signals;
 pub mod stepping;
 pub mod symbols;
-pub mod breakpoint_helpers;
+pub mod syscall_table;
+pub mod syscalls;
+pub mod target;
+pub mod threads;
+pub mod watchpoint;
 
 pub use debugger::*;
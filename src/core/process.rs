@@ -1,27 +1,230 @@
+use crate::core::corefile::{segment_permissions, CoreFile};
+use crate::core::error::DebuggerError;
+use crate::core::launch::LaunchConfig;
 use crate::core::map::Map;
+use anyhow::{bail, Result};
 use log::{debug, info};
 use nix::sys::ptrace;
-use nix::unistd::Pid;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{fork, ForkResult, Pid};
 use std::fs;
 use std::io::{self, BufRead, BufReader};
 
+#[derive(Debug)]
+pub struct Thread {
+    pub tid: Pid,
+    pub running: bool,
+    // resumed with PTRACE_SINGLESTEP rather than PTRACE_CONT
+    pub stepping: bool,
+    // a SIGSTOP we sent that the thread has not reported yet
+    pub pending_stop: bool,
+    // signal the thread stopped with while all threads were being stopped,
+    // delivered on the next resume
+    pub pending_signal: Option<Signal>,
+}
+
+impl Thread {
+    pub fn new(tid: Pid) -> Self {
+        Thread {
+            tid,
+            running: false,
+            stepping: false,
+            pending_stop: false,
+            pending_signal: None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Process {
+    // thread group id, used for process wide operations
     pub pid: Pid,
+    // selected thread, registers and stepping operate on it
+    pub tid: Pid,
+    pub threads: Vec<Thread>,
     pub maps: Vec<Map>,
     pub base_addr: u64,
+    // not started by us, detached rather than killed when the debugger exits
+    pub attached: bool,
+}
+
+fn trace_options() -> ptrace::Options {
+    ptrace::Options::PTRACE_O_EXITKILL | attach_options()
+}
+
+// without EXITKILL, an attached process survives the debugger
+fn attach_options() -> ptrace::Options {
+    ptrace::Options::PTRACE_O_TRACECLONE
+        | ptrace::Options::PTRACE_O_TRACEFORK
+        | ptrace::Options::PTRACE_O_TRACEVFORK
+        | ptrace::Options::PTRACE_O_TRACEVFORKDONE
+        | ptrace::Options::PTRACE_O_TRACEEXEC
+        | ptrace::Options::PTRACE_O_TRACESYSGOOD
 }
 
 impl Process {
-    pub fn attach(pid: i32) -> Self {
+    // attaches to every thread of the process
+    pub fn attach(pid: i32) -> Result<Self> {
         let pid = Pid::from_raw(pid);
-        ptrace::attach(pid).expect("Failed to attach to process");
-        info!("Successfully attached to PID: {}", pid);
-        let maps = Map::new(pid).expect("Failed to get maps");
+        let attach_error = |source| DebuggerError::Attach { pid, source };
+        let tids = task_ids(pid)
+            .map_err(|e| attach_error(nix::Error::from_raw(e.raw_os_error().unwrap_or(0))))?;
+        let mut threads = Vec::new();
+        for tid in tids {
+            ptrace::attach(tid).map_err(attach_error)?;
+            waitpid(tid, Some(WaitPidFlag::__WALL)).map_err(attach_error)?;
+            ptrace::setoptions(tid, attach_options()).map_err(attach_error)?;
+            threads.push(Thread::new(tid));
+        }
+        info!(
+            "Successfully attached to PID: {} ({} threads)",
+            pid,
+            threads.len()
+        );
+        let maps = Map::new(pid).map_err(|e| DebuggerError::Maps {
+            pid,
+            reason: e.to_string(),
+        })?;
+        Ok(Process {
+            pid,
+            tid: pid,
+            threads,
+            maps,
+            base_addr: 0,
+            attached: true,
+        })
+    }
+
+    // fork + PTRACE_TRACEME + exec, the child stops with SIGTRAP right after exec
+    // so nothing of the target (not even the dynamic loader) has run yet
+    pub fn launch(config: &LaunchConfig) -> Result<Self> {
+        let prepared = config.prepare()?;
+
+        match unsafe { fork() }? {
+            ForkResult::Child => prepared.exec(),
+            ForkResult::Parent { child } => {
+                match waitpid(child, None)? {
+                    WaitStatus::Stopped(_, Signal::SIGTRAP) => {}
+                    other => bail!("{} did not stop at exec: {:?}", config.path, other),
+                }
+                ptrace::setoptions(child, trace_options())?;
+                info!("Launched {} as PID: {}", config.path, child);
+                let maps = Map::new(child).map_err(|e| anyhow::anyhow!("{}", e))?;
+                Ok(Process {
+                    pid: child,
+                    tid: child,
+                    threads: vec![Thread::new(child)],
+                    maps,
+                    base_addr: 0,
+                    attached: false,
+                })
+            }
+        }
+    }
+
+    // a forked child that is already traced and stopped, options are
+    // inherited from the parent
+    pub fn adopt(pid: Pid) -> Result<Self> {
+        let maps = Map::new(pid).map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(Process {
+            pid,
+            tid: pid,
+            threads: vec![Thread::new(pid)],
+            maps,
+            base_addr: 0,
+            attached: false,
+        })
+    }
+
+    // threads as reported by a remote stub, its memory maps are unknown
+    pub fn remote(pid: Pid, tids: &[Pid]) -> Self {
+        Process {
+            pid,
+            tid: pid,
+            threads: tids.iter().map(|tid| Thread::new(*tid)).collect(),
+            maps: Vec::new(),
+            base_addr: 0,
+            attached: false,
+        }
+    }
+
+    // threads and mappings of a core dump, nothing is running
+    pub fn from_core(core: &CoreFile) -> Self {
+        let maps = core
+            .segments
+            .iter()
+            .map(|seg| {
+                let (read, write, execute) = segment_permissions(seg.flags);
+                let file = core
+                    .files
+                    .iter()
+                    .find(|f| f.start <= seg.vaddr && seg.vaddr < f.end);
+                Map {
+                    addr_start: seg.vaddr,
+                    addr_end: seg.vaddr + seg.memsz,
+                    read,
+                    write,
+                    execute,
+                    shared: false,
+                    private: true,
+                    offset: file.map_or(0, |f| f.offset + seg.vaddr - f.start),
+                    file_path: file.map(|f| f.path.clone()).unwrap_or_default(),
+                }
+            })
+            .collect();
+        let pid = core.threads[0].tid;
         Process {
             pid,
+            tid: pid,
+            threads: core.threads.iter().map(|t| Thread::new(t.tid)).collect(),
             maps,
             base_addr: 0,
+            attached: false,
+        }
+    }
+
+    pub fn kill(&self) {
+        let _ = nix::sys::signal::kill(self.pid, Signal::SIGKILL);
+        // the leader is reported last, once all other threads are reaped
+        for thread in self.threads.iter().filter(|t| t.tid != self.pid) {
+            let _ = waitpid(thread.tid, Some(WaitPidFlag::__WALL));
+        }
+        let _ = waitpid(self.pid, Some(WaitPidFlag::__WALL));
+    }
+
+    pub fn thread(&self, tid: Pid) -> Option<&Thread> {
+        self.threads.iter().find(|t| t.tid == tid)
+    }
+
+    pub fn thread_mut(&mut self, tid: Pid) -> Option<&mut Thread> {
+        self.threads.iter_mut().find(|t| t.tid == tid)
+    }
+
+    // selected thread first, the debug registers are per thread
+    pub fn thread_ids(&self) -> Vec<Pid> {
+        let mut tids = vec![self.tid];
+        tids.extend(
+            self.threads
+                .iter()
+                .map(|t| t.tid)
+                .filter(|t| *t != self.tid),
+        );
+        tids
+    }
+
+    pub fn add_thread(&mut self, tid: Pid) -> &mut Thread {
+        if let Some(pos) = self.threads.iter().position(|t| t.tid == tid) {
+            return &mut self.threads[pos];
+        }
+        self.threads.push(Thread::new(tid));
+        self.threads.last_mut().unwrap()
+    }
+
+    pub fn remove_thread(&mut self, tid: Pid) {
+        self.threads.retain(|t| t.tid != tid);
+        if self.tid == tid {
+            self.tid = self.pid;
         }
     }
     #[allow(dead_code)]
@@ -69,3 +272,15 @@ impl Process {
         }
     }
 }
+
+fn task_ids(pid: Pid) -> io::Result<Vec<Pid>> {
+    let mut tids = Vec::new();
+    for entry in fs::read_dir(format!("/proc/{}/task", pid))? {
+        if let Some(tid) = entry?.file_name().to_str().and_then(|s| s.parse().ok()) {
+            tids.push(Pid::from_raw(tid));
+        }
+    }
+    // the leader first so it ends up as the selected thread
+    tids.sort_by_key(|t| *t != pid);
+    Ok(tids)
+}
@@ -1,52 +1,197 @@
+use crate::core::breakpoint::BreakpointKind;
+use crate::core::context::Context;
+use crate::core::expression;
+use crate::core::fork::ForkHandling;
+use crate::core::script::Scripting;
+use crate::core::signals::{describe_si_code, has_fault_addr, signal_text};
+use crate::core::stepping::Stepping;
+use crate::core::syscalls::SyscallTracing;
+use crate::core::threads::ThreadControl;
+use crate::core::watchpoint::{WatchKind, WatchpointHit};
 use crate::core::*;
 use log::info;
-use nix::sys::ptrace::*;
-use nix::sys::wait::{waitpid, WaitStatus};
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
 
+pub enum TrapEvent {
+    // a user breakpoint was hit and its condition held, with its commands
+    Breakpoint(Vec<String>),
+    // the condition did not hold, the debugee is running again
+    Resumed,
+    // single step or internal breakpoint
+    Other,
+}
+
+/// Waiting for and handling the events of the debugee.
 pub trait ProcessControl {
+    /// Waits for the next event of any thread and handles it, the state goes
+    /// back to `Interactive` once the debugee stopped for the user.
     fn resume_and_wait(&mut self);
-    fn handle_sigtrap(&mut self);
+    /// Handles events until the debugee stops after `cont`, `step_line`, ...
+    fn wait_for_stop(&mut self);
+    fn handle_sigtrap(&mut self) -> TrapEvent;
+    fn report_watchpoint(&mut self, hit: &WatchpointHit);
+    fn report_step_watch_hit(&mut self) -> bool;
+    fn report_signal(&mut self, signal: Signal);
+    fn run_breakpoint_commands(&mut self, commands: &[String]);
 }
 
 impl ProcessControl for Debugger {
     fn resume_and_wait(&mut self) {
-        let status = waitpid(self.process.pid, None);
+        // any thread of the debugee may report
+        let status = self.target.wait(None);
+        if let Ok(status) = &status {
+            self.last_status = Some(*status);
+            if self.handle_thread_event(status) {
+                return;
+            }
+        }
         match status {
             Ok(WaitStatus::Exited(_, exit_status)) => {
                 println!("Process exited with status: {}", exit_status);
+                self.step_plan = None;
+                self.process.threads.clear();
+                self.state = DebuggerState::Interactive;
             }
-            Ok(WaitStatus::Stopped(_, signal)) => {
-                //temp for now, thats why sigtrap check below stays
-                if signal != nix::sys::signal::Signal::SIGTRAP {
-                    nix::sys::ptrace::cont(self.process.pid, None)
-                        .expect("Failed to continue process");
+            Ok(WaitStatus::Stopped(tid, signal)) => {
+                let mut was_stepping = false;
+                if let Some(thread) = self.process.thread_mut(tid) {
+                    was_stepping = thread.stepping;
+                    thread.running = false;
+                    thread.stepping = false;
+                }
+                if signal != Signal::SIGTRAP {
+                    let policy = self.signals.get(signal);
+                    if !policy.stop {
+                        if policy.print {
+                            println!(
+                                "Thread {} received signal {}, {}",
+                                tid,
+                                signal.as_str(),
+                                signal_text(signal)
+                            );
+                        }
+                        // an interrupted single step is repeated with the
+                        // signal, the handler runs before the step lands
+                        if let Some(thread) = self.process.thread_mut(tid) {
+                            thread.stepping = was_stepping;
+                        }
+                        if let Err(e) = self.resume_thread(tid, policy.pass.then_some(signal)) {
+                            println!("Failed to continue thread {}: {}", tid, e);
+                        }
+                        return;
+                    }
+                    self.stop_all_threads();
+                    self.cancel_step_plan();
+                    if tid != self.process.tid {
+                        println!("[Switching to thread {}]", tid);
+                    }
+                    self.process.tid = tid;
+                    if policy.pass {
+                        // delivered by the next resume_all_threads
+                        if let Some(thread) = self.process.thread_mut(tid) {
+                            thread.pending_signal = Some(signal);
+                        }
+                    }
+                    self.report_signal(signal);
+                    self.print_context();
+                    self.state = DebuggerState::Interactive;
                     return;
                 }
-                let regs = getregs(self.process.pid).unwrap();
-                if let Some(function_name) =
-                    self.get_function_name(regs.rip - self.process.base_addr)
+
+                self.stop_all_threads();
+                let previous_tid = self.process.tid;
+                self.process.tid = tid;
+
+                if let Some(hit) = self
+                    .watchpoints
+                    .check_hit(self.target.as_mut(), self.process.tid)
+                    .unwrap_or(None)
                 {
-                    println!(
-                        "Process stopped by signal: {:?} at addr: 0x{:x} ({})",
-                        signal,
-                        regs.rip - 1,
-                        function_name
-                    );
-                } else {
-                    println!(
-                        "Process stopped by signal: {:?} at addr: 0x{:x}",
-                        signal,
-                        regs.rip - 1
-                    )
+                    if hit.is_filtered() {
+                        if self.step_plan.is_some() {
+                            self.continue_step_plan();
+                        } else if let Err(e) = self.cont() {
+                            println!("Failed to continue past watchpoint {}: {}", hit.id, e);
+                            self.state = DebuggerState::Interactive;
+                        }
+                        self.process.tid = previous_tid;
+                        return;
+                    }
+                    self.cancel_step_plan();
+                    self.report_watchpoint(&hit);
+                    self.last_watch_hit = Some(hit);
+                    self.print_context();
+                    self.state = DebuggerState::Interactive;
+                    return;
+                }
+
+                let mut commands = Vec::new();
+                match self.handle_sigtrap() {
+                    TrapEvent::Resumed => return,
+                    TrapEvent::Breakpoint(list) => {
+                        self.cancel_step_plan();
+                        commands = list;
+                    }
+                    TrapEvent::Other if self.step_plan.as_ref().is_some_and(|p| p.tid != tid) => {
+                        // another thread ran into an internal breakpoint of the
+                        // step, move it past and keep going
+                        if let Err(e) = self.cont() {
+                            println!("Failed to resume thread {}: {}", tid, e);
+                        }
+                        self.process.tid = previous_tid;
+                        return;
+                    }
+                    TrapEvent::Other if self.step_plan.is_some() => {
+                        self.continue_step_plan();
+                        return;
+                    }
+                    TrapEvent::Other => {}
                 }
 
-                if signal == nix::sys::signal::Signal::SIGTRAP {
-                    self.handle_sigtrap();
+                if tid != previous_tid {
+                    println!("[Switching to thread {}]", tid);
+                }
+
+                if commands.first().is_none_or(|c| c != "silent") {
+                    let rip = self.read_regs(self.process.tid).map_or(0, |regs| regs.rip);
+                    if let Some(function_name) =
+                        self.get_function_name(rip.wrapping_sub(self.process.base_addr))
+                    {
+                        println!(
+                            "Process stopped by signal: {:?} at addr: 0x{:x} ({})",
+                            signal, rip, function_name
+                        );
+                    } else {
+                        println!(
+                            "Process stopped by signal: {:?} at addr: 0x{:x}",
+                            signal, rip
+                        )
+                    }
+                    self.print_context();
                 }
                 self.state = DebuggerState::Interactive;
+                self.run_breakpoint_commands(&commands);
             }
             Ok(WaitStatus::Signaled(_, signal, _)) => {
-                println!("Process terminated by signal: {:?}", signal);
+                println!(
+                    "Process terminated by signal: {}, {}",
+                    signal.as_str(),
+                    signal_text(signal)
+                );
+                self.step_plan = None;
+                self.process.threads.clear();
+                self.state = DebuggerState::Interactive;
+            }
+            Ok(WaitStatus::PtraceSyscall(tid)) => self.handle_syscall_stop(tid),
+            Ok(WaitStatus::PtraceEvent(tid, _, event)) => {
+                if let Some(thread) = self.process.thread_mut(tid) {
+                    thread.running = false;
+                }
+                if let Err(e) = self.handle_ptrace_event(tid, event) {
+                    println!("Failed to handle ptrace event {} of {}: {}", event, tid, e);
+                    self.state = DebuggerState::Interactive;
+                }
             }
             Ok(_) => {
                 println!("Process changed state.");
@@ -58,17 +203,191 @@ impl ProcessControl for Debugger {
         }
     }
 
-    fn handle_sigtrap(&mut self) {
-        // remove BP and replace with org. once hit
-        let mut regs = getregs(self.process.pid).unwrap();
-        let cur_addr = regs.rip - 1;
+    fn wait_for_stop(&mut self) {
+        while matches!(self.state, DebuggerState::AwaitingTrap) {
+            self.resume_and_wait();
+        }
+    }
+
+    fn handle_sigtrap(&mut self) -> TrapEvent {
+        let Some(cur_addr) = self.target.breakpoint_address(self.process.tid) else {
+            return TrapEvent::Other;
+        };
+
+        // rewind to the BP, temporary ones are removed, persistent ones get
+        // re-inserted once we stepped past them
+        let Ok(mut regs) = self.read_regs(self.process.tid) else {
+            return TrapEvent::Other;
+        };
         info!("Sigtrap HANDLE Cur Addr: 0x{:x}", cur_addr);
-        if self.breakpoint.is_breakpoint(cur_addr) {
+        let Some(bp) = self.breakpoint.get(cur_addr).filter(|bp| bp.enabled) else {
+            return TrapEvent::Other;
+        };
+        let (id, kind, condition) = (bp.id, bp.kind, bp.condition.clone());
+        // taken now, a temporary breakpoint is gone once we report the hit
+        let commands = bp.commands.clone();
+
+        regs.rip = cur_addr;
+        let _ = self.write_regs(self.process.tid, regs);
+
+        if kind == BreakpointKind::Internal {
+            if let Err(e) =
+                self.breakpoint
+                    .begin_step_over(cur_addr, self.target.as_mut(), self.process.tid)
+            {
+                println!("Failed to step over breakpoint at 0x{:x}: {}", cur_addr, e);
+            }
+            return TrapEvent::Other;
+        }
+
+        let hit = match condition {
+            Some(cond) => match expression::evaluate(&cond, self) {
+                Ok(value) => value != 0,
+                Err(e) => {
+                    println!("Error in condition of breakpoint {}: {}", id, e);
+                    true
+                }
+            },
+            None => true,
+        };
+        // counted before a temporary breakpoint is removed below
+        let mut hits = 0;
+        if hit {
+            if let Some(bp) = self.breakpoint.get_mut(cur_addr) {
+                bp.hit_count += 1;
+                hits = bp.hit_count;
+            }
+        }
+        // a script callback decides itself whether to stop
+        let stop = hit && self.run_breakpoint_callback(id).unwrap_or(true);
+
+        let result = if hit && kind == BreakpointKind::Temporary {
+            self.breakpoint
+                .remove_breakpoint(cur_addr, self.target.as_mut())
+        } else {
             self.breakpoint
-                .remove_breakpoint(cur_addr, self.process.pid)
-                .unwrap();
-            regs.rip -= 1;
-            let _ = setregs(self.process.pid, regs);
+                .begin_step_over(cur_addr, self.target.as_mut(), self.process.tid)
+        };
+        if let Err(e) = result {
+            println!("Failed to step over breakpoint {}: {}", id, e);
+        }
+
+        if !stop {
+            if self.step_plan.is_some() {
+                // keep stepping instead of running off
+                return TrapEvent::Other;
+            }
+            if let Err(e) = self.cont() {
+                println!("Failed to continue past breakpoint {}: {}", id, e);
+                return TrapEvent::Breakpoint(Vec::new());
+            }
+            return TrapEvent::Resumed;
+        }
+
+        if commands.first().is_none_or(|c| c != "silent") {
+            println!("Breakpoint {}, hit {} time(s)", id, hits);
+        }
+        TrapEvent::Breakpoint(commands)
+    }
+
+    // a watchpoint that triggered during an internal single step, true if
+    // one was reported and the debugee stays stopped
+    fn report_step_watch_hit(&mut self) -> bool {
+        let Some((tid, hit)) = self.watchpoints.pending.take() else {
+            return false;
+        };
+        if hit.is_filtered() {
+            return false;
+        }
+        self.cancel_step_plan();
+        if tid != self.process.tid {
+            println!("[Switching to thread {}]", tid);
+            self.process.tid = tid;
+        }
+        self.report_watchpoint(&hit);
+        self.last_watch_hit = Some(hit);
+        self.print_context();
+        self.state = DebuggerState::Interactive;
+        true
+    }
+
+    fn report_watchpoint(&mut self, hit: &WatchpointHit) {
+        let Ok(mut regs) = self.read_regs(self.process.tid) else {
+            return;
+        };
+        match hit.kind {
+            WatchKind::Execute => {
+                // instruction breakpoints fault before execution, RF lets the
+                // next resume run the instruction without triggering again
+                regs.eflags |= 1 << 16;
+                let _ = self.write_regs(self.process.tid, regs);
+                println!(
+                    "Hardware breakpoint {} at 0x{:x} ({})",
+                    hit.id,
+                    hit.addr,
+                    self.describe_location(hit.addr)
+                );
+            }
+            WatchKind::Write | WatchKind::ReadWrite if hit.old_value != hit.new_value => {
+                println!("Hardware watchpoint {}: 0x{:x}", hit.id, hit.addr);
+                println!("Old value = 0x{:x}", hit.old_value);
+                println!("New value = 0x{:x}", hit.new_value);
+            }
+            _ => {
+                println!("Hardware watchpoint {}: 0x{:x}", hit.id, hit.addr);
+                println!("Value = 0x{:x}", hit.new_value);
+            }
+        }
+        println!(
+            "Process stopped at addr: 0x{:x} ({})",
+            regs.rip,
+            self.describe_location(regs.rip)
+        );
+    }
+
+    // a command that resumes ends the list, the debugee then keeps running
+    // without going back to the prompt
+    fn run_breakpoint_commands(&mut self, commands: &[String]) {
+        let Some(runner) = self.command_runner.clone() else {
+            return;
+        };
+        for command in commands.iter().skip_while(|c| *c == "silent") {
+            if let Err(e) = runner.execute(command, self) {
+                println!("Error: {}", e);
+                break;
+            }
+            if runner.resumes(command) {
+                break;
+            }
+        }
+    }
+
+    // "Program received signal SIGSEGV" with what the kernel told us about it
+    fn report_signal(&mut self, signal: Signal) {
+        println!(
+            "\nThread {} received signal {}, {}.",
+            self.process.tid,
+            signal.as_str(),
+            signal_text(signal)
+        );
+        if let Ok(info) = self.target.siginfo(self.process.tid) {
+            let mut details = format!("si_code={}", describe_si_code(signal, info.si_code));
+            if has_fault_addr(signal) {
+                details += &format!(", fault address 0x{:x}", unsafe { info.si_addr() } as u64);
+            } else if matches!(
+                info.si_code,
+                libc::SI_USER | libc::SI_TKILL | libc::SI_QUEUE
+            ) {
+                details += &format!(", sent by pid {}", unsafe { info.si_pid() });
+            }
+            println!("{}", details);
+        }
+        if let Ok(regs) = self.read_regs(self.process.tid) {
+            println!(
+                "Process stopped at addr: 0x{:x} ({})",
+                regs.rip,
+                self.describe_location(regs.rip)
+            );
         }
     }
 }
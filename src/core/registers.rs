@@ -1,23 +1,22 @@
 use crate::core::Debugger;
 use anyhow::{bail, Result};
 use libc::user_regs_struct;
-use nix::sys::ptrace;
-use nix::sys::ptrace::*;
 
 pub trait Registers {
     fn get_registers(&self) -> Result<user_regs_struct>;
-    fn set_register(&self, reg: &str, value_str: &str) -> Result<()>;
+    fn set_register(&mut self, reg: &str, value_str: &str) -> Result<()>;
     fn get_register_value(&self, name: &str) -> Result<u64>;
 }
 
 impl Registers for Debugger {
     fn get_registers(&self) -> Result<user_regs_struct> {
-        Ok(getregs(self.process.pid)?)
+        self.read_regs(self.process.tid)
     }
-    fn set_register(&self, reg: &str, value_str: &str) -> Result<()> {
+    fn set_register(&mut self, reg: &str, value_str: &str) -> Result<()> {
+        self.ensure_live()?;
         let value = self.parse_address(value_str)?;
 
-        let mut regs = ptrace::getregs(self.process.pid)?;
+        let mut regs = self.read_regs(self.process.tid)?;
         match reg {
             "rip" => regs.rip = value,
             "rax" => regs.rax = value,
@@ -39,12 +38,12 @@ impl Registers for Debugger {
             "eflags" => regs.eflags = value,
             _ => bail!("Unknown register: {}", reg),
         }
-        ptrace::setregs(self.process.pid, regs)?;
+        self.write_regs(self.process.tid, regs)?;
         Ok(())
     }
 
     fn get_register_value(&self, name: &str) -> Result<u64> {
-        let regs = getregs(self.process.pid)?;
+        let regs = self.read_regs(self.process.tid)?;
         let value = match name {
             "rip" => Some(regs.rip),
             "rax" => Some(regs.rax),
@@ -69,3 +68,27 @@ impl Registers for Debugger {
         value.ok_or_else(|| anyhow::anyhow!("Unkown Register: {}", name))
     }
 }
+
+// general purpose registers in display order
+pub fn general_registers(regs: &user_regs_struct) -> [(&'static str, u64); 18] {
+    [
+        ("rax", regs.rax),
+        ("rbx", regs.rbx),
+        ("rcx", regs.rcx),
+        ("rdx", regs.rdx),
+        ("rsi", regs.rsi),
+        ("rdi", regs.rdi),
+        ("rbp", regs.rbp),
+        ("rsp", regs.rsp),
+        ("r8", regs.r8),
+        ("r9", regs.r9),
+        ("r10", regs.r10),
+        ("r11", regs.r11),
+        ("r12", regs.r12),
+        ("r13", regs.r13),
+        ("r14", regs.r14),
+        ("r15", regs.r15),
+        ("rip", regs.rip),
+        ("eflags", regs.eflags),
+    ]
+}
@@ -0,0 +1,727 @@
+use crate::core::corefile::parse_auxv;
+use crate::core::rsp::*;
+use crate::core::target::Target;
+use crate::core::watchpoint::{DR_CONTROL, DR_STATUS, SLOTS};
+use anyhow::{bail, Result};
+use libc::user_regs_struct;
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+
+// bytes per m/M packet, well below the usual PacketSize
+const MEMORY_CHUNK: usize = 0x400;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Action {
+    Continue(Option<Signal>),
+    Step(Option<Signal>),
+}
+
+impl Action {
+    // vCont action or the packet of the same name
+    fn code(&self) -> String {
+        match self {
+            Action::Continue(None) => "c".to_string(),
+            Action::Step(None) => "s".to_string(),
+            Action::Continue(Some(signal)) => format!("C{:02x}", to_gdb_signal(*signal)),
+            Action::Step(Some(signal)) => format!("S{:02x}", to_gdb_signal(*signal)),
+        }
+    }
+}
+
+// what made the stub report a SIGTRAP
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Signal,
+    // a software breakpoint, rip points at it
+    SwBreak,
+    HwBreak,
+    // data address of a watch, rwatch or awatch stop
+    Watch(u64),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum StopReply {
+    Status {
+        status: WaitStatus,
+        reason: StopReason,
+    },
+    Output(String),
+}
+
+// a gdbserver or qemu gdbstub spoken to over the remote serial protocol.
+// the stub is all-stop: one stop reply means every thread is halted
+pub struct RemoteTarget {
+    conn: RefCell<RspConnection>,
+    pub address: String,
+    pub pid: Pid,
+    // thread the stub's `Hg` selection points at
+    general_thread: Cell<Option<Pid>>,
+    // `G` has to send the whole register file, the fpu part is kept from `g`
+    register_files: RefCell<HashMap<Pid, Vec<u8>>>,
+    // resumes are collected and sent as one vCont by `wait`
+    pending: Vec<(Pid, Action)>,
+    stepping: HashSet<Pid>,
+    last_stop: Option<(Pid, Signal, StopReason)>,
+    // addresses inserted with Z0, None until we know if the stub has it
+    z0: Option<bool>,
+    breakpoints: HashSet<u64>,
+    // DR0-DR7 as the watchpoint code writes them, DR7 becomes Z1-Z4 packets
+    // for the whole process and DR6 is made up from the stop reply
+    debug_regs: [u64; 8],
+    // type, address and length of the inserted Z1-Z4 points
+    hw_points: HashSet<(char, u64, u64)>,
+    vcont: bool,
+    pub libraries: Vec<(String, u64)>,
+}
+
+impl fmt::Debug for RemoteTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "RemoteTarget {{ address: {}, pid: {} }}",
+            self.address, self.pid
+        )
+    }
+}
+
+impl RemoteTarget {
+    // `host:port`, `:port` for localhost, or the path of a unix socket
+    pub fn connect(address: &str) -> Result<Self> {
+        let stream: Box<dyn Stream> = if address.contains('/') {
+            Box::new(UnixStream::connect(address)?)
+        } else if let Some(port) = address.strip_prefix(':') {
+            Box::new(TcpStream::connect(("127.0.0.1", port.parse::<u16>()?))?)
+        } else {
+            let stream = TcpStream::connect(address)?;
+            stream.set_nodelay(true)?;
+            Box::new(stream)
+        };
+        Self::with_stream(stream, address)
+    }
+
+    fn with_stream(stream: Box<dyn Stream>, address: &str) -> Result<Self> {
+        let mut target = RemoteTarget {
+            conn: RefCell::new(RspConnection::new(stream)),
+            address: address.to_string(),
+            pid: Pid::from_raw(0),
+            general_thread: Cell::new(None),
+            register_files: RefCell::new(HashMap::new()),
+            pending: Vec::new(),
+            stepping: HashSet::new(),
+            last_stop: None,
+            z0: None,
+            breakpoints: HashSet::new(),
+            debug_regs: [0; 8],
+            hw_points: HashSet::new(),
+            vcont: false,
+            libraries: Vec::new(),
+        };
+        target.handshake()?;
+        Ok(target)
+    }
+
+    fn handshake(&mut self) -> Result<()> {
+        let features = self.request("qSupported:swbreak+;hwbreak+;xmlRegisters=i386")?;
+        let supports = |name: &str| features.split(';').any(|f| f == format!("{}+", name));
+        if supports("QStartNoAckMode") && self.request("QStartNoAckMode")? == "OK" {
+            self.conn.get_mut().no_ack = true;
+        }
+        let vcont = self.request("vCont?")?;
+        self.vcont = vcont.starts_with("vCont") && vcont.contains(";c") && vcont.contains(";s");
+
+        if supports("qXfer:features:read") {
+            let xml =
+                String::from_utf8_lossy(&self.read_xfer("features", "target.xml")?).into_owned();
+            if let Some(arch) = xml_element(&xml, "architecture") {
+                if !arch.contains("x86-64") {
+                    bail!("Remote target is {}, only x86-64 is supported", arch);
+                }
+            }
+        }
+
+        // the stop that made the stub wait for us
+        let reply = self.request("?")?;
+        match parse_stop_reply(&reply, Pid::from_raw(0))? {
+            StopReply::Status {
+                status: WaitStatus::Stopped(tid, signal),
+                ..
+            } => {
+                self.last_stop = Some((tid, signal, StopReason::Signal));
+                self.pid = tid;
+            }
+            other => bail!("Remote target is not stopped: {:?}", other),
+        }
+        if self.pid.as_raw() == 0 {
+            // stubs without thread info in the stop reply
+            self.pid = self.current_thread()?;
+        }
+
+        if supports("qXfer:libraries-svr4:read") {
+            let xml = String::from_utf8_lossy(&self.read_xfer("libraries-svr4", "")?).into_owned();
+            self.libraries = parse_svr4_libraries(&xml);
+        }
+        Ok(())
+    }
+
+    fn request(&self, payload: &str) -> Result<String> {
+        self.conn.borrow_mut().request_str(payload)
+    }
+
+    fn current_thread(&self) -> Result<Pid> {
+        let reply = self.request("qC")?;
+        match reply.strip_prefix("QC") {
+            Some(id) => parse_thread_id(id),
+            None => Ok(Pid::from_raw(1)),
+        }
+    }
+
+    // qXfer objects come in pieces, `m` while there is more and `l` at the end
+    fn read_xfer(&self, object: &str, annex: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        loop {
+            let packet = format!(
+                "qXfer:{}:read:{}:{:x},{:x}",
+                object,
+                annex,
+                data.len(),
+                0xfff
+            );
+            let reply = self.conn.borrow_mut().request(&packet)?;
+            match reply.split_first() {
+                Some((b'm', chunk)) => data.extend_from_slice(chunk),
+                Some((b'l', chunk)) => {
+                    data.extend_from_slice(chunk);
+                    return Ok(data);
+                }
+                _ => bail!(
+                    "Reading {} failed: {}",
+                    object,
+                    String::from_utf8_lossy(&reply)
+                ),
+            }
+        }
+    }
+
+    pub fn auxv(&self) -> Vec<(u64, u64)> {
+        self.read_xfer("auxv", "")
+            .map(|data| parse_auxv(&data))
+            .unwrap_or_default()
+    }
+
+    fn select_thread(&self, tid: Pid) -> Result<()> {
+        if self.general_thread.get() != Some(tid) {
+            check_error(&self.request(&format!("Hg{:x}", tid.as_raw()))?)?;
+            self.general_thread.set(Some(tid));
+        }
+        Ok(())
+    }
+
+    fn send_resume(&mut self) -> Result<()> {
+        let actions = std::mem::take(&mut self.pending);
+        self.stepping = actions
+            .iter()
+            .filter(|(_, a)| matches!(a, Action::Step(_)))
+            .map(|(tid, _)| *tid)
+            .collect();
+        self.register_files.get_mut().clear();
+        let conn = self.conn.get_mut();
+        if self.vcont {
+            let mut packet = "vCont".to_string();
+            for (tid, action) in &actions {
+                packet += &format!(";{}:{:x}", action.code(), tid.as_raw());
+            }
+            return conn.send_str(&packet);
+        }
+        // without vCont a step is for one thread and a continue for all
+        let (tid, action) = actions
+            .iter()
+            .find(|(_, a)| matches!(a, Action::Step(_)))
+            .unwrap_or(&actions[0]);
+        let thread = match action {
+            Action::Step(_) => format!("{:x}", tid.as_raw()),
+            Action::Continue(_) => "-1".to_string(),
+        };
+        check_error(&conn.request_str(&format!("Hc{}", thread))?)?;
+        conn.send_str(&action.code())
+    }
+}
+
+impl RemoteTarget {
+    // the enabled DR7 slots as Z packets: execute is Z1, write Z2 and
+    // read/write Z4, a read-only watch cannot be told apart from that
+    fn hw_slots(&self) -> Vec<(usize, (char, u64, u64))> {
+        let control = self.debug_regs[DR_CONTROL];
+        (0..SLOTS)
+            .filter(|i| control & (1 << (i * 2)) != 0)
+            .map(|i| {
+                let kind = match (control >> (16 + i * 4)) & 0b11 {
+                    0b00 => '1',
+                    0b01 => '2',
+                    _ => '4',
+                };
+                let len = match (control >> (18 + i * 4)) & 0b11 {
+                    0b00 => 1,
+                    0b01 => 2,
+                    0b10 => 8,
+                    _ => 4,
+                };
+                (i, (kind, self.debug_regs[i], len))
+            })
+            .collect()
+    }
+
+    fn sync_hw_points(&mut self) -> Result<()> {
+        let wanted: HashSet<_> = self.hw_slots().into_iter().map(|(_, p)| p).collect();
+        let stale: Vec<_> = self.hw_points.difference(&wanted).copied().collect();
+        for (kind, addr, len) in stale {
+            check_error(&self.request(&format!("z{},{:x},{:x}", kind, addr, len))?)?;
+            self.hw_points.remove(&(kind, addr, len));
+        }
+        let new: Vec<_> = wanted.difference(&self.hw_points).copied().collect();
+        for (kind, addr, len) in new {
+            match self
+                .request(&format!("Z{},{:x},{:x}", kind, addr, len))?
+                .as_str()
+            {
+                "OK" => {}
+                "" => bail!("The remote stub does not support Z{} packets", kind),
+                other => bail!("Failed to insert Z{} at 0x{:x}: {}", kind, addr, other),
+            }
+            self.hw_points.insert((kind, addr, len));
+        }
+        Ok(())
+    }
+
+    // DR6 bits of the slots that match a hwbreak or watch stop
+    fn debug_status(&self, tid: Pid, reason: StopReason) -> u64 {
+        let rip = match reason {
+            StopReason::HwBreak => self.read_regs(tid).map_or(0, |regs| regs.rip),
+            StopReason::Watch(_) => 0,
+            _ => return 0,
+        };
+        self.hw_slots()
+            .into_iter()
+            .filter(|(_, (kind, addr, len))| match reason {
+                StopReason::Watch(at) => *kind != '1' && (*addr..addr + len).contains(&at),
+                _ => *kind == '1' && *addr == rip,
+            })
+            .map(|(i, _)| i)
+            .fold(0, |status, i| status | 1 << i)
+    }
+}
+
+impl Target for RemoteTarget {
+    fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        let mut done = 0;
+        while done < buf.len() {
+            let at = addr + done as u64;
+            let len = MEMORY_CHUNK.min(buf.len() - done);
+            let reply = self.request(&format!("m{:x},{:x}", at, len))?;
+            if reply.is_empty() || reply.starts_with('E') {
+                bail!("Cannot access memory at 0x{:x}", at);
+            }
+            let bytes = from_hex(&reply)?;
+            let len = bytes.len().min(len);
+            buf[done..done + len].copy_from_slice(&bytes[..len]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        for (i, chunk) in data.chunks(MEMORY_CHUNK).enumerate() {
+            let at = addr + (i * MEMORY_CHUNK) as u64;
+            let reply = self.request(&format!("M{:x},{:x}:{}", at, chunk.len(), to_hex(chunk)))?;
+            if reply != "OK" {
+                bail!("Cannot write memory at 0x{:x}", at);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_regs(&self, tid: Pid) -> Result<user_regs_struct> {
+        self.select_thread(tid)?;
+        let reply = self.request("g")?;
+        check_error(&reply)?;
+        let bytes = from_hex(&reply)?;
+        let regs = regs_from_bytes(&bytes)?;
+        self.register_files.borrow_mut().insert(tid, bytes);
+        Ok(regs)
+    }
+
+    fn write_regs(&mut self, tid: Pid, regs: user_regs_struct) -> Result<()> {
+        if !self.register_files.get_mut().contains_key(&tid) {
+            self.read_regs(tid)?;
+        }
+        self.select_thread(tid)?;
+        let mut bytes = self
+            .register_files
+            .get_mut()
+            .remove(&tid)
+            .unwrap_or_default();
+        regs_to_bytes(&regs, &mut bytes);
+        let reply = self.request(&format!("G{}", to_hex(&bytes)))?;
+        if reply != "OK" {
+            bail!("Failed to write registers: {}", reply);
+        }
+        self.register_files.get_mut().insert(tid, bytes);
+        Ok(())
+    }
+
+    fn resume(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()> {
+        self.pending.push((tid, Action::Continue(signal)));
+        Ok(())
+    }
+
+    fn step(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()> {
+        self.pending.push((tid, Action::Step(signal)));
+        Ok(())
+    }
+
+    fn wait(&mut self, _tid: Option<Pid>) -> Result<WaitStatus> {
+        if !self.pending.is_empty() {
+            self.send_resume()?;
+        }
+        loop {
+            let reply = self.conn.get_mut().receive()?;
+            let reply = String::from_utf8_lossy(&reply).into_owned();
+            match parse_stop_reply(&reply, self.pid)? {
+                StopReply::Output(text) => print!("{}", text),
+                StopReply::Status { status, reason } => {
+                    self.general_thread.set(None);
+                    self.debug_regs[DR_STATUS] = 0;
+                    if let WaitStatus::Stopped(tid, signal) = status {
+                        self.last_stop = Some((tid, signal, reason));
+                        self.debug_regs[DR_STATUS] = self.debug_status(tid, reason);
+                    }
+                    return Ok(status);
+                }
+            }
+        }
+    }
+
+    // stubs that keep rip at the breakpoint report swbreak, gdbserver without
+    // it and our own int3s leave rip behind the instruction
+    fn breakpoint_address(&self, tid: Pid) -> Option<u64> {
+        let (stopped, signal, reason) = self.last_stop?;
+        if stopped != tid
+            || signal != Signal::SIGTRAP
+            || self.stepping.contains(&tid)
+            || matches!(reason, StopReason::HwBreak | StopReason::Watch(_))
+        {
+            return None;
+        }
+        let rip = self.read_regs(tid).ok()?.rip;
+        if reason == StopReason::SwBreak || self.breakpoints.contains(&rip) {
+            Some(rip)
+        } else {
+            Some(rip - 1)
+        }
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> Result<bool> {
+        if self.z0 == Some(false) {
+            return Ok(false);
+        }
+        match self.request(&format!("Z0,{:x},1", addr))?.as_str() {
+            "OK" => {
+                self.z0 = Some(true);
+                self.breakpoints.insert(addr);
+                Ok(true)
+            }
+            "" => {
+                self.z0 = Some(false);
+                Ok(false)
+            }
+            other => bail!("Failed to insert breakpoint at 0x{:x}: {}", addr, other),
+        }
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64) -> Result<bool> {
+        if !self.breakpoints.remove(&addr) {
+            return Ok(false);
+        }
+        check_error(&self.request(&format!("z0,{:x},1", addr))?)?;
+        Ok(true)
+    }
+
+    // DR6 only belongs to the thread of the last stop
+    fn read_debug_reg(&self, tid: Pid, index: usize) -> Result<u64> {
+        match self.last_stop {
+            Some((stopped, ..)) if index == DR_STATUS && stopped != tid => Ok(0),
+            _ => Ok(self.debug_regs[index]),
+        }
+    }
+
+    fn write_debug_reg(&mut self, _tid: Pid, index: usize, value: u64) -> Result<()> {
+        self.debug_regs[index] = value;
+        if index == DR_CONTROL {
+            self.sync_hw_points()?;
+        }
+        Ok(())
+    }
+
+    fn thread_list(&mut self) -> Option<Vec<Pid>> {
+        let mut tids = Vec::new();
+        let mut reply = self.request("qfThreadInfo").ok()?;
+        while let Some(ids) = reply.strip_prefix('m') {
+            tids.extend(ids.split(',').filter_map(|id| parse_thread_id(id).ok()));
+            reply = self.request("qsThreadInfo").ok()?;
+        }
+        if tids.is_empty() {
+            tids.push(self.pid);
+        }
+        Some(tids)
+    }
+
+    fn is_remote(&self) -> bool {
+        true
+    }
+}
+
+// "1a2" or "p1a2.1a3" in multiprocess mode
+fn parse_thread_id(id: &str) -> Result<Pid> {
+    let id = id.rsplit('.').next().unwrap_or(id).trim_start_matches('p');
+    Ok(Pid::from_raw(i32::from_str_radix(id, 16)?))
+}
+
+pub fn parse_stop_reply(reply: &str, pid: Pid) -> Result<StopReply> {
+    let number = |hex: &str| u8::from_str_radix(hex.get(..2).unwrap_or("0"), 16).unwrap_or(0);
+    let signal = |hex: &str| from_gdb_signal(number(hex)).unwrap_or(Signal::SIGTRAP);
+    let Some(kind) = reply.chars().next() else {
+        bail!("Empty stop reply");
+    };
+    let Some(rest) = reply.get(1..) else {
+        bail!("Unexpected stop reply: {}", reply);
+    };
+    Ok(match kind {
+        'S' => StopReply::Status {
+            status: WaitStatus::Stopped(pid, signal(rest)),
+            reason: StopReason::Signal,
+        },
+        'T' => {
+            let mut tid = pid;
+            let mut reason = StopReason::Signal;
+            for pair in rest.get(2..).unwrap_or("").split(';') {
+                match pair.split_once(':') {
+                    Some(("thread", id)) => tid = parse_thread_id(id)?,
+                    Some(("swbreak", _)) => reason = StopReason::SwBreak,
+                    Some(("hwbreak", _)) => reason = StopReason::HwBreak,
+                    Some(("watch" | "rwatch" | "awatch", addr)) => {
+                        reason = StopReason::Watch(u64::from_str_radix(addr, 16)?)
+                    }
+                    _ => {}
+                }
+            }
+            StopReply::Status {
+                status: WaitStatus::Stopped(tid, signal(rest)),
+                reason,
+            }
+        }
+        'W' => StopReply::Status {
+            status: WaitStatus::Exited(pid, number(rest) as i32),
+            reason: StopReason::Signal,
+        },
+        'X' => StopReply::Status {
+            status: WaitStatus::Signaled(pid, signal(rest), false),
+            reason: StopReason::Signal,
+        },
+        'O' => StopReply::Output(String::from_utf8_lossy(&from_hex(rest)?).into_owned()),
+        _ => bail!("Unexpected stop reply: {}", reply),
+    })
+}
+
+// text of the first <name>...</name>
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = xml[start..].find(&format!("</{}>", name))? + start;
+    Some(&xml[start..end])
+}
+
+// value of `name="..."` among the attributes of an element
+fn xml_attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    let pattern = format!("{}=\"", name);
+    let (at, _) = element
+        .match_indices(&pattern)
+        .find(|(i, _)| *i == 0 || element.as_bytes()[i - 1].is_ascii_whitespace())?;
+    let start = at + pattern.len();
+    let end = element[start..].find('"')? + start;
+    Some(&element[start..end])
+}
+
+// name and load bias of every <library> in a qXfer:libraries-svr4 document
+pub fn parse_svr4_libraries(xml: &str) -> Vec<(String, u64)> {
+    xml.split("<library ")
+        .skip(1)
+        .filter_map(|element| {
+            let name = xml_attribute(element, "name")?;
+            let l_addr = xml_attribute(element, "l_addr")?;
+            let l_addr = u64::from_str_radix(l_addr.trim_start_matches("0x"), 16).ok()?;
+            Some((name.to_string(), l_addr))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::breakpoint::Breakpoint;
+    use crate::core::watchpoint::{WatchKind, Watchpoints};
+
+    // answers like gdbserver stopped at the entry of a single threaded program
+    fn fake_stub(mut conn: RspConnection) {
+        let mut memory = vec![0x55u8, 0x48, 0x89, 0xe5];
+        let mut regs = vec![0u8; GPR_BYTES];
+        regs[128..136].copy_from_slice(&0x401000u64.to_le_bytes());
+        while let Ok(packet) = conn.receive() {
+            let packet = String::from_utf8(packet).unwrap();
+            let reply = match packet.as_str() {
+                p if p.starts_with("qSupported") => {
+                    "PacketSize=4000;QStartNoAckMode+;qXfer:features:read+".to_string()
+                }
+                "QStartNoAckMode" => {
+                    conn.send_str("OK").unwrap();
+                    conn.no_ack = true;
+                    continue;
+                }
+                "vCont?" => "vCont;c;C;s;S".to_string(),
+                p if p.starts_with("qXfer:features:read:target.xml:") => {
+                    "l<target><architecture>i386:x86-64</architecture></target>".to_string()
+                }
+                "?" => "T05thread:2a;".to_string(),
+                "qfThreadInfo" => "m2a,2b".to_string(),
+                "qsThreadInfo" => "l".to_string(),
+                "g" => to_hex(&regs),
+                p if p.starts_with('G') => {
+                    regs = from_hex(&p[1..]).unwrap();
+                    "OK".to_string()
+                }
+                p if p.starts_with('H') || p.starts_with("Z0") || p.starts_with("z0") => {
+                    "OK".to_string()
+                }
+                "m401000,4" => to_hex(&memory),
+                "m401001,1" => to_hex(&memory[1..2]),
+                p if p.starts_with("M401001,1:") => {
+                    memory[1] = from_hex(&p[10..]).unwrap()[0];
+                    "OK".to_string()
+                }
+                "vCont;c:2a;c:2b" => "T05thread:2b;swbreak:;".to_string(),
+                "Z2,401000,4" | "z2,401000,4" => "OK".to_string(),
+                "vCont;c:2a" => "T05thread:2a;watch:401000;".to_string(),
+                _ => String::new(),
+            };
+            conn.send_str(&reply).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_remote_target() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let stub = std::thread::spawn(move || fake_stub(RspConnection::new(Box::new(b))));
+        let mut target = RemoteTarget::with_stream(Box::new(a), "fake").unwrap();
+        assert_eq!(target.pid, Pid::from_raw(0x2a));
+        assert_eq!(
+            target.thread_list().unwrap(),
+            vec![Pid::from_raw(0x2a), Pid::from_raw(0x2b)]
+        );
+
+        // Z0 breakpoints leave memory alone
+        let mut breakpoint = Breakpoint::new();
+        breakpoint.set_breakpoint(0x401001, &mut target).unwrap();
+        let mut code = [0u8; 4];
+        target.read_memory(0x401000, &mut code).unwrap();
+        assert_eq!(code, [0x55, 0x48, 0x89, 0xe5]);
+
+        let tid = Pid::from_raw(0x2b);
+        let mut regs = target.read_regs(tid).unwrap();
+        assert_eq!(regs.rip, 0x401000);
+        regs.rip = 0x401001;
+        target.write_regs(tid, regs).unwrap();
+
+        target.resume(Pid::from_raw(0x2a), None).unwrap();
+        target.resume(tid, None).unwrap();
+        assert_eq!(
+            target.wait(None).unwrap(),
+            WaitStatus::Stopped(tid, Signal::SIGTRAP)
+        );
+        assert_eq!(target.breakpoint_address(tid), Some(0x401001));
+
+        // debug registers become Z2 packets, the watch stop sets DR6
+        let leader = Pid::from_raw(0x2a);
+        let mut watchpoints = Watchpoints::new();
+        watchpoints
+            .set_watchpoint(
+                3,
+                0x401000,
+                4,
+                WatchKind::Write,
+                &mut target,
+                &[leader, tid],
+            )
+            .unwrap();
+        target.resume(leader, None).unwrap();
+        target.wait(None).unwrap();
+        assert_eq!(target.breakpoint_address(leader), None);
+        assert_eq!(
+            watchpoints
+                .check_hit(&mut target, tid)
+                .unwrap()
+                .map(|h| h.id),
+            None
+        );
+        let hit = watchpoints.check_hit(&mut target, leader).unwrap();
+        assert_eq!(hit.map(|h| h.id), Some(3));
+        watchpoints.remove(3, &mut target, &[leader, tid]).unwrap();
+        assert!(target.hw_points.is_empty());
+
+        drop(target);
+        stub.join().unwrap();
+    }
+
+    #[test]
+    fn test_stop_replies() {
+        let pid = Pid::from_raw(100);
+        assert_eq!(
+            parse_stop_reply("T05thread:65;swbreak:;06:0000000000000000;", pid).unwrap(),
+            StopReply::Status {
+                status: WaitStatus::Stopped(Pid::from_raw(0x65), Signal::SIGTRAP),
+                reason: StopReason::SwBreak
+            }
+        );
+        assert_eq!(
+            parse_stop_reply("S0a", pid).unwrap(),
+            StopReply::Status {
+                status: WaitStatus::Stopped(pid, Signal::SIGBUS),
+                reason: StopReason::Signal
+            }
+        );
+        assert_eq!(
+            parse_stop_reply("W01;process:64", pid).unwrap(),
+            StopReply::Status {
+                status: WaitStatus::Exited(pid, 1),
+                reason: StopReason::Signal
+            }
+        );
+        assert_eq!(
+            parse_stop_reply("O68690a", pid).unwrap(),
+            StopReply::Output("hi\n".to_string())
+        );
+        assert!(parse_stop_reply("é05", pid).is_err());
+        assert_eq!(parse_thread_id("p64.65").unwrap(), Pid::from_raw(0x65));
+
+        let xml = r#"<library-list-svr4 version="1.0" main-lm="0x7ffff7ffe2e0"><library name="/lib/x86_64-linux-gnu/libc.so.6" lm="0x7ffff7fc1000" l_addr="0x7ffff7dd5000" l_ld="0x7ffff7fa1000"/></library-list-svr4>"#;
+        assert_eq!(
+            parse_svr4_libraries(xml),
+            vec![(
+                "/lib/x86_64-linux-gnu/libc.so.6".to_string(),
+                0x7ffff7dd5000
+            )]
+        );
+    }
+}
@@ -0,0 +1,370 @@
+use anyhow::{bail, Result};
+use libc::user_regs_struct;
+use nix::sys::signal::Signal;
+use std::io::{Read, Write};
+
+// anything a packet connection can run over, tcp or unix sockets
+pub trait Stream: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Stream for T {}
+
+// size of the general registers in a `g` packet: 16 gprs, rip, eflags and
+// the six segment registers of 4 bytes
+pub const GPR_BYTES: usize = 16 * 8 + 8 + 4 + 6 * 4;
+// orig_rax, fs_base and gs_base follow the fpu and sse registers
+const ORIG_RAX_OFFSET: usize = GPR_BYTES + 8 * 10 + 8 * 4 + 16 * 16 + 4;
+// the whole `g` packet of an amd64 linux target
+pub const REGISTER_FILE_BYTES: usize = ORIG_RAX_OFFSET + 24;
+
+// $<payload>#<checksum> framing with acks, both sides of the protocol
+pub struct RspConnection {
+    stream: Box<dyn Stream>,
+    pub no_ack: bool,
+}
+
+impl RspConnection {
+    pub fn new(stream: Box<dyn Stream>) -> Self {
+        RspConnection {
+            stream,
+            no_ack: false,
+        }
+    }
+
+    pub fn send(&mut self, payload: &[u8]) -> Result<()> {
+        let mut packet = Vec::with_capacity(payload.len() + 4);
+        packet.push(b'$');
+        for byte in payload {
+            // binary data has the framing characters escaped
+            if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+                packet.push(b'}');
+                packet.push(byte ^ 0x20);
+            } else {
+                packet.push(*byte);
+            }
+        }
+        let checksum = packet[1..].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        packet.extend_from_slice(format!("#{:02x}", checksum).as_bytes());
+
+        loop {
+            self.stream.write_all(&packet)?;
+            self.stream.flush()?;
+            if self.no_ack {
+                return Ok(());
+            }
+            match self.read_byte()? {
+                b'+' => return Ok(()),
+                b'-' => continue,
+                other => bail!("Expected an ack, got {:?}", other as char),
+            }
+        }
+    }
+
+    pub fn send_str(&mut self, payload: &str) -> Result<()> {
+        self.send(payload.as_bytes())
+    }
+
+    // the next packet, or [0x03] when the other side sent an interrupt
+    pub fn receive(&mut self) -> Result<Vec<u8>> {
+        loop {
+            match self.read_byte()? {
+                b'$' => {}
+                0x03 => return Ok(vec![0x03]),
+                // stray acks
+                _ => continue,
+            }
+            let mut raw = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => raw.push(byte),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = u8::from_str_radix(std::str::from_utf8(&checksum)?, 16)?;
+            let actual = raw.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+            if !self.no_ack {
+                if expected != actual {
+                    self.stream.write_all(b"-")?;
+                    continue;
+                }
+                self.stream.write_all(b"+")?;
+            }
+            return Ok(unescape(&raw));
+        }
+    }
+
+    // sends a request and returns the reply
+    pub fn request(&mut self, payload: &str) -> Result<Vec<u8>> {
+        self.send_str(payload)?;
+        self.receive()
+    }
+
+    pub fn request_str(&mut self, payload: &str) -> Result<String> {
+        let reply = self.request(payload)?;
+        Ok(String::from_utf8_lossy(&reply).into_owned())
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut byte = [0u8];
+        if self.stream.read(&mut byte)? == 0 {
+            bail!("Remote connection closed");
+        }
+        Ok(byte[0])
+    }
+}
+
+// `}` escapes and `*` run length encoding
+fn unescape(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut bytes = raw.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => {
+                if let Some(next) = bytes.next() {
+                    out.push(next ^ 0x20);
+                }
+            }
+            b'*' => {
+                if let (Some(&count), Some(&last)) = (bytes.next(), out.last()) {
+                    let count = count.saturating_sub(29) as usize;
+                    out.extend(std::iter::repeat_n(last, count));
+                }
+            }
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>> {
+    if !hex.is_ascii() {
+        bail!("Invalid hex string");
+    }
+    if !hex.len().is_multiple_of(2) {
+        bail!("Odd length hex string");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            // registers the stub can not read are sent as xx
+            match &hex[i..i + 2] {
+                "xx" => Ok(0),
+                byte => u8::from_str_radix(byte, 16).map_err(|e| anyhow::anyhow!("{}", e)),
+            }
+        })
+        .collect()
+}
+
+// "E01" replies to requests that failed
+pub fn check_error(reply: &str) -> Result<()> {
+    if reply.len() == 3 && reply.starts_with('E') {
+        bail!("Remote error {}", &reply[1..]);
+    }
+    Ok(())
+}
+
+// gdb's own signal numbers differ from linux above SIGTERM
+const GDB_SIGNALS: [(Signal, u8); 30] = [
+    (Signal::SIGHUP, 1),
+    (Signal::SIGINT, 2),
+    (Signal::SIGQUIT, 3),
+    (Signal::SIGILL, 4),
+    (Signal::SIGTRAP, 5),
+    (Signal::SIGABRT, 6),
+    (Signal::SIGFPE, 8),
+    (Signal::SIGKILL, 9),
+    (Signal::SIGBUS, 10),
+    (Signal::SIGSEGV, 11),
+    (Signal::SIGSYS, 12),
+    (Signal::SIGPIPE, 13),
+    (Signal::SIGALRM, 14),
+    (Signal::SIGTERM, 15),
+    (Signal::SIGURG, 16),
+    (Signal::SIGSTOP, 17),
+    (Signal::SIGTSTP, 18),
+    (Signal::SIGCONT, 19),
+    (Signal::SIGCHLD, 20),
+    (Signal::SIGTTIN, 21),
+    (Signal::SIGTTOU, 22),
+    (Signal::SIGIO, 23),
+    (Signal::SIGXCPU, 24),
+    (Signal::SIGXFSZ, 25),
+    (Signal::SIGVTALRM, 26),
+    (Signal::SIGPROF, 27),
+    (Signal::SIGWINCH, 28),
+    (Signal::SIGUSR1, 30),
+    (Signal::SIGUSR2, 31),
+    (Signal::SIGPWR, 32),
+];
+
+pub fn to_gdb_signal(signal: Signal) -> u8 {
+    GDB_SIGNALS
+        .iter()
+        .find(|(s, _)| *s == signal)
+        .map_or(0, |(_, n)| *n)
+}
+
+pub fn from_gdb_signal(number: u8) -> Option<Signal> {
+    GDB_SIGNALS
+        .iter()
+        .find(|(_, n)| *n == number)
+        .map(|(s, _)| *s)
+}
+
+// the amd64 `g` packet layout, little endian
+pub fn regs_from_bytes(bytes: &[u8]) -> Result<user_regs_struct> {
+    if bytes.len() < GPR_BYTES {
+        bail!("Short register packet of {} bytes", bytes.len());
+    }
+    let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+    let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap()) as u64;
+    let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+    regs.rax = u64_at(0);
+    regs.rbx = u64_at(8);
+    regs.rcx = u64_at(16);
+    regs.rdx = u64_at(24);
+    regs.rsi = u64_at(32);
+    regs.rdi = u64_at(40);
+    regs.rbp = u64_at(48);
+    regs.rsp = u64_at(56);
+    regs.r8 = u64_at(64);
+    regs.r9 = u64_at(72);
+    regs.r10 = u64_at(80);
+    regs.r11 = u64_at(88);
+    regs.r12 = u64_at(96);
+    regs.r13 = u64_at(104);
+    regs.r14 = u64_at(112);
+    regs.r15 = u64_at(120);
+    regs.rip = u64_at(128);
+    regs.eflags = u32_at(136);
+    regs.cs = u32_at(140);
+    regs.ss = u32_at(144);
+    regs.ds = u32_at(148);
+    regs.es = u32_at(152);
+    regs.fs = u32_at(156);
+    regs.gs = u32_at(160);
+    if bytes.len() >= ORIG_RAX_OFFSET + 24 {
+        regs.orig_rax = u64_at(ORIG_RAX_OFFSET);
+        regs.fs_base = u64_at(ORIG_RAX_OFFSET + 8);
+        regs.gs_base = u64_at(ORIG_RAX_OFFSET + 16);
+    }
+    Ok(regs)
+}
+
+// writes the general registers into `bytes`, the rest of the register file
+// is left as it is
+pub fn regs_to_bytes(regs: &user_regs_struct, bytes: &mut Vec<u8>) {
+    if bytes.len() < GPR_BYTES {
+        bytes.resize(GPR_BYTES, 0);
+    }
+    let gprs = [
+        regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp, regs.r8,
+        regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+    ];
+    for (i, value) in gprs.iter().enumerate() {
+        bytes[i * 8..i * 8 + 8].copy_from_slice(&value.to_le_bytes());
+    }
+    let segments = [
+        regs.eflags,
+        regs.cs,
+        regs.ss,
+        regs.ds,
+        regs.es,
+        regs.fs,
+        regs.gs,
+    ];
+    for (i, value) in segments.iter().enumerate() {
+        let at = 136 + i * 4;
+        bytes[at..at + 4].copy_from_slice(&(*value as u32).to_le_bytes());
+    }
+    if bytes.len() >= ORIG_RAX_OFFSET + 24 {
+        for (i, value) in [regs.orig_rax, regs.fs_base, regs.gs_base]
+            .iter()
+            .enumerate()
+        {
+            let at = ORIG_RAX_OFFSET + i * 8;
+            bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+// st0-7, the x87 control registers, xmm0-15 and mxcsr from an fxsave area
+pub fn fxsave_to_bytes(fxsave: &[u8], bytes: &mut [u8]) {
+    let u16_at = |i: usize| u16::from_le_bytes([fxsave[i], fxsave[i + 1]]) as u32;
+    let u64_at = |i: usize| u64::from_le_bytes(fxsave[i..i + 8].try_into().unwrap());
+    for i in 0..8 {
+        let at = GPR_BYTES + i * 10;
+        bytes[at..at + 10].copy_from_slice(&fxsave[32 + i * 16..32 + i * 16 + 10]);
+    }
+    // fxsave keeps one bit per register, gdb wants the full tag word
+    let tags = (0..8)
+        .filter(|i| fxsave[4] & (1 << i) == 0)
+        .fold(0u32, |tags, i| tags | (0b11 << (i * 2)));
+    let (rip, rdp) = (u64_at(8), u64_at(16));
+    let control = [
+        u16_at(0),
+        u16_at(2),
+        tags,
+        (rip >> 32) as u32,
+        rip as u32,
+        (rdp >> 32) as u32,
+        rdp as u32,
+        u16_at(6),
+    ];
+    for (i, value) in control.iter().enumerate() {
+        let at = GPR_BYTES + 80 + i * 4;
+        bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+    }
+    let xmm = GPR_BYTES + 80 + 32;
+    bytes[xmm..xmm + 256].copy_from_slice(&fxsave[160..416]);
+    bytes[xmm + 256..xmm + 260].copy_from_slice(&fxsave[24..28]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    #[test]
+    fn test_packets() {
+        let (a, b) = UnixStream::pair().unwrap();
+        let mut client = RspConnection::new(Box::new(a));
+        let mut server = RspConnection::new(Box::new(b));
+        let handle = std::thread::spawn(move || {
+            let request = server.receive().unwrap();
+            server.send(&request).unwrap();
+        });
+        let reply = client.request("m1000,4#}").unwrap();
+        handle.join().unwrap();
+        assert_eq!(reply, b"m1000,4#}");
+
+        assert_eq!(unescape(b"0* "), b"0000");
+        assert_eq!(from_hex("41xx").unwrap(), vec![0x41, 0]);
+        assert!(from_hex("4é").is_err());
+        assert_eq!(to_gdb_signal(Signal::SIGBUS), 10);
+        assert_eq!(from_gdb_signal(30), Some(Signal::SIGUSR1));
+    }
+
+    #[test]
+    fn test_register_layout() {
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x401000;
+        regs.r15 = 15;
+        regs.eflags = 0x246;
+        regs.fs_base = 0x7f00;
+        let mut bytes = vec![0u8; ORIG_RAX_OFFSET + 24];
+        regs_to_bytes(&regs, &mut bytes);
+        assert_eq!(bytes[128], 0x00);
+        assert_eq!(bytes[129], 0x10);
+        let parsed = regs_from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.rip, 0x401000);
+        assert_eq!(parsed.r15, 15);
+        assert_eq!(parsed.eflags, 0x246);
+        assert_eq!(parsed.fs_base, 0x7f00);
+    }
+}
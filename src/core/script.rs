@@ -0,0 +1,321 @@
+use crate::core::breakpoint_helpers::BreakpointHelper;
+use crate::core::process_control::ProcessControl;
+use crate::core::registers::Registers;
+use crate::core::stepping::Stepping;
+use crate::core::*;
+use anyhow::{anyhow, bail, Result};
+use rhai::{Blob, Dynamic, Engine, EvalAltResult, FnPtr, Scope, AST, INT};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+use std::ptr;
+use std::rc::Rc;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// largest blob `read()` hands out
+const MAX_READ: INT = 1 << 24;
+
+thread_local! {
+    // the debugger the running script works on, see `enter`
+    static CURRENT: Cell<*mut Debugger> = const { Cell::new(ptr::null_mut()) };
+}
+
+// makes `debugger` the target of the script functions until dropped, the
+// previous one is restored for callbacks that run inside a script's `cont()`
+struct Current(*mut Debugger);
+
+fn enter(debugger: &mut Debugger) -> Current {
+    Current(CURRENT.replace(debugger))
+}
+
+impl Drop for Current {
+    fn drop(&mut self) {
+        CURRENT.set(self.0);
+    }
+}
+
+fn with_debugger<T>(f: impl FnOnce(&mut Debugger) -> Result<T>) -> ScriptResult<T> {
+    let debugger = CURRENT.get();
+    if debugger.is_null() {
+        return Err("no debugger attached to this script".into());
+    }
+    // SAFETY: the pointer comes from the `&mut Debugger` of `eval_script` or
+    // `run_breakpoint_callback`, which do not touch it until the engine
+    // returns, and nested calls go through a reborrow of this reference
+    f(unsafe { &mut *debugger }).map_err(|e| e.to_string().into())
+}
+
+// `cont()`, `step()`, ... wait for the stop, false once the program is gone
+fn resume(f: impl FnOnce(&mut Debugger) -> Result<()>) -> ScriptResult<bool> {
+    with_debugger(|d| {
+        if d.scripts.in_callback {
+            bail!("A breakpoint callback cannot resume the program, return false instead");
+        }
+        f(d)?;
+        d.wait_for_stop();
+        Ok(!d.process.threads.is_empty())
+    })
+}
+
+fn set_breakpoint(location: &str, callback: Option<FnPtr>) -> ScriptResult<INT> {
+    with_debugger(|d| {
+        let ids = d.set_breakpoint_by_input(location)?;
+        for (id, addr) in &ids {
+            println!("Breakpoint {} at 0x{:x}", id, addr);
+            if let (Some(callback), Some(ast)) = (&callback, &d.scripts.ast) {
+                d.scripts
+                    .callbacks
+                    .insert(*id, (ast.clone(), callback.clone()));
+            }
+        }
+        Ok(ids.first().map_or(-1, |(id, _)| *id as INT))
+    })
+}
+
+fn read_bytes(addr: INT, len: INT) -> ScriptResult<Blob> {
+    if !(0..=MAX_READ).contains(&len) {
+        return Err(format!("read length {} is not between 0 and {}", len, MAX_READ).into());
+    }
+    with_debugger(|d| {
+        let mut buf = vec![0u8; len as usize];
+        d.read_memory(addr as u64, &mut buf)?;
+        Ok(buf)
+    })
+}
+
+fn read_int(addr: INT, size: usize) -> ScriptResult<INT> {
+    let bytes = read_bytes(addr, size as INT)?;
+    let mut word = [0u8; 8];
+    word[..size].copy_from_slice(&bytes);
+    Ok(u64::from_le_bytes(word) as INT)
+}
+
+fn write_int(addr: INT, value: INT, size: usize) -> ScriptResult<()> {
+    with_debugger(|d| d.write_memory(addr as u64, &value.to_le_bytes()[..size]))
+}
+
+fn build_engine() -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .register_fn("breakpoint", |location: &str| {
+            set_breakpoint(location, None)
+        })
+        .register_fn("breakpoint", |location: &str, callback: FnPtr| {
+            set_breakpoint(location, Some(callback))
+        })
+        .register_fn("delete", |id: INT| {
+            with_debugger(|d| {
+                d.scripts.callbacks.remove(&(id as usize));
+                d.breakpoint.remove_by_id(id as usize, d.target.as_mut())
+            })
+        })
+        .register_fn("cont", || resume(|d| d.cont()))
+        .register_fn("step", || resume(|d| d.step_line(false)))
+        .register_fn("next", || resume(|d| d.step_line(true)))
+        .register_fn("stepi", || resume(|d| d.single_step()))
+        .register_fn("nexti", || resume(|d| d.step_over()))
+        .register_fn("finish", || resume(|d| d.finish()))
+        .register_fn("reg", |name: &str| {
+            with_debugger(|d| Ok(d.get_register_value(name)? as INT))
+        })
+        .register_fn("set_reg", |name: &str, value: INT| {
+            with_debugger(|d| d.set_register(name, &format!("{:#x}", value as u64)))
+        })
+        .register_fn("read", read_bytes)
+        .register_fn("write", |addr: INT, bytes: Blob| {
+            with_debugger(|d| d.write_memory(addr as u64, &bytes))
+        })
+        .register_fn("read_u8", |addr: INT| read_int(addr, 1))
+        .register_fn("read_u16", |addr: INT| read_int(addr, 2))
+        .register_fn("read_u32", |addr: INT| read_int(addr, 4))
+        .register_fn("read_u64", |addr: INT| read_int(addr, 8))
+        .register_fn("write_u8", |addr: INT, value: INT| {
+            write_int(addr, value, 1)
+        })
+        .register_fn("write_u16", |addr: INT, value: INT| {
+            write_int(addr, value, 2)
+        })
+        .register_fn("write_u32", |addr: INT, value: INT| {
+            write_int(addr, value, 4)
+        })
+        .register_fn("write_u64", |addr: INT, value: INT| {
+            write_int(addr, value, 8)
+        })
+        .register_fn("evaluate", |expr: &str| {
+            with_debugger(|d| Ok(d.parse_address(expr)? as INT))
+        })
+        .register_fn("location", |addr: INT| {
+            with_debugger(|d| Ok(d.describe_location(addr as u64)))
+        })
+        .register_fn("pid", || {
+            with_debugger(|d| Ok(d.process.pid.as_raw() as INT))
+        })
+        .register_fn("tid", || {
+            with_debugger(|d| Ok(d.process.tid.as_raw() as INT))
+        })
+        .register_fn("cmd", |line: &str| {
+            with_debugger(|d| {
+                let Some(runner) = d.command_runner.clone() else {
+                    bail!("No debugger commands in this frontend");
+                };
+                if d.scripts.in_callback && runner.resumes(line) {
+                    bail!("A breakpoint callback cannot resume the program, return false instead");
+                }
+                runner.execute(line, d)?;
+                if !d.scripts.in_callback {
+                    d.wait_for_stop();
+                }
+                Ok(())
+            })
+        });
+    engine
+}
+
+/// Rhai scripts driving the debugger and the breakpoint callbacks they set.
+#[derive(Default)]
+pub struct Scripts {
+    engine: Option<Rc<Engine>>,
+    // the script that is running, callbacks keep it for their closures
+    ast: Option<Rc<AST>>,
+    callbacks: HashMap<usize, (Rc<AST>, FnPtr)>,
+    // resuming from a callback would re-enter resume_and_wait
+    in_callback: bool,
+}
+
+impl Scripts {
+    fn engine(&mut self) -> Rc<Engine> {
+        self.engine
+            .get_or_insert_with(|| Rc::new(build_engine()))
+            .clone()
+    }
+}
+
+impl fmt::Debug for Scripts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scripts")
+            .field("callbacks", &self.callbacks.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Variables and functions that outlive a single statement, e.g. of the
+/// interactive `script` mode.
+#[derive(Default)]
+pub struct ScriptSession {
+    scope: Scope<'static>,
+    functions: AST,
+}
+
+pub trait Scripting {
+    /// Compiles and runs `source` in `session` and returns its value.
+    fn eval_script(&mut self, session: &mut ScriptSession, source: &str) -> Result<Dynamic>;
+    /// Runs the callback of breakpoint `id`, `None` if it has none, else
+    /// whether the program should stop.
+    fn run_breakpoint_callback(&mut self, id: usize) -> Option<bool>;
+}
+
+impl Scripting for Debugger {
+    fn eval_script(&mut self, session: &mut ScriptSession, source: &str) -> Result<Dynamic> {
+        let engine = self.scripts.engine();
+        let compiled = engine
+            .compile_with_scope(&session.scope, source)
+            .map_err(|e| anyhow!("{}", e))?;
+        let ast = Rc::new(session.functions.merge(&compiled));
+        session.functions = session.functions.merge(&compiled.clone_functions_only());
+
+        let previous = self.scripts.ast.replace(ast.clone());
+        let result = {
+            let _current = enter(self);
+            engine.eval_ast_with_scope::<Dynamic>(&mut session.scope, &ast)
+        };
+        self.scripts.ast = previous;
+        result.map_err(|e| anyhow!("{}", e))
+    }
+
+    fn run_breakpoint_callback(&mut self, id: usize) -> Option<bool> {
+        let (ast, callback) = self.scripts.callbacks.get(&id)?.clone();
+        let engine = self.scripts.engine();
+
+        let previous = self.scripts.ast.replace(ast.clone());
+        let in_callback = std::mem::replace(&mut self.scripts.in_callback, true);
+        let result = {
+            let _current = enter(self);
+            // `|id| ...` or a closure without parameters
+            match callback.call::<Dynamic>(&engine, &ast, (id as INT,)) {
+                Err(e) if matches!(*e, EvalAltResult::ErrorFunctionNotFound(..)) => {
+                    callback.call::<Dynamic>(&engine, &ast, ())
+                }
+                result => result,
+            }
+        };
+        self.scripts.in_callback = in_callback;
+        self.scripts.ast = previous;
+
+        match result {
+            Ok(stop) => Some(stop.as_bool().unwrap_or(false)),
+            Err(e) => {
+                println!("Error in callback of breakpoint {}: {}", id, e);
+                Some(true)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::breakpoint_helpers::tests::build_main_c;
+    use nix::sys::wait::WaitStatus;
+    use std::{fs, path::Path};
+
+    #[test]
+    fn test_script_on_ls() {
+        let mut debugger =
+            Debugger::new("/bin/ls".to_string(), "".to_string(), Vec::new()).unwrap();
+        let mut session = ScriptSession::default();
+        let value = debugger
+            .eval_script(&mut session, "let rip = reg(\"rip\");")
+            .unwrap();
+        assert!(value.is_unit());
+        let value = debugger
+            .eval_script(&mut session, "rip == evaluate(\"$rip\") && pid() == tid()")
+            .unwrap();
+        assert!(value.as_bool().unwrap());
+
+        let rip = debugger.get_register_value("rip").unwrap();
+        let word = debugger.read_word(rip).unwrap();
+        let value = debugger.eval_script(&mut session, "read_u64(rip)").unwrap();
+        assert_eq!(value.as_int().unwrap() as u64, word);
+        assert!(debugger.eval_script(&mut session, "reg(\"xyz\")").is_err());
+        for script in ["read(rip, -1)", "read(rip, 1 << 40)"] {
+            assert!(debugger.eval_script(&mut session, script).is_err());
+        }
+    }
+
+    #[test]
+    fn test_breakpoint_callback() {
+        let binary = build_main_c("callback");
+        let mut debugger = Debugger::new(binary.clone(), "".to_string(), Vec::new()).unwrap();
+        debugger.process.get_base_addr_from_map();
+        let mut session = ScriptSession::default();
+        // the callback runs inside cont() and lets the program go on to exit
+        let value = debugger
+            .eval_script(
+                &mut session,
+                "let hits = 0;
+                 breakpoint(\"main\", || { hits += 1; false });
+                 let running = cont();
+                 [hits, running]",
+            )
+            .unwrap();
+        let value = value.into_array().unwrap();
+        assert_eq!(value[0].as_int().unwrap(), 1);
+        assert!(!value[1].as_bool().unwrap());
+        assert!(matches!(
+            debugger.last_status,
+            Some(WaitStatus::Exited(_, 1))
+        ));
+        let _ = fs::remove_dir_all(Path::new(&binary).parent().unwrap());
+    }
+}
@@ -0,0 +1,218 @@
+use anyhow::{bail, Result};
+use nix::sys::signal::Signal;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// what happens when the debugee receives a signal, `handle <SIG> ...`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SignalPolicy {
+    pub stop: bool,
+    pub print: bool,
+    pub pass: bool,
+}
+
+// signals that are part of normal operation and would only get in the way
+const QUIET: [Signal; 7] = [
+    Signal::SIGCHLD,
+    Signal::SIGALRM,
+    Signal::SIGWINCH,
+    Signal::SIGURG,
+    Signal::SIGPROF,
+    Signal::SIGVTALRM,
+    Signal::SIGIO,
+];
+
+#[derive(Debug)]
+pub struct SignalTable {
+    policies: HashMap<Signal, SignalPolicy>,
+}
+
+impl SignalTable {
+    pub fn new() -> Self {
+        let mut policies = HashMap::new();
+        for signal in Signal::iterator() {
+            let policy = if QUIET.contains(&signal) {
+                SignalPolicy {
+                    stop: false,
+                    print: false,
+                    pass: true,
+                }
+            } else {
+                // SIGSEGV, SIGABRT, SIGILL, SIGFPE and everything unusual
+                SignalPolicy {
+                    stop: true,
+                    print: true,
+                    pass: true,
+                }
+            };
+            policies.insert(signal, policy);
+        }
+        // an interrupt is meant for us, not the program
+        policies.get_mut(&Signal::SIGINT).unwrap().pass = false;
+        SignalTable { policies }
+    }
+
+    pub fn get(&self, signal: Signal) -> SignalPolicy {
+        self.policies[&signal]
+    }
+
+    // keywords like gdb: stop implies print and noprint implies nostop
+    pub fn set(&mut self, signal: Signal, keywords: &[&str]) -> Result<()> {
+        if matches!(signal, Signal::SIGTRAP | Signal::SIGKILL) {
+            bail!("{:?} is used by the debugger", signal);
+        }
+        let mut policy = self.get(signal);
+        for keyword in keywords {
+            match keyword.to_lowercase().as_str() {
+                "stop" => {
+                    policy.stop = true;
+                    policy.print = true;
+                }
+                "nostop" => policy.stop = false,
+                "print" => policy.print = true,
+                "noprint" => {
+                    policy.print = false;
+                    policy.stop = false;
+                }
+                "pass" | "noignore" => policy.pass = true,
+                "nopass" | "ignore" => policy.pass = false,
+                other => bail!(
+                    "Unknown keyword '{}', expected stop|nostop print|noprint pass|nopass",
+                    other
+                ),
+            }
+        }
+        self.policies.insert(signal, policy);
+        Ok(())
+    }
+
+    pub fn print(&self, signals: &[Signal]) {
+        let yes_no = |b: bool| if b { "Yes" } else { "No" };
+        println!(
+            "{:<10} {:<5} {:<6} {:<5}",
+            "Signal", "Stop", "Print", "Pass"
+        );
+        for signal in signals {
+            let policy = self.get(*signal);
+            println!(
+                "{:<10} {:<5} {:<6} {:<5}",
+                signal.as_str(),
+                yes_no(policy.stop),
+                yes_no(policy.print),
+                yes_no(policy.pass)
+            );
+        }
+    }
+}
+
+impl Default for SignalTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// "SIGSEGV", "segv" or the signal number
+pub fn parse_signal(name: &str) -> Result<Signal> {
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::try_from(number).map_err(|_| anyhow::anyhow!("No signal {}", number));
+    }
+    let upper = name.to_uppercase();
+    let full = if upper.starts_with("SIG") {
+        upper
+    } else {
+        format!("SIG{}", upper)
+    };
+    Signal::from_str(&full).map_err(|_| anyhow::anyhow!("Unknown signal '{}'", name))
+}
+
+// "SEGV_MAPERR (1)" style name of siginfo.si_code
+pub fn describe_si_code(signal: Signal, code: i32) -> String {
+    let name = match (signal, code) {
+        (_, libc::SI_USER) => "SI_USER",
+        (_, libc::SI_KERNEL) => "SI_KERNEL",
+        (_, libc::SI_QUEUE) => "SI_QUEUE",
+        (_, libc::SI_TKILL) => "SI_TKILL",
+        (Signal::SIGSEGV, 1) => "SEGV_MAPERR",
+        (Signal::SIGSEGV, 2) => "SEGV_ACCERR",
+        (Signal::SIGSEGV, 3) => "SEGV_BNDERR",
+        (Signal::SIGSEGV, 4) => "SEGV_PKUERR",
+        (Signal::SIGBUS, 1) => "BUS_ADRALN",
+        (Signal::SIGBUS, 2) => "BUS_ADRERR",
+        (Signal::SIGBUS, 3) => "BUS_OBJERR",
+        (Signal::SIGILL, 1) => "ILL_ILLOPC",
+        (Signal::SIGILL, 2) => "ILL_ILLOPN",
+        (Signal::SIGILL, 3) => "ILL_ILLADR",
+        (Signal::SIGILL, 4) => "ILL_ILLTRP",
+        (Signal::SIGILL, 5) => "ILL_PRVOPC",
+        (Signal::SIGILL, 6) => "ILL_PRVREG",
+        (Signal::SIGILL, 7) => "ILL_COPROC",
+        (Signal::SIGILL, 8) => "ILL_BADSTK",
+        (Signal::SIGFPE, 1) => "FPE_INTDIV",
+        (Signal::SIGFPE, 2) => "FPE_INTOVF",
+        (Signal::SIGFPE, 3) => "FPE_FLTDIV",
+        (Signal::SIGFPE, 4) => "FPE_FLTOVF",
+        (Signal::SIGFPE, 5) => "FPE_FLTUND",
+        (Signal::SIGFPE, 6) => "FPE_FLTRES",
+        (Signal::SIGFPE, 7) => "FPE_FLTINV",
+        (Signal::SIGFPE, 8) => "FPE_FLTSUB",
+        (Signal::SIGCHLD, 1) => "CLD_EXITED",
+        (Signal::SIGCHLD, 2) => "CLD_KILLED",
+        (Signal::SIGCHLD, 3) => "CLD_DUMPED",
+        (Signal::SIGCHLD, 4) => "CLD_TRAPPED",
+        (Signal::SIGCHLD, 5) => "CLD_STOPPED",
+        (Signal::SIGCHLD, 6) => "CLD_CONTINUED",
+        _ => "?",
+    };
+    format!("{} ({})", name, code)
+}
+
+// the signals that carry the faulting address in si_addr
+pub fn has_fault_addr(signal: Signal) -> bool {
+    matches!(
+        signal,
+        Signal::SIGSEGV | Signal::SIGBUS | Signal::SIGILL | Signal::SIGFPE
+    )
+}
+
+// strsignal(3), e.g. "Segmentation fault"
+pub fn signal_text(signal: Signal) -> String {
+    let text = unsafe { libc::strsignal(signal as i32) };
+    if text.is_null() {
+        return signal.as_str().to_string();
+    }
+    unsafe { std::ffi::CStr::from_ptr(text) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGSEGV").unwrap(), Signal::SIGSEGV);
+        assert_eq!(parse_signal("segv").unwrap(), Signal::SIGSEGV);
+        assert_eq!(parse_signal("14").unwrap(), Signal::SIGALRM);
+        assert!(parse_signal("SIGNOPE").is_err());
+    }
+
+    #[test]
+    fn test_policies() {
+        let mut table = SignalTable::new();
+        assert!(table.get(Signal::SIGSEGV).stop);
+        assert!(table.get(Signal::SIGABRT).stop);
+        assert!(!table.get(Signal::SIGCHLD).stop && table.get(Signal::SIGCHLD).pass);
+        assert!(!table.get(Signal::SIGALRM).print && table.get(Signal::SIGALRM).pass);
+
+        table.set(Signal::SIGUSR1, &["noprint", "nopass"]).unwrap();
+        let usr1 = table.get(Signal::SIGUSR1);
+        assert!(!usr1.stop && !usr1.print && !usr1.pass);
+
+        table.set(Signal::SIGALRM, &["stop"]).unwrap();
+        assert!(table.get(Signal::SIGALRM).print);
+
+        assert!(table.set(Signal::SIGTRAP, &["nostop"]).is_err());
+        assert!(table.set(Signal::SIGUSR2, &["sometimes"]).is_err());
+    }
+}
@@ -1,55 +1,402 @@
-use crate::core::memory::read_process_memory;
+use crate::core::context::Context;
+use crate::core::process_control::ProcessControl;
+use crate::core::symbols::get_unwind_info;
+use crate::core::syscalls::SyscallTracing;
+use crate::core::threads::ThreadControl;
 use crate::core::*;
-use anyhow::Result;
+use anyhow::{bail, Result};
 use capstone::prelude::*;
-use nix::sys::ptrace;
-use nix::sys::ptrace::*;
+use nix::unistd::Pid;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepKind {
+    Step,
+    Next,
+    Finish,
+    NextInstruction,
+}
+
+// multi-stop stepping operation, advanced by resume_and_wait on every trap
+#[derive(Debug, Clone)]
+pub struct StepPlan {
+    // thread that is stepped, other threads only run during `run_to`
+    pub tid: Pid,
+    pub kind: StepKind,
+    pub file: String,
+    pub line: u64,
+    pub run_to: Option<RunTo>,
+    // the last single step executed a call instruction
+    pub stepped_call: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RunTo {
+    pub addr: u64,
+    // deeper frames of the same function (recursion) pass by `addr`
+    pub min_rsp: u64,
+    // stop once reached instead of going on with line stepping
+    pub stop: bool,
+    // the internal breakpoint is ours and has to be removed again
+    pub inserted: bool,
+}
 
 pub trait Stepping {
     fn cont(&mut self) -> Result<()>;
     fn single_step(&mut self) -> Result<()>;
     fn step_over(&mut self) -> Result<()>;
+    fn step_over_breakpoint(&mut self) -> Result<bool>;
+    fn step_line(&mut self, over: bool) -> Result<()>;
+    fn finish(&mut self) -> Result<()>;
+    fn continue_step_plan(&mut self);
+    fn cancel_step_plan(&mut self);
 }
 
 impl Stepping for Debugger {
     fn cont(&mut self) -> Result<()> {
-        nix::sys::ptrace::cont(self.process.pid, None)?;
+        self.ensure_live()?;
+        self.step_over_breakpoint()?;
+        if self.report_step_watch_hit() || self.report_pending_catch() {
+            return Ok(());
+        }
+        self.resume_all_threads()?;
         self.state = DebuggerState::AwaitingTrap;
         Ok(())
     }
 
     fn single_step(&mut self) -> Result<()> {
-        nix::sys::ptrace::step(self.process.pid, None)?;
+        self.ensure_live()?;
+        if !self.step_over_breakpoint()? {
+            self.step_thread(self.process.tid)?;
+        }
+        if self.report_step_watch_hit() {
+            return Ok(());
+        }
+        let regs = self.read_regs(self.process.tid)?;
+        println!("0x{:x}", regs.rip);
+        self.print_context();
         Ok(())
     }
 
     fn step_over(&mut self) -> Result<()> {
+        self.ensure_live()?;
+        let regs = self.read_regs(self.process.tid)?;
+        if let Some(len) = self.call_length(regs.rip)? {
+            self.step_plan = Some(StepPlan {
+                tid: self.process.tid,
+                kind: StepKind::NextInstruction,
+                file: String::new(),
+                line: 0,
+                run_to: Some(RunTo {
+                    addr: regs.rip + len,
+                    min_rsp: regs.rsp,
+                    stop: true,
+                    inserted: false,
+                }),
+                stepped_call: false,
+            });
+            self.drive_step_plan()
+        } else {
+            self.single_step()
+        }
+    }
+
+    // steps the thread that hit the disarmed breakpoint off it and puts the
+    // 0xCC back afterwards, true if that was the selected thread
+    fn step_over_breakpoint(&mut self) -> Result<bool> {
+        let Some(addr) = self.breakpoint.step_over.take() else {
+            return Ok(false);
+        };
+        let tid = self
+            .breakpoint
+            .step_over_thread
+            .take()
+            .unwrap_or(self.process.tid);
+        let stopped = self.process.thread(tid).is_some_and(|t| !t.running);
+        // rip may have been moved away by the user
+        let stepped = stopped && self.read_regs(tid)?.rip == addr;
+        if stepped {
+            self.step_thread(tid)?;
+        }
+        self.breakpoint.arm(addr, self.target.as_mut())?;
+        Ok(stepped && tid == self.process.tid)
+    }
+
+    fn step_line(&mut self, over: bool) -> Result<()> {
+        self.ensure_live()?;
+        let rip = self.read_regs(self.process.tid)?.rip;
+        // below the load address, e.g. in the vdso or ld.so
+        let line = rip
+            .checked_sub(self.process.base_addr)
+            .and_then(|offset| self.dwarf.find_line(offset));
+        let Some((file, line)) = line else {
+            // no line info here, fall back to instruction stepping
+            return if over {
+                self.step_over()
+            } else {
+                self.single_step()
+            };
+        };
+        self.step_plan = Some(StepPlan {
+            tid: self.process.tid,
+            kind: if over { StepKind::Next } else { StepKind::Step },
+            file,
+            line,
+            run_to: None,
+            stepped_call: false,
+        });
+        self.drive_step_plan()
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.ensure_live()?;
+        let regs = self.read_regs(self.process.tid)?;
+        let offset = regs.rip.wrapping_sub(self.process.base_addr);
+        let info = get_unwind_info(&self.path, offset)?;
+        let cfa_base = match info.cfa_register {
+            6 => regs.rbp,
+            7 => regs.rsp,
+            other => bail!("unsupported cfa reg {}", other),
+        };
+        let cfa = (cfa_base as i64 + info.cfa_offset) as u64;
+        let ret_addr_addr = (cfa as i64 + info.ra_offset) as u64;
+        let ret_addr = self.read_word(ret_addr_addr)?;
+
+        println!("Run till exit from {}", self.describe_location(regs.rip));
+        self.step_plan = Some(StepPlan {
+            tid: self.process.tid,
+            kind: StepKind::Finish,
+            file: String::new(),
+            line: 0,
+            run_to: Some(RunTo {
+                addr: ret_addr,
+                // after the `ret` rsp points right at the CFA of this frame
+                min_rsp: cfa,
+                stop: true,
+                inserted: false,
+            }),
+            stepped_call: false,
+        });
+        self.drive_step_plan()
+    }
+
+    fn continue_step_plan(&mut self) {
+        let result = match self.step_plan_reached() {
+            Ok(true) => {
+                self.step_plan_done();
+                Ok(())
+            }
+            Ok(false) => self.drive_step_plan(),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            println!("Stepping failed: {}", e);
+            self.cancel_step_plan();
+            self.state = DebuggerState::Interactive;
+        }
+    }
+
+    fn cancel_step_plan(&mut self) {
+        if let Some(plan) = self.step_plan.take() {
+            if let Some(run_to) = plan.run_to.filter(|r| r.inserted) {
+                let _ = self
+                    .breakpoint
+                    .remove_breakpoint(run_to.addr, self.target.as_mut());
+            }
+        }
+    }
+}
+
+impl Debugger {
+    // issues the next single step or continue of the plan, returns once the
+    // debugee runs (state AwaitingTrap) or the plan is done
+    fn drive_step_plan(&mut self) -> Result<()> {
+        loop {
+            let pid = self.process.tid;
+            let Some(plan) = self.step_plan.as_mut() else {
+                return Ok(());
+            };
+
+            if let Some(run_to) = plan.run_to.as_mut() {
+                if !run_to.inserted && !self.breakpoint.is_breakpoint(run_to.addr) {
+                    self.breakpoint
+                        .set_internal_breakpoint(run_to.addr, self.target.as_mut())?;
+                    run_to.inserted = true;
+                }
+                return self.cont();
+            }
+
+            let regs = self.read_regs(pid)?;
+            let call_len = self.call_length(regs.rip)?;
+            let plan = self.step_plan.as_mut().unwrap();
+            if let (Some(len), StepKind::Next) = (call_len, plan.kind) {
+                plan.run_to = Some(RunTo {
+                    addr: regs.rip + len,
+                    min_rsp: regs.rsp,
+                    stop: false,
+                    inserted: false,
+                });
+                continue;
+            }
+            plan.stepped_call = call_len.is_some();
+
+            if self.step_over_breakpoint()? {
+                if self.report_step_watch_hit() {
+                    return Ok(());
+                }
+                if self.step_plan_reached()? {
+                    self.step_plan_done();
+                    return Ok(());
+                }
+                continue;
+            }
+            self.start_step(pid)?;
+            self.state = DebuggerState::AwaitingTrap;
+            return Ok(());
+        }
+    }
+
+    // whether the plan is complete at the current stop, updates the plan if
+    // it has to run somewhere first
+    fn step_plan_reached(&mut self) -> Result<bool> {
+        let Some(plan) = self.step_plan.clone() else {
+            return Ok(true);
+        };
+        let regs = self.read_regs(self.process.tid)?;
+        let offset = regs.rip.wrapping_sub(self.process.base_addr);
+
+        if let Some(run_to) = plan.run_to {
+            if regs.rip != run_to.addr || regs.rsp < run_to.min_rsp {
+                return Ok(false);
+            }
+            if run_to.inserted {
+                self.breakpoint
+                    .remove_breakpoint(run_to.addr, self.target.as_mut())?;
+            }
+            self.step_plan.as_mut().unwrap().run_to = None;
+            if run_to.stop {
+                return Ok(true);
+            }
+        }
+
+        if plan.stepped_call {
+            self.step_plan.as_mut().unwrap().stepped_call = false;
+            let function = self
+                .functions
+                .iter()
+                .find(|f| f.offset <= offset && f.offset + f.size > offset);
+            let prologue_end = function
+                .filter(|_| self.dwarf.find_line(offset).is_some())
+                .and_then(|f| self.dwarf.prologue_end(f.offset, f.offset + f.size));
+
+            let run_to = match prologue_end {
+                Some(end) if offset >= end => return Ok(true),
+                Some(end) => RunTo {
+                    addr: end + self.process.base_addr,
+                    min_rsp: 0,
+                    stop: true,
+                    inserted: false,
+                },
+                None => {
+                    // no line info in the callee, return to the caller and
+                    // keep stepping there
+                    let ret_addr = self.read_word(regs.rsp)?;
+                    RunTo {
+                        addr: ret_addr,
+                        min_rsp: regs.rsp + 8,
+                        stop: false,
+                        inserted: false,
+                    }
+                }
+            };
+            self.step_plan.as_mut().unwrap().run_to = Some(run_to);
+            return Ok(false);
+        }
+
+        Ok(match self.dwarf.line_row_at(offset) {
+            Some(row) => row.line != plan.line || row.file != plan.file,
+            // stepped out of the code we have line info for
+            None => self.dwarf.find_line(offset).is_none(),
+        })
+    }
+
+    fn step_plan_done(&mut self) {
+        let plan = self.step_plan.take();
+        self.state = DebuggerState::Interactive;
+        let Ok(regs) = self.read_regs(self.process.tid) else {
+            return;
+        };
+        if plan.is_some_and(|p| p.kind == StepKind::Finish) {
+            println!(
+                "Value returned: rax = 0x{:x} ({})",
+                regs.rax, regs.rax as i64
+            );
+        }
+        println!("0x{:x} in {}", regs.rip, self.describe_location(regs.rip));
+        self.print_context();
+    }
+
+    // length of the instruction at addr if it is a call
+    fn call_length(&self, addr: u64) -> Result<Option<u64>> {
         let cs = Capstone::new()
             .x86()
             .mode(arch::x86::ArchMode::Mode64)
             .syntax(arch::x86::ArchSyntax::Intel)
-            .detail(true)
-            .build()
-            .expect("Failed to create Capstone object");
+            .build()?;
 
-        let regs = getregs(self.process.pid).unwrap();
+        let mut code = vec![0u8; 16];
+        self.read_memory(addr, &mut code)?;
+        // the byte under a disarmed or pending breakpoint is the original one
+        if let Some(bp) = self.breakpoint.get(addr).filter(|bp| bp.enabled) {
+            code[0] = bp.original_byte;
+        }
+        let insns = cs.disasm_count(&code, addr, 1)?;
+        Ok(insns
+            .iter()
+            .next()
+            .filter(|i| i.mnemonic() == Some("call"))
+            .map(|i| i.len() as u64))
+    }
+}
 
-        let rip = regs.rip;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::target::MockTarget;
 
-        let num_bytes = 10;
-        let mut code = vec![0u8; num_bytes];
+    #[test]
+    fn test_nexti_on_mock_target() {
+        let tid = Pid::from_raw(1);
+        // a call of the next instruction, then nops
+        let mut code = vec![0xe8, 0, 0, 0, 0];
+        code.resize(32, 0x90);
+        let target = MockTarget::with_code(tid, 0x1000, &code);
+        let mut debugger = Debugger::with_mock(target, tid);
 
-        read_process_memory(self.process.pid, rip as usize, &mut code)?;
-        let insns = cs.disasm_all(&code, rip).expect("Disassembly failed");
-        let next_inst = insns.iter().next().unwrap();
-        if next_inst.mnemonic() == Some("call") {
-            let next_addr = rip + next_inst.len() as u64;
-            self.breakpoint
-                .set_breakpoint(next_addr, self.process.pid)?;
-            self.cont()?;
-        } else {
-            ptrace::step(self.process.pid, None)?;
-        }
-        Ok(())
+        // the call is run through to an internal breakpoint behind it
+        debugger.step_over().unwrap();
+        assert!(debugger.breakpoint.is_breakpoint(0x1005));
+        debugger.wait_for_stop();
+        assert_eq!(debugger.read_regs(tid).unwrap().rip, 0x1005);
+        assert!(!debugger.breakpoint.is_breakpoint(0x1005));
+        assert_eq!(debugger.read_word(0x1005).unwrap() & 0xff, 0x90);
+        assert!(debugger.step_plan.is_none());
+
+        // anything else is a single step
+        debugger.step_over().unwrap();
+        assert_eq!(debugger.read_regs(tid).unwrap().rip, 0x1006);
+    }
+
+    #[test]
+    fn test_step_below_load_address() {
+        let tid = Pid::from_raw(1);
+        let target = MockTarget::with_code(tid, 0x1000, &[0x90; 32]);
+        let mut debugger = Debugger::with_mock(target, tid);
+        debugger.process.base_addr = 0x555555554000;
+
+        // no line info outside the binary, these step single instructions
+        debugger.step_line(false).unwrap();
+        debugger.step_line(true).unwrap();
+        assert_eq!(debugger.read_regs(tid).unwrap().rip, 0x1002);
+        assert!(debugger.finish().is_err());
     }
 }
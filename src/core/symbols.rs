@@ -1,3 +1,4 @@
+use crate::core::error::DebuggerError;
 use crate::core::Debugger;
 use anyhow::Result;
 use gimli::{BaseAddresses, EhFrame, RunTimeEndian, UnwindContext, UnwindSection};
@@ -7,12 +8,32 @@ use memmap2::Mmap;
 use object::{Object, ObjectSection};
 use std::{borrow, error, fs, path::PathBuf};
 
+#[derive(Debug, Clone)]
+pub struct LineEntry {
+    pub address: u64, //offset of base
+    pub file: String,
+    pub line: u64,
+    pub is_stmt: bool,
+    pub end_sequence: bool,
+}
+
+// address range of one DW_TAG_inlined_subroutine, `instance` tells the
+// ranges of different inlined copies apart
+#[derive(Debug, Clone)]
+pub struct InlinedRange {
+    pub low: u64,
+    pub high: u64,
+    pub instance: usize,
+}
+
 #[derive(Debug)]
 #[allow(dead_code)]
 pub struct DwarfContext {
     pub mmap: Mmap,
     pub endian: RunTimeEndian,
     pub object: object::File<'static>,
+    pub lines: Vec<LineEntry>,
+    pub inlined: Vec<InlinedRange>,
 }
 
 impl DwarfContext {
@@ -28,14 +49,85 @@ impl DwarfContext {
             RunTimeEndian::Big
         };
 
-        Ok(Self {
+        let mut ctx = Self {
             mmap,
             endian,
             object,
-        })
+            lines: Vec::new(),
+            inlined: Vec::new(),
+        };
+        (ctx.lines, ctx.inlined) = ctx.load_units()?;
+        Ok(ctx)
     }
 
     pub fn get_line_and_file(&self, target_addr: u64) {
+        if let Some((file, line)) = self.find_line(target_addr) {
+            println!("{}:{}", file, line);
+        }
+    }
+
+    // file and line of the row covering target_addr
+    pub fn find_line(&self, target_addr: u64) -> Option<(String, u64)> {
+        self.lines
+            .windows(2)
+            .find(|w| {
+                !w[0].end_sequence && w[0].address <= target_addr && target_addr < w[1].address
+            })
+            .map(|w| (w[0].file.clone(), w[0].line))
+    }
+
+    // statement row starting exactly at target_addr
+    pub fn line_row_at(&self, target_addr: u64) -> Option<&LineEntry> {
+        self.lines
+            .iter()
+            .find(|e| e.is_stmt && !e.end_sequence && e.address == target_addr)
+    }
+
+    // first statement after the one at the function start, this is where the
+    // prologue is done and the arguments are in their stack slots
+    pub fn prologue_end(&self, func_start: u64, func_end: u64) -> Option<u64> {
+        let mut addrs: Vec<u64> = self
+            .lines
+            .iter()
+            .filter(|e| {
+                e.is_stmt && !e.end_sequence && e.address >= func_start && e.address < func_end
+            })
+            .map(|e| e.address)
+            .collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs.get(1).or(addrs.first()).copied()
+    }
+
+    // statement rows of the first line >= `line` with code in a file ending
+    // in `file`, sorted offsets, may span several functions or inlined copies
+    pub fn find_addresses(&self, file: &str, line: u64) -> Vec<u64> {
+        let suffix = format!("/{}", file);
+        let rows = self.lines.iter().filter(|e| {
+            e.is_stmt && !e.end_sequence && (e.file == file || e.file.ends_with(&suffix))
+        });
+        let Some(best) = rows.clone().map(|e| e.line).filter(|l| *l >= line).min() else {
+            return Vec::new();
+        };
+        let mut addrs: Vec<u64> = rows.filter(|e| e.line == best).map(|e| e.address).collect();
+        addrs.sort_unstable();
+        addrs.dedup();
+        addrs
+    }
+
+    // innermost inlined copy of a function that target_addr belongs to
+    pub fn inlined_instance(&self, target_addr: u64) -> Option<usize> {
+        self.inlined
+            .iter()
+            .filter(|r| r.low <= target_addr && target_addr < r.high)
+            .min_by_key(|r| r.high - r.low)
+            .map(|r| r.instance)
+    }
+
+    // walks the line programs and the inlined subroutines of all units once,
+    // rows keep the program order
+    #[allow(clippy::type_complexity)]
+    fn load_units(&self) -> Result<(Vec<LineEntry>, Vec<InlinedRange>), Box<dyn error::Error>> {
         let load_section =
             |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, Box<dyn error::Error>> {
                 Ok(match self.object.section_by_name(id.name()) {
@@ -47,13 +139,15 @@ impl DwarfContext {
         let borrow_section =
             |section| gimli::EndianSlice::new(borrow::Cow::as_ref(section), self.endian);
 
-        let dwarf_sections = gimli::DwarfSections::load(&load_section).unwrap();
+        let dwarf_sections = gimli::DwarfSections::load(&load_section)?;
 
         let dwarf = dwarf_sections.borrow(borrow_section);
 
+        let mut lines = Vec::new();
+        let mut inlined = Vec::new();
         let mut units = dwarf.units();
-        while let Some(header) = units.next().unwrap() {
-            let unit = dwarf.unit(header).unwrap();
+        while let Some(header) = units.next()? {
+            let unit = dwarf.unit(header)?;
             let unit = unit.unit_ref(&dwarf);
 
             if let Some(program) = unit.line_program.clone() {
@@ -64,45 +158,49 @@ impl DwarfContext {
                     .unwrap_or_default();
 
                 let mut rows = program.rows();
-                let mut last_match = None;
-
-                while let Some((header, row)) = rows.next_row().unwrap() {
-                    if row.end_sequence() {
-                        continue;
-                    }
-
-                    if row.address() > target_addr {
-                        break;
-                    }
-
-                    last_match = Some((header.clone(), row.clone()));
-                }
-
-                if let Some((header, row)) = last_match {
-                    if let Some(file) = row.file(&header) {
-                        let mut path = comp_dir;
 
+                while let Some((header, row)) = rows.next_row()? {
+                    let mut path = comp_dir.clone();
+                    if let Some(file) = row.file(header) {
                         if file.directory_index() != 0 {
-                            if let Some(dir) = file.directory(&header) {
-                                path.push(
-                                    unit.attr_string(dir).unwrap().to_string_lossy().as_ref(),
-                                );
+                            if let Some(dir) = file.directory(header) {
+                                path.push(unit.attr_string(dir)?.to_string_lossy().as_ref());
                             }
                         }
-
                         path.push(
-                            unit.attr_string(file.path_name())
-                                .unwrap()
+                            unit.attr_string(file.path_name())?
                                 .to_string_lossy()
                                 .as_ref(),
                         );
-
-                        let line = row.line().map(|l| l.get()).unwrap_or(0);
-                        println!("{}:{}", path.display(), line);
                     }
+
+                    lines.push(LineEntry {
+                        address: row.address(),
+                        file: path.display().to_string(),
+                        line: row.line().map(|l| l.get()).unwrap_or(0),
+                        is_stmt: row.is_stmt(),
+                        end_sequence: row.end_sequence(),
+                    });
+                }
+            }
+
+            let mut entries = unit.entries();
+            while let Some((_, entry)) = entries.next_dfs()? {
+                if entry.tag() != gimli::DW_TAG_inlined_subroutine {
+                    continue;
+                }
+                let instance = inlined.len();
+                let mut ranges = unit.die_ranges(entry)?;
+                while let Some(range) = ranges.next()? {
+                    inlined.push(InlinedRange {
+                        low: range.begin,
+                        high: range.end,
+                        instance,
+                    });
                 }
             }
         }
+        Ok((lines, inlined))
     }
 }
 
@@ -115,28 +213,53 @@ pub struct FunctionInfo {
 }
 
 impl FunctionInfo {
-    pub fn new(path: &String, debuger_name: String) -> Vec<FunctionInfo> {
+    pub fn new(path: &String, debuger_name: String) -> Result<Vec<FunctionInfo>> {
         info!("debuger_name: {}", debuger_name); // Debg. name probably not needed anymore like this
-        let buffer = fs::read(path).unwrap();
+        let symbols_error = |reason: String| DebuggerError::Symbols {
+            path: path.clone(),
+            reason,
+        };
+        let buffer = fs::read(path).map_err(|e| symbols_error(e.to_string()))?;
         let mut ret = Vec::new();
-        match GoblinObject::parse(&buffer).unwrap() {
-            GoblinObject::Elf(elf) => {
-                for sym in elf.syms.iter() {
-                    if sym.is_function() {
-                        if let Some(name) = elf.strtab.get_at(sym.st_name) {
-                            debug!("{} {}", name, sym.st_value);
-                            ret.push(FunctionInfo {
-                                name: name.to_string(),
-                                offset: sym.st_value,
-                                size: sym.st_size,
-                            })
-                        }
+        if let GoblinObject::Elf(elf) =
+            GoblinObject::parse(&buffer).map_err(|e| symbols_error(e.to_string()))?
+        {
+            for sym in elf.syms.iter() {
+                if sym.is_function() {
+                    if let Some(name) = elf.strtab.get_at(sym.st_name) {
+                        debug!("{} {}", name, sym.st_value);
+                        ret.push(FunctionInfo {
+                            name: name.to_string(),
+                            offset: sym.st_value,
+                            size: sym.st_size,
+                        })
                     }
                 }
             }
-            _ => {}
         }
-        return ret;
+        Ok(ret)
+    }
+
+    // global data objects, same offset/size layout as the functions
+    pub fn globals(path: &str) -> Vec<FunctionInfo> {
+        let Ok(buffer) = fs::read(path) else {
+            return Vec::new();
+        };
+        let mut ret = Vec::new();
+        if let Ok(GoblinObject::Elf(elf)) = GoblinObject::parse(&buffer) {
+            for sym in elf.syms.iter() {
+                if sym.st_type() == goblin::elf::sym::STT_OBJECT && sym.st_value != 0 {
+                    if let Some(name) = elf.strtab.get_at(sym.st_name) {
+                        ret.push(FunctionInfo {
+                            name: name.to_string(),
+                            offset: sym.st_value,
+                            size: sym.st_size,
+                        })
+                    }
+                }
+            }
+        }
+        ret
     }
 }
 
@@ -172,8 +295,7 @@ pub fn get_unwind_info(path: &str, target_addr: u64) -> Result<UnwindRowInfo> {
             gimli::CieOrFde::Cie(_cie) => {}
             gimli::CieOrFde::Fde(partial) => {
                 let fde = partial
-                    .parse(|_section, bases, offset| eh_frame.cie_from_offset(bases, offset))
-                    .unwrap();
+                    .parse(|_section, bases, offset| eh_frame.cie_from_offset(bases, offset))?;
 
                 let start = fde.initial_address();
                 let end = start + fde.len();
@@ -192,16 +314,12 @@ pub fn get_unwind_info(path: &str, target_addr: u64) -> Result<UnwindRowInfo> {
                         gimli::CfaRule::RegisterAndOffset { register, offset } => {
                             (register.0, *offset)
                         }
-                        rule => {
-                            return Err(anyhow::anyhow!("Unsupported CFA rule: {:?}", rule).into())
-                        }
+                        rule => return Err(anyhow::anyhow!("Unsupported CFA rule: {:?}", rule)),
                     };
 
                     let ra_offset = match row.register(gimli::X86_64::RA) {
                         gimli::RegisterRule::Offset(off) => off,
-                        rule => {
-                            return Err(anyhow::anyhow!("Unsupported RA rule: {:?}", rule).into())
-                        }
+                        rule => return Err(anyhow::anyhow!("Unsupported RA rule: {:?}", rule)),
                     };
 
                     let info = UnwindRowInfo {
@@ -215,7 +333,10 @@ pub fn get_unwind_info(path: &str, target_addr: u64) -> Result<UnwindRowInfo> {
             }
         }
     }
-    return Err(anyhow::anyhow!("No FDE found for address 0x{:x}", target_addr).into());
+    Err(anyhow::anyhow!(
+        "No FDE found for address 0x{:x}",
+        target_addr
+    ))
     //need better way to detect end of backtrace
 }
 
@@ -0,0 +1,480 @@
+use crate::core::context::Context;
+use crate::core::stepping::Stepping;
+use crate::core::syscall_table::SYSCALLS;
+use crate::core::target::Target;
+use crate::core::threads::ThreadControl;
+use crate::core::*;
+use anyhow::{bail, Result};
+use libc::user_regs_struct;
+use nix::errno::Errno;
+use nix::unistd::Pid;
+use std::collections::HashMap;
+
+const MAX_STRING: usize = 48;
+
+// `catch syscall`, an empty list catches every syscall
+#[derive(Debug, Clone)]
+pub struct SyscallCatch {
+    pub id: usize,
+    pub numbers: Vec<u64>,
+    pub hit_count: usize,
+}
+
+#[derive(Debug)]
+pub struct SyscallTracer {
+    // strace mode, every syscall is printed
+    pub trace: bool,
+    pub catches: Vec<SyscallCatch>,
+    // threads inside a syscall with the call as printed at entry
+    inside: HashMap<Pid, String>,
+    // caught entries/exits of threads that stopped while all threads were
+    // being stopped, reported on the next continue
+    pub pending: Vec<(Pid, bool, u64)>,
+}
+
+impl SyscallTracer {
+    pub fn new() -> Self {
+        SyscallTracer {
+            trace: false,
+            catches: Vec::new(),
+            inside: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    // the debugee has to be resumed with PTRACE_SYSCALL instead of PTRACE_CONT
+    pub fn active(&self) -> bool {
+        self.trace || !self.catches.is_empty()
+    }
+
+    pub fn add_catch(&mut self, id: usize, numbers: Vec<u64>) {
+        self.catches.push(SyscallCatch {
+            id,
+            numbers,
+            hit_count: 0,
+        });
+    }
+
+    pub fn remove_catch(&mut self, id: usize) -> bool {
+        let before = self.catches.len();
+        self.catches.retain(|c| c.id != id);
+        before != self.catches.len()
+    }
+
+    fn catch_for(&mut self, nr: u64) -> Option<&mut SyscallCatch> {
+        self.catches
+            .iter_mut()
+            .find(|c| c.numbers.is_empty() || c.numbers.contains(&nr))
+    }
+}
+
+impl Default for SyscallTracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn syscall_name(nr: u64) -> Option<&'static str> {
+    SYSCALLS
+        .iter()
+        .find(|(n, _)| *n == nr)
+        .map(|(_, name)| *name)
+}
+
+// "write", "sys_write" or the number
+pub fn parse_syscall(input: &str) -> Result<u64> {
+    if let Ok(nr) = input.parse::<u64>() {
+        return Ok(nr);
+    }
+    let name = input.strip_prefix("sys_").unwrap_or(input);
+    match SYSCALLS.iter().find(|(_, n)| *n == name) {
+        Some((nr, _)) => Ok(*nr),
+        None => bail!("Unknown syscall '{}'", input),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Arg {
+    Int,
+    Hex,
+    Fd,
+    DirFd,
+    Path,
+    // buffer whose length is the next argument
+    Buf,
+    OpenFlags,
+    Mode,
+    Prot,
+    MapFlags,
+    AtFlags,
+    Signal,
+}
+
+// how the arguments of common syscalls are printed, others get three hex
+// values
+fn signature(name: &str) -> &'static [Arg] {
+    use Arg::*;
+    match name {
+        "read" | "pread64" => &[Fd, Hex, Int],
+        "write" | "pwrite64" => &[Fd, Buf, Int],
+        "open" => &[Path, OpenFlags, Mode],
+        "openat" => &[DirFd, Path, OpenFlags, Mode],
+        "close" | "fsync" | "dup" => &[Fd],
+        "dup2" | "dup3" => &[Fd, Fd],
+        "stat" | "lstat" => &[Path, Hex],
+        "fstat" => &[Fd, Hex],
+        "newfstatat" => &[DirFd, Path, Hex, AtFlags],
+        "statx" => &[DirFd, Path, AtFlags, Hex, Hex],
+        "access" => &[Path, Int],
+        "faccessat" | "faccessat2" => &[DirFd, Path, Int],
+        "lseek" => &[Fd, Int, Int],
+        "mmap" => &[Hex, Int, Prot, MapFlags, Fd, Hex],
+        "mprotect" => &[Hex, Int, Prot],
+        "munmap" => &[Hex, Int],
+        "brk" => &[Hex],
+        "ioctl" | "fcntl" => &[Fd, Hex, Hex],
+        "execve" => &[Path, Hex, Hex],
+        "exit" | "exit_group" => &[Int],
+        "kill" => &[Int, Signal],
+        "tgkill" => &[Int, Int, Signal],
+        "rt_sigaction" | "rt_sigprocmask" => &[Signal, Hex, Hex],
+        "wait4" => &[Int, Hex, Hex, Hex],
+        "unlink" | "rmdir" | "chdir" => &[Path],
+        "unlinkat" => &[DirFd, Path, AtFlags],
+        "mkdir" => &[Path, Mode],
+        "readlink" => &[Path, Hex, Int],
+        "readlinkat" => &[DirFd, Path, Hex, Int],
+        "socket" => &[Int, Int, Int],
+        "connect" | "bind" => &[Fd, Hex, Int],
+        "getrandom" => &[Hex, Int, Hex],
+        "getpid" | "gettid" | "getppid" | "fork" | "vfork" | "sched_yield" => &[],
+        _ => &[Hex, Hex, Hex],
+    }
+}
+
+pub trait SyscallTracing {
+    fn handle_syscall_stop(&mut self, tid: Pid);
+    fn note_syscall_stop(&mut self, tid: Pid) -> Option<(bool, u64)>;
+    fn queue_syscall_stop(&mut self, tid: Pid);
+    fn report_pending_catch(&mut self) -> bool;
+}
+
+impl SyscallTracing for Debugger {
+    // a PTRACE_SYSCALL stop reported by resume_and_wait, prints the call in
+    // strace mode and stops on catchpoints
+    fn handle_syscall_stop(&mut self, tid: Pid) {
+        if let Some(thread) = self.process.thread_mut(tid) {
+            thread.running = false;
+        }
+        let Some((entry, nr)) = self.note_syscall_stop(tid) else {
+            let _ = self.resume_thread(tid, None);
+            return;
+        };
+
+        let Some(catch) = self.syscalls.catch_for(nr) else {
+            if let Err(e) = self.resume_thread(tid, None) {
+                println!("Failed to resume thread {}: {}", tid, e);
+            }
+            return;
+        };
+        catch.hit_count += 1;
+        let id = catch.id;
+
+        self.stop_all_threads();
+        self.report_catch(tid, id, entry, nr);
+    }
+
+    // entry or exit and the syscall number, also used by stop_all_threads so
+    // no stop goes unprinted
+    fn note_syscall_stop(&mut self, tid: Pid) -> Option<(bool, u64)> {
+        let regs = self.read_regs(tid).ok()?;
+        let nr = regs.orig_rax;
+        let entry = match self.target.syscall_entry(tid) {
+            Some(entry) => entry,
+            // older kernels, entries and exits come in pairs
+            None => !self.syscalls.inside.contains_key(&tid),
+        };
+        if entry {
+            let call = format_call(self.target.as_ref(), nr, &regs);
+            if self.syscalls.trace && matches!(syscall_name(nr), Some("exit" | "exit_group")) {
+                // never returns
+                println!("{}{} = ?", thread_prefix(self, tid), call);
+            }
+            self.syscalls.inside.insert(tid, call);
+        } else {
+            let call = self.syscalls.inside.remove(&tid).unwrap_or_else(|| {
+                format!("<... {} resumed>", syscall_name(nr).unwrap_or("syscall"))
+            });
+            if self.syscalls.trace {
+                println!(
+                    "{}{} = {}",
+                    thread_prefix(self, tid),
+                    call,
+                    format_return(nr, regs.rax)
+                );
+            }
+        }
+        Some((entry, nr))
+    }
+
+    // a syscall stop that stop_all_threads took in, kept if it is caught
+    fn queue_syscall_stop(&mut self, tid: Pid) {
+        let Some((entry, nr)) = self.note_syscall_stop(tid) else {
+            return;
+        };
+        if self.syscalls.catch_for(nr).is_some() {
+            self.syscalls.pending.push((tid, entry, nr));
+        }
+    }
+
+    // stops on the first queued catch whose thread and catchpoint are still
+    // there, true if the debugee stays stopped
+    fn report_pending_catch(&mut self) -> bool {
+        while !self.syscalls.pending.is_empty() {
+            let (tid, entry, nr) = self.syscalls.pending.remove(0);
+            if self.process.thread(tid).is_none() {
+                continue;
+            }
+            let Some(catch) = self.syscalls.catch_for(nr) else {
+                continue;
+            };
+            catch.hit_count += 1;
+            let id = catch.id;
+            self.report_catch(tid, id, entry, nr);
+            return true;
+        }
+        false
+    }
+}
+
+impl Debugger {
+    fn report_catch(&mut self, tid: Pid, id: usize, entry: bool, nr: u64) {
+        self.cancel_step_plan();
+        if tid != self.process.tid {
+            println!("[Switching to thread {}]", tid);
+        }
+        self.process.tid = tid;
+        let name = syscall_name(nr).unwrap_or("?");
+        if entry {
+            println!("Catchpoint {} (call to syscall {} ({}))", id, name, nr);
+        } else {
+            println!(
+                "Catchpoint {} (returned from syscall {} ({}))",
+                id, name, nr
+            );
+        }
+        if let Ok(regs) = self.read_regs(tid) {
+            println!(
+                "Process stopped at addr: 0x{:x} ({})",
+                regs.rip,
+                self.describe_location(regs.rip)
+            );
+        }
+        self.print_context();
+        self.state = DebuggerState::Interactive;
+    }
+}
+
+fn thread_prefix(debugger: &Debugger, tid: Pid) -> String {
+    if debugger.process.threads.len() > 1 {
+        format!("[pid {}] ", tid)
+    } else {
+        String::new()
+    }
+}
+
+fn format_call(target: &dyn Target, nr: u64, regs: &user_regs_struct) -> String {
+    let name = syscall_name(nr)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("syscall_{}", nr));
+    let values = [regs.rdi, regs.rsi, regs.rdx, regs.r10, regs.r8, regs.r9];
+    let args: Vec<String> = signature(&name)
+        .iter()
+        .zip(values.iter().enumerate())
+        .map(|(arg, (i, value))| format_arg(target, *arg, *value, values.get(i + 1).copied()))
+        .collect();
+    format!("{}({})", name, args.join(", "))
+}
+
+fn format_arg(target: &dyn Target, arg: Arg, value: u64, next: Option<u64>) -> String {
+    match arg {
+        Arg::Int => (value as i64).to_string(),
+        Arg::Hex => format!("0x{:x}", value),
+        Arg::Fd => (value as i32).to_string(),
+        Arg::DirFd if value as i32 == libc::AT_FDCWD => "AT_FDCWD".to_string(),
+        Arg::DirFd => (value as i32).to_string(),
+        Arg::Path => read_string(target, value, MAX_STRING),
+        Arg::Buf => read_string(
+            target,
+            value,
+            next.unwrap_or(0).min(MAX_STRING as u64) as usize,
+        ),
+        Arg::OpenFlags => open_flags(value),
+        Arg::Mode => format!("0{:o}", value),
+        Arg::Prot => flags(
+            value,
+            &[
+                (libc::PROT_READ as u64, "PROT_READ"),
+                (libc::PROT_WRITE as u64, "PROT_WRITE"),
+                (libc::PROT_EXEC as u64, "PROT_EXEC"),
+            ],
+            "PROT_NONE",
+        ),
+        Arg::MapFlags => flags(
+            value,
+            &[
+                (libc::MAP_SHARED as u64, "MAP_SHARED"),
+                (libc::MAP_PRIVATE as u64, "MAP_PRIVATE"),
+                (libc::MAP_FIXED as u64, "MAP_FIXED"),
+                (libc::MAP_ANONYMOUS as u64, "MAP_ANONYMOUS"),
+                (libc::MAP_DENYWRITE as u64, "MAP_DENYWRITE"),
+                (libc::MAP_NORESERVE as u64, "MAP_NORESERVE"),
+                (libc::MAP_STACK as u64, "MAP_STACK"),
+            ],
+            "0",
+        ),
+        Arg::AtFlags => flags(
+            value,
+            &[
+                (libc::AT_SYMLINK_NOFOLLOW as u64, "AT_SYMLINK_NOFOLLOW"),
+                (libc::AT_REMOVEDIR as u64, "AT_REMOVEDIR"),
+                (libc::AT_EMPTY_PATH as u64, "AT_EMPTY_PATH"),
+            ],
+            "0",
+        ),
+        Arg::Signal => match nix::sys::signal::Signal::try_from(value as i32) {
+            Ok(signal) => signal.as_str().to_string(),
+            Err(_) => value.to_string(),
+        },
+    }
+}
+
+// "OK_FLAG|OTHER|0x40" for the known bits, the rest in hex
+fn flags(value: u64, known: &[(u64, &str)], zero: &str) -> String {
+    if value == 0 {
+        return zero.to_string();
+    }
+    let mut names = Vec::new();
+    let mut rest = value;
+    for (bit, name) in known {
+        if value & bit == *bit {
+            names.push(name.to_string());
+            rest &= !bit;
+        }
+    }
+    if rest != 0 {
+        names.push(format!("0x{:x}", rest));
+    }
+    names.join("|")
+}
+
+fn open_flags(value: u64) -> String {
+    let access = match value as i32 & libc::O_ACCMODE {
+        libc::O_RDONLY => "O_RDONLY",
+        libc::O_WRONLY => "O_WRONLY",
+        _ => "O_RDWR",
+    };
+    let rest = flags(
+        value & !(libc::O_ACCMODE as u64),
+        &[
+            (libc::O_CREAT as u64, "O_CREAT"),
+            (libc::O_EXCL as u64, "O_EXCL"),
+            (libc::O_NOCTTY as u64, "O_NOCTTY"),
+            (libc::O_TRUNC as u64, "O_TRUNC"),
+            (libc::O_APPEND as u64, "O_APPEND"),
+            (libc::O_NONBLOCK as u64, "O_NONBLOCK"),
+            (libc::O_DIRECTORY as u64, "O_DIRECTORY"),
+            (libc::O_NOFOLLOW as u64, "O_NOFOLLOW"),
+            (libc::O_CLOEXEC as u64, "O_CLOEXEC"),
+        ],
+        "",
+    );
+    if rest.is_empty() {
+        access.to_string()
+    } else {
+        format!("{}|{}", access, rest)
+    }
+}
+
+// "-1 ENOENT (No such file or directory)" for errors, pointers in hex
+fn format_return(nr: u64, rax: u64) -> String {
+    let value = rax as i64;
+    if (-4095..0).contains(&value) {
+        let errno = Errno::from_raw(-value as i32);
+        return format!("-1 {:?} ({})", errno, errno.desc());
+    }
+    match syscall_name(nr) {
+        Some("mmap" | "brk" | "mremap") => format!("0x{:x}", rax),
+        _ => value.to_string(),
+    }
+}
+
+// quoted and escaped like strace, "..." when cut off
+fn read_string(target: &dyn Target, addr: u64, max: usize) -> String {
+    if addr == 0 {
+        return "NULL".to_string();
+    }
+    let mut bytes = Vec::new();
+    let mut complete = false;
+    'words: while bytes.len() < max {
+        let mut word = [0u8; 8];
+        if target
+            .read_memory(addr + bytes.len() as u64, &mut word)
+            .is_err()
+        {
+            return format!("0x{:x}", addr);
+        }
+        for byte in word {
+            if byte == 0 {
+                complete = true;
+                break 'words;
+            }
+            bytes.push(byte);
+        }
+    }
+    let cut = bytes.len() > max || (!complete && bytes.len() == max);
+    bytes.truncate(max);
+    let text: String = bytes.iter().flat_map(|b| escape(*b)).collect();
+    format!("\"{}\"{}", text, if cut { "..." } else { "" })
+}
+
+fn escape(byte: u8) -> Vec<char> {
+    match byte {
+        b'\n' => vec!['\\', 'n'],
+        b'\t' => vec!['\\', 't'],
+        b'"' => vec!['\\', '"'],
+        b'\\' => vec!['\\', '\\'],
+        0x20..=0x7e => vec![byte as char],
+        _ => format!("\\x{:02x}", byte).chars().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syscall_names() {
+        assert_eq!(syscall_name(0), Some("read"));
+        assert_eq!(syscall_name(231), Some("exit_group"));
+        assert_eq!(parse_syscall("openat").unwrap(), 257);
+        assert_eq!(parse_syscall("sys_write").unwrap(), 1);
+        assert_eq!(parse_syscall("60").unwrap(), 60);
+        assert!(parse_syscall("frobnicate").is_err());
+    }
+
+    #[test]
+    fn test_formatting() {
+        assert_eq!(open_flags(0), "O_RDONLY");
+        assert_eq!(
+            open_flags((libc::O_WRONLY | libc::O_CREAT | libc::O_CLOEXEC) as u64),
+            "O_WRONLY|O_CREAT|O_CLOEXEC"
+        );
+        assert_eq!(
+            format_return(2, (-2i64) as u64),
+            "-1 ENOENT (No such file or directory)"
+        );
+        assert_eq!(format_return(9, 0x7fff0000), "0x7fff0000");
+        assert_eq!(format_return(1, 5), "5");
+    }
+}
@@ -0,0 +1,395 @@
+use crate::core::corefile::CoreFile;
+use crate::core::memory::read_process_memory;
+use crate::core::threads::SI_KERNEL;
+use anyhow::{bail, Result};
+use libc::user_regs_struct;
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use std::fmt::Debug;
+use std::mem::offset_of;
+
+// what the debugger drives, a traced process, a core file or a remote stub.
+// stops are reported as wait statuses, whatever the backend
+pub trait Target: Debug {
+    fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<()>;
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<()>;
+    fn read_regs(&self, tid: Pid) -> Result<user_regs_struct>;
+    fn write_regs(&mut self, tid: Pid, regs: user_regs_struct) -> Result<()>;
+    fn resume(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()>;
+    fn step(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()>;
+
+    // runs until the next syscall entry or exit
+    fn resume_syscall(&mut self, _tid: Pid, _signal: Option<Signal>) -> Result<()> {
+        bail!("Syscall stops are not supported by this target")
+    }
+
+    // whether a syscall stop of `tid` is the entry, None if the target can
+    // not tell it from the exit
+    fn syscall_entry(&self, _tid: Pid) -> Option<bool> {
+        None
+    }
+
+    // next stop of `tid`, or of any thread
+    fn wait(&mut self, tid: Option<Pid>) -> Result<WaitStatus>;
+
+    // whether the target can be resumed at all
+    fn is_live(&self) -> bool {
+        true
+    }
+
+    fn core(&self) -> Option<&CoreFile> {
+        None
+    }
+
+    // address of the breakpoint `tid` just stopped at, None for other traps
+    fn breakpoint_address(&self, _tid: Pid) -> Option<u64> {
+        None
+    }
+
+    // targets that keep breakpoints themselves return true, otherwise the
+    // int3 is written to memory
+    fn insert_breakpoint(&mut self, _addr: u64) -> Result<bool> {
+        Ok(false)
+    }
+
+    fn remove_breakpoint(&mut self, _addr: u64) -> Result<bool> {
+        Ok(false)
+    }
+
+    // DR0-DR7 of a thread, hardware breakpoints and watchpoints are set up
+    // and reported through them
+    fn read_debug_reg(&self, _tid: Pid, _index: usize) -> Result<u64> {
+        bail!("Hardware breakpoints are not supported by this target")
+    }
+
+    fn write_debug_reg(&mut self, _tid: Pid, _index: usize, _value: u64) -> Result<()> {
+        bail!("Hardware breakpoints are not supported by this target")
+    }
+
+    // what the kernel reported about the signal `tid` stopped with
+    fn siginfo(&self, _tid: Pid) -> Result<libc::siginfo_t> {
+        bail!("Signal details are not available from this target")
+    }
+
+    // the new pid or tid that comes with a fork or clone event stop
+    fn event_message(&self, _tid: Pid) -> Result<u64> {
+        bail!("Event stops are not supported by this target")
+    }
+
+    // lets `tid` run on its own, delivering `signal`
+    fn detach(&mut self, _tid: Pid, _signal: Option<Signal>) -> Result<()> {
+        bail!("Detaching is not supported by this target")
+    }
+
+    // threads of a target that halts all of them on every stop by itself,
+    // None when they are tracked and stopped by the debugger
+    fn thread_list(&mut self) -> Option<Vec<Pid>> {
+        None
+    }
+
+    fn is_remote(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug)]
+pub struct PtraceTarget {
+    pub pid: Pid,
+}
+
+impl PtraceTarget {
+    pub fn new(pid: Pid) -> Self {
+        PtraceTarget { pid }
+    }
+}
+
+impl Target for PtraceTarget {
+    fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        let read = read_process_memory(self.pid, addr as usize, buf)?;
+        if read < buf.len() {
+            bail!("Cannot access memory at 0x{:x}", addr + read as u64);
+        }
+        Ok(())
+    }
+
+    // word sized pokes, the partial words at both ends are merged with what
+    // is in memory
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        let mut done = 0;
+        while done < data.len() {
+            let at = addr + done as u64;
+            let aligned = at & !0x7;
+            let offset = (at - aligned) as usize;
+            let len = (8 - offset).min(data.len() - done);
+            let mut word = if offset == 0 && len == 8 {
+                [0u8; 8]
+            } else {
+                (ptrace::read(self.pid, aligned as ptrace::AddressType)? as u64).to_le_bytes()
+            };
+            word[offset..offset + len].copy_from_slice(&data[done..done + len]);
+            ptrace::write(
+                self.pid,
+                aligned as ptrace::AddressType,
+                u64::from_le_bytes(word) as i64,
+            )?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn read_regs(&self, tid: Pid) -> Result<user_regs_struct> {
+        Ok(ptrace::getregs(tid)?)
+    }
+
+    fn write_regs(&mut self, tid: Pid, regs: user_regs_struct) -> Result<()> {
+        Ok(ptrace::setregs(tid, regs)?)
+    }
+
+    fn resume(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()> {
+        Ok(ptrace::cont(tid, signal)?)
+    }
+
+    fn step(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()> {
+        Ok(ptrace::step(tid, signal)?)
+    }
+
+    fn resume_syscall(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()> {
+        Ok(ptrace::syscall(tid, signal)?)
+    }
+
+    // PTRACE_GET_SYSCALL_INFO, op is the first byte
+    fn syscall_entry(&self, tid: Pid) -> Option<bool> {
+        let mut info = [0u8; 88];
+        let ret = unsafe {
+            libc::ptrace(
+                libc::PTRACE_GET_SYSCALL_INFO,
+                tid.as_raw(),
+                info.len(),
+                info.as_mut_ptr(),
+            )
+        };
+        (ret > 0).then_some(info[0] == libc::PTRACE_SYSCALL_INFO_ENTRY)
+    }
+
+    fn wait(&mut self, tid: Option<Pid>) -> Result<WaitStatus> {
+        Ok(waitpid(tid, Some(WaitPidFlag::__WALL))?)
+    }
+
+    // only an int3 leaves rip behind a breakpoint, a single step may just
+    // happen to land there
+    fn breakpoint_address(&self, tid: Pid) -> Option<u64> {
+        let info = self.siginfo(tid).ok()?;
+        if info.si_code != SI_KERNEL {
+            return None;
+        }
+        Some(ptrace::getregs(tid).ok()?.rip - 1)
+    }
+
+    fn read_debug_reg(&self, tid: Pid, index: usize) -> Result<u64> {
+        Ok(ptrace::read_user(tid, debug_reg_offset(index))? as u64)
+    }
+
+    fn write_debug_reg(&mut self, tid: Pid, index: usize, value: u64) -> Result<()> {
+        ptrace::write_user(tid, debug_reg_offset(index), value as libc::c_long)?;
+        Ok(())
+    }
+
+    fn siginfo(&self, tid: Pid) -> Result<libc::siginfo_t> {
+        Ok(ptrace::getsiginfo(tid)?)
+    }
+
+    fn event_message(&self, tid: Pid) -> Result<u64> {
+        Ok(ptrace::getevent(tid)? as u64)
+    }
+
+    fn detach(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()> {
+        Ok(ptrace::detach(tid, signal)?)
+    }
+}
+
+fn debug_reg_offset(index: usize) -> ptrace::AddressType {
+    (offset_of!(libc::user, u_debugreg) + index * 8) as ptrace::AddressType
+}
+
+// a core file can only be looked at
+impl Target for CoreFile {
+    fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        CoreFile::read_memory(self, addr, buf)
+    }
+
+    fn write_memory(&mut self, _addr: u64, _data: &[u8]) -> Result<()> {
+        bail!("The program is not being run, use `run` to start it")
+    }
+
+    fn read_regs(&self, tid: Pid) -> Result<user_regs_struct> {
+        self.thread(tid)
+            .map(|t| t.regs)
+            .ok_or_else(|| anyhow::anyhow!("No thread {}", tid))
+    }
+
+    fn write_regs(&mut self, _tid: Pid, _regs: user_regs_struct) -> Result<()> {
+        bail!("The program is not being run, use `run` to start it")
+    }
+
+    fn resume(&mut self, _tid: Pid, _signal: Option<Signal>) -> Result<()> {
+        bail!("The program is not being run, use `run` to start it")
+    }
+
+    fn step(&mut self, _tid: Pid, _signal: Option<Signal>) -> Result<()> {
+        bail!("The program is not being run, use `run` to start it")
+    }
+
+    fn wait(&mut self, _tid: Option<Pid>) -> Result<WaitStatus> {
+        bail!("The program is not being run, use `run` to start it")
+    }
+
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    fn core(&self) -> Option<&CoreFile> {
+        Some(self)
+    }
+}
+
+// memory and registers only, for unit tests of code above the backend. A
+// step moves rip by one byte, a resume runs on to the next int3
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MockTarget {
+    pub memory: std::collections::HashMap<u64, u8>,
+    pub regs: std::collections::HashMap<Pid, user_regs_struct>,
+    // thread that ran last and whether it stopped at an int3
+    last: Option<Pid>,
+    at_int3: bool,
+}
+
+#[cfg(test)]
+impl MockTarget {
+    // `code` at `addr` and one thread about to run it
+    pub fn with_code(tid: Pid, addr: u64, code: &[u8]) -> Self {
+        let mut target = MockTarget::default();
+        target.write_memory(addr, code).unwrap();
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = addr;
+        regs.rsp = 0x7ff0;
+        target.regs.insert(tid, regs);
+        target
+    }
+}
+
+// a debugger on the symbols of /bin/ls without running it, `tid` is the only
+// thread
+#[cfg(test)]
+impl crate::core::Debugger {
+    pub fn with_mock(target: MockTarget, tid: Pid) -> Self {
+        let process = crate::core::process::Process::remote(tid, &[tid]);
+        let launch = crate::core::launch::LaunchConfig::new("/bin/ls".to_string(), Vec::new());
+        let mut debugger = Self::with_process(process, launch, String::new()).unwrap();
+        debugger.target = Box::new(target);
+        debugger.context.set("all", false).unwrap();
+        debugger
+    }
+}
+
+#[cfg(test)]
+impl Target for MockTarget {
+    fn read_memory(&self, addr: u64, buf: &mut [u8]) -> Result<()> {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let at = addr + i as u64;
+            *byte = *self
+                .memory
+                .get(&at)
+                .ok_or_else(|| anyhow::anyhow!("Cannot access memory at 0x{:x}", at))?;
+        }
+        Ok(())
+    }
+
+    fn write_memory(&mut self, addr: u64, data: &[u8]) -> Result<()> {
+        for (i, byte) in data.iter().enumerate() {
+            self.memory.insert(addr + i as u64, *byte);
+        }
+        Ok(())
+    }
+
+    fn read_regs(&self, tid: Pid) -> Result<user_regs_struct> {
+        self.regs
+            .get(&tid)
+            .copied()
+            .ok_or_else(|| anyhow::anyhow!("No thread {}", tid))
+    }
+
+    fn write_regs(&mut self, tid: Pid, regs: user_regs_struct) -> Result<()> {
+        self.regs.insert(tid, regs);
+        Ok(())
+    }
+
+    fn resume(&mut self, tid: Pid, _signal: Option<Signal>) -> Result<()> {
+        let Some(regs) = self.regs.get_mut(&tid) else {
+            bail!("No thread {}", tid);
+        };
+        loop {
+            match self.memory.get(&regs.rip) {
+                Some(0xcc) => break,
+                Some(_) => regs.rip += 1,
+                None => bail!("Cannot access memory at 0x{:x}", regs.rip),
+            }
+        }
+        regs.rip += 1;
+        self.last = Some(tid);
+        self.at_int3 = true;
+        Ok(())
+    }
+
+    fn step(&mut self, tid: Pid, _signal: Option<Signal>) -> Result<()> {
+        if let Some(regs) = self.regs.get_mut(&tid) {
+            self.at_int3 = self.memory.get(&regs.rip) == Some(&0xcc);
+            regs.rip += 1;
+        }
+        self.last = Some(tid);
+        Ok(())
+    }
+
+    fn wait(&mut self, tid: Option<Pid>) -> Result<WaitStatus> {
+        let tid = tid.or(self.last).unwrap_or_else(|| Pid::from_raw(1));
+        Ok(WaitStatus::Stopped(tid, Signal::SIGTRAP))
+    }
+
+    fn breakpoint_address(&self, tid: Pid) -> Option<u64> {
+        let rip = self.regs.get(&tid)?.rip;
+        (self.at_int3 && self.last == Some(tid)).then_some(rip - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::breakpoint::Breakpoint;
+
+    #[test]
+    fn test_breakpoints_on_mock_target() {
+        let mut target = MockTarget::default();
+        target
+            .write_memory(0x1000, &[0x55, 0x48, 0x89, 0xe5])
+            .unwrap();
+        let mut breakpoint = Breakpoint::new();
+
+        let id = breakpoint.set_breakpoint(0x1001, &mut target).unwrap();
+        let mut code = [0u8; 4];
+        target.read_memory(0x1000, &mut code).unwrap();
+        assert_eq!(code, [0x55, 0xcc, 0x89, 0xe5]);
+        assert_eq!(breakpoint.get(0x1001).unwrap().original_byte, 0x48);
+
+        breakpoint.disable(id, &mut target).unwrap();
+        target.read_memory(0x1000, &mut code).unwrap();
+        assert_eq!(code, [0x55, 0x48, 0x89, 0xe5]);
+
+        breakpoint.enable(id, &mut target).unwrap();
+        breakpoint.remove_by_id(id, &mut target).unwrap();
+        target.read_memory(0x1000, &mut code).unwrap();
+        assert_eq!(code, [0x55, 0x48, 0x89, 0xe5]);
+        assert!(breakpoint.set_breakpoint(0x2000, &mut target).is_err());
+    }
+}
@@ -0,0 +1,293 @@
+use crate::core::fork::is_thread_of;
+use crate::core::stepping::Stepping;
+use crate::core::syscalls::SyscallTracing;
+use crate::core::*;
+use anyhow::{bail, Result};
+use log::debug;
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+
+// si_code of a SIGTRAP raised by an int3
+pub const SI_KERNEL: i32 = 0x80;
+
+pub trait ThreadControl {
+    fn stop_all_threads(&mut self);
+    fn resume_all_threads(&mut self) -> Result<()>;
+    fn resume_thread(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()>;
+    fn step_thread(&mut self, tid: Pid) -> Result<()>;
+    fn start_step(&mut self, tid: Pid) -> Result<()>;
+    fn handle_thread_event(&mut self, status: &WaitStatus) -> bool;
+    fn select_thread(&mut self, tid: Pid) -> Result<()>;
+    fn print_threads(&self);
+}
+
+impl ThreadControl for Debugger {
+    // all-stop: once one thread reports a stop every other thread is halted
+    // with a SIGSTOP before the user gets the prompt
+    fn stop_all_threads(&mut self) {
+        // a remote stub has already stopped everything
+        if let Some(tids) = self.target.thread_list() {
+            self.process.threads.retain(|t| tids.contains(&t.tid));
+            for tid in tids {
+                let thread = self.process.add_thread(tid);
+                thread.running = false;
+                thread.stepping = false;
+            }
+            return;
+        }
+        let pid = self.process.pid;
+        for thread in self
+            .process
+            .threads
+            .iter_mut()
+            .filter(|t| t.running && !t.pending_stop)
+        {
+            unsafe {
+                libc::syscall(
+                    libc::SYS_tgkill,
+                    pid.as_raw(),
+                    thread.tid.as_raw(),
+                    libc::SIGSTOP,
+                );
+            }
+            thread.pending_stop = true;
+        }
+
+        while let Some(tid) = self
+            .process
+            .threads
+            .iter()
+            .find(|t| t.running)
+            .map(|t| t.tid)
+        {
+            let status = self.target.wait(Some(tid));
+            debug!("[STOP ALL] {}: {:?}", tid, status);
+            match status {
+                Ok(WaitStatus::Stopped(_, signal)) => {
+                    let thread = self.process.thread_mut(tid).unwrap();
+                    thread.running = false;
+                    thread.stepping = false;
+                    match signal {
+                        Signal::SIGSTOP if thread.pending_stop => thread.pending_stop = false,
+                        Signal::SIGTRAP => self.requeue_breakpoint_hit(tid),
+                        other => thread.pending_signal = Some(other),
+                    }
+                }
+                Ok(WaitStatus::PtraceEvent(_, _, event)) => {
+                    self.process.thread_mut(tid).unwrap().running = false;
+                    if event == libc::PTRACE_EVENT_CLONE {
+                        self.add_cloned_thread(tid);
+                    }
+                }
+                Ok(WaitStatus::PtraceSyscall(_)) => {
+                    self.process.thread_mut(tid).unwrap().running = false;
+                    self.queue_syscall_stop(tid);
+                }
+                Ok(WaitStatus::Exited(..)) | Ok(WaitStatus::Signaled(..)) | Err(_) => {
+                    self.process.remove_thread(tid);
+                }
+                Ok(_) => self.process.thread_mut(tid).unwrap().running = false,
+            }
+        }
+    }
+
+    fn resume_all_threads(&mut self) -> Result<()> {
+        let stopped: Vec<Pid> = self
+            .process
+            .threads
+            .iter()
+            .filter(|t| !t.running)
+            .map(|t| t.tid)
+            .collect();
+        for tid in stopped {
+            // signals that arrived while the thread was being stopped
+            let signal = self
+                .process
+                .thread_mut(tid)
+                .and_then(|t| t.pending_signal.take())
+                .filter(|s| self.signals.get(*s).pass);
+            self.resume_thread(tid, signal)?;
+        }
+        Ok(())
+    }
+
+    // continues the thread, or repeats its single step if one is in flight
+    fn resume_thread(&mut self, tid: Pid, signal: Option<Signal>) -> Result<()> {
+        let syscalls = self.syscalls.active();
+        let thread = self.process.add_thread(tid);
+        let stepping = thread.stepping;
+        self.clear_watch_status(tid);
+        if stepping {
+            self.target.step(tid, signal)?;
+        } else if syscalls {
+            self.target.resume_syscall(tid, signal)?;
+        } else {
+            self.target.resume(tid, signal)?;
+        }
+        self.process.add_thread(tid).running = true;
+        Ok(())
+    }
+
+    // synchronous single step of one thread, the others stay stopped
+    fn step_thread(&mut self, tid: Pid) -> Result<()> {
+        loop {
+            self.clear_watch_status(tid);
+            self.target.step(tid, None)?;
+            match self.target.wait(Some(tid))? {
+                WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                    // the stepped instruction may have triggered a watchpoint,
+                    // the caller reports it with report_step_watch_hit
+                    if !self.watchpoints.is_empty() {
+                        if let Some(hit) = self
+                            .watchpoints
+                            .check_hit(self.target.as_mut(), tid)
+                            .unwrap_or(None)
+                        {
+                            self.watchpoints.pending = Some((tid, hit));
+                        }
+                    }
+                    return Ok(());
+                }
+                // a SIGSTOP of ours or a signal got in before the step
+                WaitStatus::Stopped(_, signal) => {
+                    let thread = self.process.add_thread(tid);
+                    if signal == Signal::SIGSTOP && thread.pending_stop {
+                        thread.pending_stop = false;
+                    } else {
+                        thread.pending_signal = Some(signal);
+                    }
+                }
+                other => bail!("Unexpected stop while stepping: {:?}", other),
+            }
+        }
+    }
+
+    // single step that is reported through resume_and_wait
+    fn start_step(&mut self, tid: Pid) -> Result<()> {
+        self.clear_watch_status(tid);
+        self.target.step(tid, None)?;
+        let thread = self.process.add_thread(tid);
+        thread.running = true;
+        thread.stepping = true;
+        Ok(())
+    }
+
+    // bookkeeping for stops that only concern the thread list, returns true
+    // if the event was consumed and the debugee keeps running
+    fn handle_thread_event(&mut self, status: &WaitStatus) -> bool {
+        match *status {
+            WaitStatus::PtraceEvent(tid, _, event) if event == libc::PTRACE_EVENT_CLONE => {
+                self.add_cloned_thread(tid);
+                let _ = self.resume_thread(tid, None);
+                true
+            }
+            WaitStatus::Stopped(tid, Signal::SIGSTOP)
+                if self.process.thread(tid).is_none() && !is_thread_of(tid, self.process.pid) =>
+            {
+                // a forked child that stopped before its parent reported the
+                // fork, picked up by handle_fork
+                self.forks.early_stops.push(tid);
+                true
+            }
+            WaitStatus::Stopped(tid, Signal::SIGSTOP)
+                if self.process.thread(tid).is_none_or(|t| t.pending_stop) =>
+            {
+                // initial stop of a new thread or a SIGSTOP of stop_all_threads
+                // that arrived after the thread stopped for something else
+                let thread = self.process.add_thread(tid);
+                thread.pending_stop = false;
+                if !self.watchpoints.is_empty() {
+                    if let Err(e) = self.watchpoints.apply(self.target.as_mut(), &[tid]) {
+                        println!(
+                            "Failed to set hardware watchpoints in thread {}: {}",
+                            tid, e
+                        );
+                    }
+                }
+                let _ = self.resume_thread(tid, None);
+                true
+            }
+            WaitStatus::Exited(tid, _) | WaitStatus::Signaled(tid, _, _)
+                if tid != self.process.pid =>
+            {
+                println!("[Thread {} exited]", tid);
+                if self.step_plan.as_ref().is_some_and(|p| p.tid == tid) {
+                    self.cancel_step_plan();
+                }
+                self.process.remove_thread(tid);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn select_thread(&mut self, tid: Pid) -> Result<()> {
+        if self.process.thread(tid).is_none() {
+            bail!("No thread {}", tid);
+        }
+        self.process.tid = tid;
+        let rip = self.read_regs(tid)?.rip;
+        println!(
+            "[Switching to thread {}] 0x{:x} in {}",
+            tid,
+            rip,
+            self.describe_location(rip)
+        );
+        Ok(())
+    }
+
+    fn print_threads(&self) {
+        for thread in &self.process.threads {
+            let marker = if thread.tid == self.process.tid {
+                "*"
+            } else {
+                " "
+            };
+            let location = match self.read_regs(thread.tid) {
+                Ok(regs) => format!("0x{:x} in {}", regs.rip, self.describe_location(regs.rip)),
+                Err(e) => format!("<{}>", e),
+            };
+            println!("{} Thread {:<8} {}", marker, thread.tid, location);
+        }
+    }
+}
+
+impl Debugger {
+    // the new thread starts with a SIGSTOP that is consumed by
+    // handle_thread_event or stop_all_threads
+    fn add_cloned_thread(&mut self, parent: Pid) {
+        let Ok(new_tid) = self.target.event_message(parent) else {
+            return;
+        };
+        let new_tid = Pid::from_raw(new_tid as i32);
+        if self.process.thread(new_tid).is_none() {
+            println!("[New Thread {}]", new_tid);
+            let thread = self.process.add_thread(new_tid);
+            thread.running = true;
+            thread.pending_stop = true;
+        }
+    }
+
+    fn clear_watch_status(&mut self, tid: Pid) {
+        if !self.watchpoints.is_empty() {
+            let _ = self.watchpoints.clear_status(self.target.as_mut(), tid);
+        }
+    }
+
+    // a thread that ran into a breakpoint while we stopped it is moved back
+    // onto the int3, so it reports the hit again once resumed
+    fn requeue_breakpoint_hit(&mut self, tid: Pid) {
+        let from_int3 = self
+            .target
+            .siginfo(tid)
+            .is_ok_and(|info| info.si_code == SI_KERNEL);
+        let Ok(mut regs) = self.read_regs(tid) else {
+            return;
+        };
+        if from_int3 && self.breakpoint.is_breakpoint(regs.rip - 1) {
+            regs.rip -= 1;
+            let _ = self.write_regs(tid, regs);
+        }
+    }
+}
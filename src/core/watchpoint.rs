@@ -0,0 +1,316 @@
+use crate::core::target::Target;
+use anyhow::{bail, Result};
+use log::debug;
+use nix::unistd::Pid;
+
+pub const DR_STATUS: usize = 6;
+pub const DR_CONTROL: usize = 7;
+pub const SLOTS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Execute,
+    Write,
+    // x86 has no read-only condition, it traps on read/write and the hits
+    // that changed the value are dropped
+    Read,
+    ReadWrite,
+}
+
+impl WatchKind {
+    // R/W bits of DR7
+    fn condition_bits(self) -> u64 {
+        match self {
+            WatchKind::Execute => 0b00,
+            WatchKind::Write => 0b01,
+            WatchKind::Read | WatchKind::ReadWrite => 0b11,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub addr: u64,
+    pub len: usize,
+    pub kind: WatchKind,
+    pub hit_count: u64,
+    pub enabled: bool,
+    // last value seen, used to report old/new on a hit
+    pub value: u64,
+}
+
+// hits decoded from DR6
+#[derive(Debug)]
+pub struct WatchpointHit {
+    pub id: usize,
+    pub addr: u64,
+    pub kind: WatchKind,
+    pub old_value: u64,
+    pub new_value: u64,
+}
+
+impl WatchpointHit {
+    // a write to a `rwatch` location, the program goes on
+    pub fn is_filtered(&self) -> bool {
+        self.kind == WatchKind::Read && self.old_value != self.new_value
+    }
+}
+
+// hardware breakpoints and watchpoints in DR0-DR3, configured through DR7
+#[derive(Debug)]
+pub struct Watchpoints {
+    pub slots: [Option<Watchpoint>; SLOTS],
+    // hit of an internal single step, reported once the step is done
+    pub pending: Option<(Pid, WatchpointHit)>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Self {
+        Watchpoints {
+            slots: Default::default(),
+            pending: None,
+        }
+    }
+
+    // hardware breakpoints follow the executable like the int3 ones, data
+    // watchpoints may as well be on the stack or heap and are left alone
+    pub fn rebase(&mut self, old_base: u64, new_base: u64) {
+        for wp in self.slots.iter_mut().flatten() {
+            if wp.kind == WatchKind::Execute {
+                wp.addr = wp.addr.wrapping_sub(old_base).wrapping_add(new_base);
+            }
+        }
+    }
+
+    pub fn set_watchpoint(
+        &mut self,
+        id: usize,
+        addr: u64,
+        len: usize,
+        kind: WatchKind,
+        target: &mut dyn Target,
+        tids: &[Pid],
+    ) -> Result<()> {
+        let len = if kind == WatchKind::Execute { 1 } else { len };
+        if !matches!(len, 1 | 2 | 4 | 8) {
+            bail!("Watchpoint length must be 1, 2, 4 or 8 bytes");
+        }
+        if !addr.is_multiple_of(len as u64) {
+            bail!(
+                "Watchpoint address {:#x} is not aligned to {} bytes",
+                addr,
+                len
+            );
+        }
+        let Some(slot) = self.slots.iter().position(|s| s.is_none()) else {
+            bail!("All {} hardware debug registers are in use", SLOTS);
+        };
+
+        let value = if kind == WatchKind::Execute {
+            0
+        } else {
+            read_value(target, addr, len)?
+        };
+        self.slots[slot] = Some(Watchpoint {
+            id,
+            addr,
+            len,
+            kind,
+            hit_count: 0,
+            enabled: true,
+            value,
+        });
+        if let Err(e) = self.apply(target, tids) {
+            self.slots[slot] = None;
+            let _ = self.apply(target, tids);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: usize, target: &mut dyn Target, tids: &[Pid]) -> Result<()> {
+        let Some(slot) = self.slot_of(id) else {
+            bail!("No breakpoint number {}", id);
+        };
+        self.slots[slot] = None;
+        self.apply(target, tids)
+    }
+
+    pub fn set_enabled(
+        &mut self,
+        id: usize,
+        enabled: bool,
+        target: &mut dyn Target,
+        tids: &[Pid],
+    ) -> Result<()> {
+        let Some(slot) = self.slot_of(id) else {
+            bail!("No breakpoint number {}", id);
+        };
+        if let Some(wp) = self.slots[slot].as_mut() {
+            wp.enabled = enabled;
+        }
+        self.apply(target, tids)
+    }
+
+    pub fn remove_all(&mut self, target: &mut dyn Target, tids: &[Pid]) -> Result<()> {
+        self.slots = Default::default();
+        self.apply(target, tids)
+    }
+
+    pub fn slot_of(&self, id: usize) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|s| s.as_ref().is_some_and(|w| w.id == id))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Watchpoint> {
+        self.slots.iter().flatten()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.iter().all(|s| s.is_none())
+    }
+
+    // writes DR0-DR3 and DR7 of every thread from the slot table
+    pub fn apply(&self, target: &mut dyn Target, tids: &[Pid]) -> Result<()> {
+        for tid in tids {
+            self.apply_thread(target, *tid)?;
+        }
+        Ok(())
+    }
+
+    fn apply_thread(&self, target: &mut dyn Target, tid: Pid) -> Result<()> {
+        let mut control = 0u64;
+        for (i, slot) in self.slots.iter().enumerate() {
+            let Some(wp) = slot.as_ref().filter(|wp| wp.enabled) else {
+                continue;
+            };
+            target.write_debug_reg(tid, i, wp.addr)?;
+            let len_bits = match wp.len {
+                1 => 0b00,
+                2 => 0b01,
+                8 => 0b10,
+                _ => 0b11,
+            };
+            control |= 1 << (i * 2);
+            control |= wp.kind.condition_bits() << (16 + i * 4);
+            control |= len_bits << (18 + i * 4);
+        }
+        debug!("[DR7] {:#x}", control);
+        target.write_debug_reg(tid, DR_CONTROL, control)
+    }
+
+    // checks DR6 after a SIGTRAP, None if no debug register triggered
+    pub fn check_hit(
+        &mut self,
+        target: &mut dyn Target,
+        tid: Pid,
+    ) -> Result<Option<WatchpointHit>> {
+        let status = target.read_debug_reg(tid, DR_STATUS)?;
+        debug!("[DR6] {:#x}", status);
+        let Some(slot) = (0..SLOTS).find(|i| status & (1 << i) != 0) else {
+            return Ok(None);
+        };
+        // DR6 is sticky, clear it for the next hit
+        target.write_debug_reg(tid, DR_STATUS, 0)?;
+
+        let Some(wp) = self.slots[slot].as_mut() else {
+            return Ok(None);
+        };
+        let old_value = wp.value;
+        if wp.kind != WatchKind::Execute {
+            wp.value = read_value(target, wp.addr, wp.len)?;
+        }
+        let hit = WatchpointHit {
+            id: wp.id,
+            addr: wp.addr,
+            kind: wp.kind,
+            old_value,
+            new_value: wp.value,
+        };
+        if !hit.is_filtered() {
+            wp.hit_count += 1;
+        }
+        Ok(Some(hit))
+    }
+
+    // DR6 is not reset by the CPU, a bit left from an unreported hit would be
+    // taken for the cause of the next SIGTRAP
+    pub fn clear_status(&self, target: &mut dyn Target, tid: Pid) -> Result<()> {
+        target.write_debug_reg(tid, DR_STATUS, 0)
+    }
+}
+
+impl Default for Watchpoints {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn read_value(target: &dyn Target, addr: u64, len: usize) -> Result<u64> {
+    let mut word = [0u8; 8];
+    target.read_memory(addr, &mut word[..len])?;
+    Ok(u64::from_le_bytes(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::breakpoint_helpers::BreakpointHelper;
+    use crate::core::process_control::ProcessControl;
+    use crate::core::registers::Registers;
+    use crate::core::stepping::Stepping;
+    use crate::core::{Debugger, DebuggerState};
+
+    #[test]
+    fn test_watchpoint_while_stepping_over_breakpoint() {
+        let mut debugger =
+            Debugger::new("/bin/ls".to_string(), "".to_string(), Vec::new()).unwrap();
+        let rip = debugger.get_register_value("rip").unwrap();
+        let rsp = debugger.get_register_value("rsp").unwrap();
+        // push rax; nop; int3 at the entry point
+        debugger.write_memory(rip, &[0x50, 0x90, 0xcc]).unwrap();
+        debugger
+            .set_breakpoint_by_input(&format!("{:#x}", rip))
+            .unwrap();
+        let id = debugger.breakpoint.allocate_id();
+        let tids = debugger.process.thread_ids();
+        debugger
+            .watchpoints
+            .set_watchpoint(
+                id,
+                rsp - 8,
+                8,
+                WatchKind::Write,
+                debugger.target.as_mut(),
+                &tids,
+            )
+            .unwrap();
+
+        debugger.cont().unwrap();
+        debugger.wait_for_stop();
+        assert!(debugger.last_watch_hit.is_none());
+        assert_eq!(debugger.get_register_value("rip").unwrap(), rip);
+
+        // the push is executed while stepping off the breakpoint
+        debugger.cont().unwrap();
+        debugger.wait_for_stop();
+        assert!(matches!(debugger.state, DebuggerState::Interactive));
+        assert_eq!(debugger.last_watch_hit.as_ref().map(|hit| hit.id), Some(id));
+        assert_eq!(debugger.get_register_value("rip").unwrap(), rip + 1);
+        let status = debugger
+            .target
+            .read_debug_reg(debugger.process.tid, DR_STATUS)
+            .unwrap();
+        assert_eq!(status & 0xf, 0);
+
+        // the int3 after it is not taken for the watchpoint again
+        debugger.last_watch_hit = None;
+        debugger.cont().unwrap();
+        debugger.wait_for_stop();
+        assert!(debugger.last_watch_hit.is_none());
+        assert_eq!(debugger.get_register_value("rip").unwrap(), rip + 3);
+        debugger.process.kill();
+    }
+}
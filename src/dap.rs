@@ -0,0 +1,862 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use rusty_dbg::commands::CommandRouter;
+use rusty_dbg::core::backtrace::Backtrace;
+use rusty_dbg::core::breakpoint::BreakpointKind;
+use rusty_dbg::core::breakpoint_helpers::BreakpointHelper;
+use rusty_dbg::core::disassembler::Disassembler;
+use rusty_dbg::core::launch::LaunchConfig;
+use rusty_dbg::core::memory::Memory;
+use rusty_dbg::core::process_control::ProcessControl;
+use rusty_dbg::core::registers::general_registers;
+use rusty_dbg::core::signals::signal_text;
+use rusty_dbg::core::stepping::Stepping;
+use rusty_dbg::core::{Debugger, DebuggerState};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::os::fd::FromRawFd;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+// longest x86 instruction, for sizing disassembly reads
+const MAX_INSN_LEN: usize = 15;
+// largest `readMemory` and `disassemble` requests
+const MAX_READ_LEN: u64 = 1 << 20;
+const MAX_INSN_COUNT: u64 = 4096;
+
+// `--dap`, the Debug Adapter Protocol over stdin/stdout for VS Code, nvim-dap
+// and friends. Everything the debugger prints goes out as output events.
+pub fn serve() -> Result<()> {
+    let writer = Writer::redirect_stdout()?;
+    let running = Arc::new(AtomicI32::new(0));
+    let paused = Arc::new(AtomicBool::new(false));
+
+    // pause has to get through while the main thread waits on the debugee
+    let (tx, rx) = mpsc::channel();
+    {
+        let writer = writer.clone();
+        let running = running.clone();
+        let paused = paused.clone();
+        thread::spawn(move || {
+            let mut stdin = io::stdin().lock();
+            while let Ok(Some(request)) = read_message(&mut stdin) {
+                if request["command"] == "pause" {
+                    let pid = running.load(Ordering::SeqCst);
+                    let result = if pid == 0 {
+                        Err(anyhow!("The program is not running"))
+                    } else {
+                        paused.store(true, Ordering::SeqCst);
+                        kill(Pid::from_raw(pid), Signal::SIGINT)
+                            .map(|_| Value::Null)
+                            .map_err(Into::into)
+                    };
+                    writer.respond(&request, result);
+                    continue;
+                }
+                if tx.send(request).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut session = Session {
+        debugger: None,
+        writer,
+        running,
+        paused,
+        launched: false,
+        stop_on_entry: false,
+        source_breakpoints: HashMap::new(),
+        function_breakpoints: Vec::new(),
+        instruction_breakpoints: Vec::new(),
+        frames: Vec::new(),
+        scopes: Vec::new(),
+        pending: None,
+        done: false,
+    };
+    while let Ok(request) = rx.recv() {
+        let command = request["command"].as_str().unwrap_or_default().to_string();
+        let result = session.handle(&command, &request["arguments"]);
+        session.writer.respond(&request, result);
+        match command.as_str() {
+            "launch" | "attach" => session.writer.event("initialized", Value::Null),
+            "configurationDone" if session.stop_on_entry => {
+                if let Some(d) = session.debugger.as_ref() {
+                    session.writer.event(
+                        "stopped",
+                        json!({ "reason": "entry", "threadId": d.process.tid.as_raw(), "allThreadsStopped": true }),
+                    );
+                }
+            }
+            _ => {}
+        }
+        if let Some(resume) = session.pending.take() {
+            session.resume(resume);
+        }
+        if session.done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+enum Resume {
+    Continue,
+    Line { over: bool },
+    Instruction { over: bool },
+    Out,
+    // a repl command already resumed the debugee, or it was killed
+    Wait,
+}
+
+enum Scope {
+    Registers(Pid),
+    Globals,
+}
+
+struct Session {
+    debugger: Option<Debugger>,
+    writer: Writer,
+    // pid while the debugee runs, 0 while it is stopped
+    running: Arc<AtomicI32>,
+    paused: Arc<AtomicBool>,
+    launched: bool,
+    stop_on_entry: bool,
+    // addresses set for each source path, replaced as a whole by setBreakpoints
+    source_breakpoints: HashMap<String, Vec<u64>>,
+    function_breakpoints: Vec<u64>,
+    instruction_breakpoints: Vec<u64>,
+    // frame and variables references are indices into these, they are only
+    // valid until the next resume
+    frames: Vec<(Pid, u64)>,
+    scopes: Vec<Scope>,
+    // resumes run after their response is sent
+    pending: Option<Resume>,
+    done: bool,
+}
+
+impl Session {
+    fn handle(&mut self, command: &str, args: &Value) -> Result<Value> {
+        debug!("dap request {} {}", command, args);
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsDisassembleRequest": true,
+                "supportsReadMemoryRequest": true,
+                "supportsSteppingGranularity": true,
+                "supportsEvaluateForHovers": true,
+                "supportsTerminateRequest": true,
+                "exceptionBreakpointFilters": [],
+            })),
+            "launch" => self.launch(args),
+            "attach" => self.attach(args),
+            "configurationDone" => {
+                if !self.stop_on_entry {
+                    self.pending = Some(Resume::Continue);
+                }
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => self.set_source_breakpoints(args),
+            "setFunctionBreakpoints" => {
+                let old = std::mem::take(&mut self.function_breakpoints);
+                let (addrs, breakpoints) = self.replace_breakpoints(old, args, |d, bp| {
+                    d.resolve_breakpoint_input(bp["name"].as_str().unwrap_or_default())
+                })?;
+                self.function_breakpoints = addrs;
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setInstructionBreakpoints" => {
+                let old = std::mem::take(&mut self.instruction_breakpoints);
+                let (addrs, breakpoints) = self.replace_breakpoints(old, args, |d, bp| {
+                    let reference = bp["instructionReference"].as_str().unwrap_or_default();
+                    let offset = bp["offset"].as_i64().unwrap_or(0);
+                    Ok(vec![d
+                        .parse_address(reference)?
+                        .wrapping_add(offset as u64)])
+                })?;
+                self.instruction_breakpoints = addrs;
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+            "threads" => {
+                let threads: Vec<Value> = self
+                    .debugger()?
+                    .process
+                    .thread_ids()
+                    .iter()
+                    .map(|tid| json!({ "id": tid.as_raw(), "name": format!("Thread {}", tid) }))
+                    .collect();
+                Ok(json!({ "threads": threads }))
+            }
+            "stackTrace" => self.stack_trace(args),
+            "scopes" => self.scopes(args),
+            "variables" => self.variables(args),
+            "continue" => {
+                self.pending = Some(Resume::Continue);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" => {
+                self.select_thread(args)?;
+                let over = command == "next";
+                self.pending = Some(if args["granularity"] == "instruction" {
+                    Resume::Instruction { over }
+                } else {
+                    Resume::Line { over }
+                });
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                self.select_thread(args)?;
+                self.pending = Some(Resume::Out);
+                Ok(Value::Null)
+            }
+            "disassemble" => self.disassemble(args),
+            "readMemory" => self.read_memory(args),
+            "evaluate" => self.evaluate(args),
+            "terminate" => {
+                let d = self.debugger()?;
+                d.process.kill();
+                d.process.threads.clear();
+                // `exited` and `terminated` follow the response
+                self.pending = Some(Resume::Wait);
+                Ok(Value::Null)
+            }
+            "disconnect" => {
+                self.done = true;
+                let terminate = args["terminateDebuggee"].as_bool().unwrap_or(self.launched);
+                if let Some(d) = self.debugger.as_mut() {
+                    if d.process.threads.is_empty() {
+                        // already gone
+                    } else if terminate {
+                        d.process.kill();
+                    } else {
+                        d.detach()?;
+                    }
+                }
+                Ok(Value::Null)
+            }
+            _ => bail!("Unsupported request {}", command),
+        }
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value> {
+        let program = args["program"].as_str().context("launch needs a program")?;
+        let program_args = args["args"]
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(|v| v.as_str().map(String::from))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut launch = LaunchConfig::new(program.to_string(), program_args);
+        // only the debugee runs there, not the adapter
+        launch.cwd = args["cwd"].as_str().map(String::from);
+        self.start(|name| Debugger::launch(launch, name))?;
+        self.launched = true;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    fn attach(&mut self, args: &Value) -> Result<Value> {
+        let pid = args["processId"]
+            .as_i64()
+            .or_else(|| args["processId"].as_str().and_then(|p| p.parse().ok()))
+            .context("attach needs a processId")?;
+        self.start(|name| Debugger::new(pid.to_string(), name, Vec::new()))?;
+        // an attached process stops wherever it was, show that
+        self.stop_on_entry = true;
+        Ok(Value::Null)
+    }
+
+    fn start(&mut self, create: impl FnOnce(String) -> Result<Debugger>) -> Result<()> {
+        if self.debugger.is_some() {
+            bail!("A debug session is already running");
+        }
+        let mut debugger = create("rusty-dbg".to_string())?;
+        debugger.process.get_base_addr_from_map();
+        // stops are reported as events, the client shows the context
+        debugger.context.set("all", false)?;
+        debugger.command_runner = Some(Rc::new(CommandRouter::new()));
+        self.debugger = Some(debugger);
+        Ok(())
+    }
+
+    fn debugger(&mut self) -> Result<&mut Debugger> {
+        self.debugger
+            .as_mut()
+            .context("No program, send launch or attach first")
+    }
+
+    fn select_thread(&mut self, args: &Value) -> Result<()> {
+        let d = self.debugger()?;
+        if let Some(tid) = args["threadId"].as_i64().map(|t| Pid::from_raw(t as i32)) {
+            if tid != d.process.tid && d.process.thread(tid).is_some() {
+                d.process.tid = tid;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_source_breakpoints(&mut self, args: &Value) -> Result<Value> {
+        let path = args["source"]["path"]
+            .as_str()
+            .context("setBreakpoints needs a source path")?
+            .to_string();
+        let old = self.source_breakpoints.remove(&path).unwrap_or_default();
+        let (addrs, breakpoints) = self.replace_breakpoints(old, args, |d, bp| {
+            let line = bp["line"].as_u64().unwrap_or_default();
+            // the client sends absolute paths, the line table may not have them
+            d.resolve_source_line(&path, line).or_else(|e| {
+                let name = path.rsplit('/').next().unwrap_or(&path);
+                d.resolve_source_line(name, line).map_err(|_| e)
+            })
+        })?;
+        self.source_breakpoints.insert(path, addrs);
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    // removes the `old` addresses and sets one breakpoint per entry of
+    // `breakpoints` in the arguments, the client always sends the full list
+    fn replace_breakpoints(
+        &mut self,
+        old: Vec<u64>,
+        args: &Value,
+        resolve: impl Fn(&Debugger, &Value) -> Result<Vec<u64>>,
+    ) -> Result<(Vec<u64>, Vec<Value>)> {
+        let d = self.debugger()?;
+        for addr in old {
+            if d.breakpoint.is_breakpoint(addr) {
+                d.breakpoint.remove_breakpoint(addr, d.target.as_mut())?;
+            }
+        }
+        let mut addrs = Vec::new();
+        let mut breakpoints = Vec::new();
+        for bp in args["breakpoints"].as_array().into_iter().flatten() {
+            let condition = bp["condition"].as_str().map(String::from);
+            match resolve(d, bp).and_then(|resolved| set_breakpoints(d, &resolved, condition)) {
+                Ok(set) => {
+                    let (id, addr) = set[0];
+                    let mut breakpoint = json!({
+                        "id": id,
+                        "verified": true,
+                        "instructionReference": format!("{:#x}", addr),
+                    });
+                    if let Some((file, line)) = d.dwarf.find_line(addr - d.process.base_addr) {
+                        breakpoint["source"] = source(&file);
+                        breakpoint["line"] = json!(line);
+                    }
+                    addrs.extend(set.iter().map(|(_, addr)| *addr));
+                    breakpoints.push(breakpoint);
+                }
+                Err(e) => breakpoints.push(json!({ "verified": false, "message": e.to_string() })),
+            }
+        }
+        Ok((addrs, breakpoints))
+    }
+
+    fn stack_trace(&mut self, args: &Value) -> Result<Value> {
+        let d = self.debugger.as_mut().context("No program")?;
+        let tid = args["threadId"]
+            .as_i64()
+            .map(|t| Pid::from_raw(t as i32))
+            .unwrap_or(d.process.tid);
+        // the unwinder starts from the selected thread
+        let selected = std::mem::replace(&mut d.process.tid, tid);
+        let pcs = d.frames();
+        d.process.tid = selected;
+        let pcs = pcs?;
+
+        let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+        let levels = match args["levels"].as_u64().unwrap_or(0) as usize {
+            0 => pcs.len(),
+            levels => levels,
+        };
+        let mut frames = Vec::new();
+        for (i, pc) in pcs.iter().enumerate().skip(start).take(levels) {
+            // return addresses point behind the call, describe the call itself
+            let call_site = if i == 0 { *pc } else { pc - 1 };
+            let offset = call_site.wrapping_sub(d.process.base_addr);
+            self.frames.push((tid, *pc));
+            let mut frame = json!({
+                "id": self.frames.len(),
+                "name": d.get_function_name(offset).unwrap_or_else(|| "??".to_string()),
+                "line": 0,
+                "column": 0,
+                "instructionPointerReference": format!("{:#x}", pc),
+            });
+            if let Some((file, line)) = d.dwarf.find_line(offset) {
+                frame["source"] = source(&file);
+                frame["line"] = json!(line);
+                frame["column"] = json!(1);
+            }
+            frames.push(frame);
+        }
+        Ok(json!({ "stackFrames": frames, "totalFrames": pcs.len() }))
+    }
+
+    fn scopes(&mut self, args: &Value) -> Result<Value> {
+        let frame_id = args["frameId"].as_u64().unwrap_or_default() as usize;
+        let mut scopes = Vec::new();
+        // registers are only known for the innermost frame of a thread
+        let frame = frame_id
+            .checked_sub(1)
+            .and_then(|i| self.frames.get(i))
+            .copied();
+        let innermost = match frame {
+            Some((tid, pc)) => self.debugger()?.read_regs(tid)?.rip == pc,
+            None => false,
+        };
+        if innermost {
+            let (tid, _) = self.frames[frame_id - 1];
+            self.scopes.push(Scope::Registers(tid));
+            scopes.push(json!({
+                "name": "Registers",
+                "presentationHint": "registers",
+                "variablesReference": self.scopes.len(),
+                "expensive": false,
+            }));
+        }
+        self.scopes.push(Scope::Globals);
+        scopes.push(json!({
+            "name": "Globals",
+            "variablesReference": self.scopes.len(),
+            "expensive": false,
+        }));
+        Ok(json!({ "scopes": scopes }))
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value> {
+        let reference = args["variablesReference"].as_u64().unwrap_or_default() as usize;
+        let scope = reference
+            .checked_sub(1)
+            .and_then(|i| self.scopes.get(i))
+            .context("Unknown variablesReference")?;
+        let d = self.debugger.as_ref().context("No program")?;
+        let mut variables = Vec::new();
+        match scope {
+            Scope::Registers(tid) => {
+                let regs = d.read_regs(*tid)?;
+                for (name, value) in general_registers(&regs) {
+                    variables.push(json!({
+                        "name": name,
+                        "value": format!("{:#x}", value),
+                        "variablesReference": 0,
+                        "memoryReference": format!("{:#x}", value),
+                    }));
+                }
+            }
+            Scope::Globals => {
+                for global in d.globals.iter().filter(|g| g.size > 0) {
+                    let addr = d.process.base_addr + global.offset;
+                    let value = match global.size {
+                        1 | 2 | 4 | 8 => {
+                            let mut buf = [0u8; 8];
+                            match d.read_memory(addr, &mut buf[..global.size as usize]) {
+                                Ok(()) => u64::from_le_bytes(buf).to_string(),
+                                Err(e) => format!("<{}>", e),
+                            }
+                        }
+                        size => format!("{} bytes at {:#x}", size, addr),
+                    };
+                    variables.push(json!({
+                        "name": global.name,
+                        "value": value,
+                        "variablesReference": 0,
+                        "memoryReference": format!("{:#x}", addr),
+                    }));
+                }
+            }
+        }
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn disassemble(&mut self, args: &Value) -> Result<Value> {
+        let d = self.debugger.as_ref().context("No program")?;
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        let addr = d
+            .parse_address(reference)?
+            .wrapping_add(args["offset"].as_i64().unwrap_or(0) as u64);
+        let instruction_offset = args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_u64().unwrap_or(0);
+        if count > MAX_INSN_COUNT || instruction_offset.unsigned_abs() > MAX_INSN_COUNT {
+            bail!(
+                "instructionCount and instructionOffset must not exceed {}",
+                MAX_INSN_COUNT
+            );
+        }
+        let count = count as usize;
+
+        let mut insns = Vec::new();
+        if instruction_offset < 0 {
+            let before = instruction_offset.unsigned_abs() as usize;
+            // decoding backwards is ambiguous, start at the function when the
+            // address is inside one
+            let offset = addr.wrapping_sub(d.process.base_addr + 1);
+            let from = d
+                .functions
+                .iter()
+                .find(|f| f.offset <= offset && f.offset + f.size > offset)
+                .map(|f| f.offset + d.process.base_addr)
+                .filter(|from| addr - from <= (before * MAX_INSN_LEN) as u64)
+                .unwrap_or(addr.saturating_sub((before * MAX_INSN_LEN) as u64));
+            let mut prefix = d
+                .disassemble_range(from, (addr - from) as usize)
+                .unwrap_or_default();
+            prefix.retain(|(a, _)| *a < addr);
+            let skip = prefix.len().saturating_sub(before);
+            insns.extend(
+                std::iter::repeat_n(None, before.saturating_sub(prefix.len()))
+                    .chain(prefix.into_iter().skip(skip).map(Some)),
+            );
+        }
+        let skip = instruction_offset.max(0) as usize;
+        let rest = count.saturating_sub(insns.len()) + skip;
+        let decoded = d
+            .disassemble_range(addr, rest * MAX_INSN_LEN)
+            .unwrap_or_default();
+        insns.extend(decoded.into_iter().skip(skip).map(Some));
+        insns.truncate(count);
+        insns.resize(count, None);
+
+        let mut last_line = None;
+        let instructions: Vec<Value> = insns
+            .into_iter()
+            .map(|insn| {
+                let Some((addr, text)) = insn else {
+                    return json!({ "address": "0x0", "instruction": "??", "presentationHint": "invalid" });
+                };
+                let mut instruction = json!({ "address": format!("{:#x}", addr), "instruction": text });
+                let offset = addr.wrapping_sub(d.process.base_addr);
+                if let Some(f) = d.functions.iter().find(|f| f.offset == offset) {
+                    instruction["symbol"] = json!(f.name);
+                }
+                let line = d.dwarf.find_line(offset);
+                if line.is_some() && line != last_line {
+                    let (file, number) = line.clone().unwrap();
+                    instruction["location"] = source(&file);
+                    instruction["line"] = json!(number);
+                }
+                last_line = line;
+                instruction
+            })
+            .collect();
+        Ok(json!({ "instructions": instructions }))
+    }
+
+    fn read_memory(&mut self, args: &Value) -> Result<Value> {
+        let d = self.debugger.as_ref().context("No program")?;
+        let reference = args["memoryReference"].as_str().unwrap_or_default();
+        let addr = d
+            .parse_address(reference)?
+            .wrapping_add(args["offset"].as_i64().unwrap_or(0) as u64);
+        let count = args["count"].as_u64().unwrap_or(0);
+        if count > MAX_READ_LEN {
+            bail!("count must not exceed {}", MAX_READ_LEN);
+        }
+        let count = count as usize;
+
+        let data = d.read_mapped(addr, count);
+        Ok(json!({
+            "address": format!("{:#x}", addr),
+            "data": base64(&data),
+            "unreadableBytes": count - data.len(),
+        }))
+    }
+
+    fn evaluate(&mut self, args: &Value) -> Result<Value> {
+        let expression = args["expression"].as_str().unwrap_or_default();
+        let d = self.debugger()?;
+        match d.parse_address(expression) {
+            Ok(value) => Ok(json!({
+                "result": format!("{:#x} ({})", value, value as i64),
+                "variablesReference": 0,
+                "memoryReference": format!("{:#x}", value),
+            })),
+            // the debug console takes debugger commands as well
+            Err(_) if args["context"] == "repl" => {
+                CommandRouter::new().handle(expression, d);
+                if matches!(d.state, DebuggerState::AwaitingTrap) {
+                    self.pending = Some(Resume::Wait);
+                }
+                Ok(json!({ "result": "", "variablesReference": 0 }))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn resume(&mut self, resume: Resume) {
+        self.frames.clear();
+        self.scopes.clear();
+        let Some(d) = self.debugger.as_mut() else {
+            return;
+        };
+        d.last_status = None;
+        d.last_watch_hit = None;
+        self.running.store(d.process.pid.as_raw(), Ordering::SeqCst);
+        let result = match resume {
+            Resume::Continue => d.cont(),
+            Resume::Line { over } => d.step_line(over),
+            Resume::Instruction { over: true } => d.step_over(),
+            Resume::Instruction { over: false } => d.single_step(),
+            Resume::Out => d.finish(),
+            Resume::Wait => Ok(()),
+        };
+        if let Err(e) = result {
+            println!("Error: {}", e);
+        }
+        d.wait_for_stop();
+        self.running.store(0, Ordering::SeqCst);
+        self.report_stop();
+    }
+
+    fn report_stop(&mut self) {
+        let Some(d) = self.debugger.as_ref() else {
+            return;
+        };
+        if matches!(d.state, DebuggerState::Exit) || d.process.threads.is_empty() {
+            let exit_code = match d.last_status {
+                Some(WaitStatus::Exited(_, code)) => code,
+                Some(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+                _ => 0,
+            };
+            self.writer
+                .event("exited", json!({ "exitCode": exit_code }));
+            self.writer.event("terminated", Value::Null);
+            return;
+        }
+
+        let tid = d.process.tid;
+        let mut body = json!({ "threadId": tid.as_raw(), "allThreadsStopped": true });
+        let rip = d.read_regs(tid).map(|regs| regs.rip).unwrap_or_default();
+        let user_breakpoint = d
+            .breakpoint
+            .get(rip)
+            .filter(|bp| bp.enabled && !matches!(bp.kind, BreakpointKind::Internal));
+        body["reason"] = json!(if self.paused.swap(false, Ordering::SeqCst) {
+            "pause"
+        } else if d.last_watch_hit.is_some() {
+            "data breakpoint"
+        } else if let Some(WaitStatus::Stopped(_, signal)) = d
+            .last_status
+            .filter(|s| !matches!(s, WaitStatus::Stopped(_, Signal::SIGTRAP)))
+        {
+            body["description"] = json!(format!("{}, {}", signal.as_str(), signal_text(signal)));
+            "exception"
+        } else if let Some(bp) = user_breakpoint {
+            body["hitBreakpointIds"] = json!([bp.id]);
+            "breakpoint"
+        } else {
+            "step"
+        });
+        self.writer.event("stopped", body);
+    }
+}
+
+// sets a breakpoint at each address, an address that already has one keeps it
+fn set_breakpoints(
+    d: &mut Debugger,
+    addrs: &[u64],
+    condition: Option<String>,
+) -> Result<Vec<(usize, u64)>> {
+    let mut set = Vec::new();
+    for &addr in addrs {
+        let id = match d.breakpoint.get(addr) {
+            Some(bp) => bp.id,
+            None => d.breakpoint.set_breakpoint(addr, d.target.as_mut())?,
+        };
+        d.breakpoint.set_condition(id, condition.clone())?;
+        set.push((id, addr));
+    }
+    if set.is_empty() {
+        bail!("No address to break at");
+    }
+    Ok(set)
+}
+
+fn source(path: &str) -> Value {
+    json!({ "name": path.rsplit('/').next().unwrap_or(path), "path": path })
+}
+
+// protocol messages go to the real stdout, fd 1 itself becomes a pipe whose
+// contents are forwarded as output events
+#[derive(Clone)]
+struct Writer {
+    out: Arc<Mutex<File>>,
+    // read end of the pipe that fd 1 points to
+    output: Arc<Mutex<File>>,
+    seq: Arc<AtomicI64>,
+}
+
+impl Writer {
+    fn redirect_stdout() -> Result<Self> {
+        let mut fds = [0; 2];
+        // SAFETY: plain fd juggling before any other thread writes to stdout
+        let (out, output) = unsafe {
+            let out = libc::dup(1);
+            if out < 0 || libc::pipe(fds.as_mut_ptr()) < 0 || libc::dup2(fds[1], 1) < 0 {
+                bail!("Failed to redirect stdout: {}", io::Error::last_os_error());
+            }
+            libc::close(fds[1]);
+            // drained by whoever holds the lock, see drain_output
+            libc::fcntl(fds[0], libc::F_SETFL, libc::O_NONBLOCK);
+            (File::from_raw_fd(out), File::from_raw_fd(fds[0]))
+        };
+        let writer = Writer {
+            out: Arc::new(Mutex::new(out)),
+            output: Arc::new(Mutex::new(output)),
+            seq: Arc::new(AtomicI64::new(1)),
+        };
+
+        // the debugee writes to the pipe as well, forward that while it runs
+        let events = writer.clone();
+        thread::spawn(move || loop {
+            let mut pollfd = libc::pollfd {
+                fd: fds[0],
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: a single valid pollfd
+            if unsafe { libc::poll(&mut pollfd, 1, -1) } < 0 {
+                break;
+            }
+            events.drain_output();
+        });
+        Ok(writer)
+    }
+
+    // sends what is in the pipe as output events, responses and events drain
+    // it first so the output of a step comes before its stopped event
+    fn drain_output(&self) {
+        let mut output = self.output.lock().unwrap();
+        let mut buf = [0u8; 4096];
+        while let Ok(n @ 1..) = output.read(&mut buf) {
+            let text = String::from_utf8_lossy(&buf[..n]);
+            self.send(json!({
+                "type": "event",
+                "event": "output",
+                "body": { "category": "console", "output": text },
+            }));
+        }
+    }
+
+    fn send(&self, mut message: Value) {
+        message["seq"] = json!(self.seq.fetch_add(1, Ordering::SeqCst));
+        let body = message.to_string();
+        debug!("dap send {}", body);
+        let mut out = self.out.lock().unwrap();
+        let _ = write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = out.flush();
+    }
+
+    fn respond(&self, request: &Value, result: Result<Value>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(e) => response["message"] = json!(e.to_string()),
+        }
+        let _ = io::stdout().flush();
+        self.drain_output();
+        self.send(response);
+    }
+
+    fn event(&self, event: &str, body: Value) {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        let _ = io::stdout().flush();
+        self.drain_output();
+        self.send(message);
+    }
+}
+
+// one `Content-Length` framed message, None at the end of the input
+fn read_message(reader: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = length.context("Missing Content-Length header")?;
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Cursor};
+
+    #[test]
+    fn test_framing() {
+        let body = r#"{"seq":1,"type":"request","command":"initialize"}"#;
+        let input = format!(
+            "Content-Length: {}\r\n\r\n{}content-length: 2\r\n\r\n{{}}",
+            body.len(),
+            body
+        );
+        let mut reader = BufReader::new(Cursor::new(input));
+        let first = read_message(&mut reader).unwrap().unwrap();
+        assert_eq!(first["command"], "initialize");
+        assert_eq!(read_message(&mut reader).unwrap().unwrap(), json!({}));
+        assert!(read_message(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(&[0xff, 0xfe, 0x00, 0x01]), "//4AAQ==");
+    }
+}
@@ -0,0 +1,554 @@
+use anyhow::{anyhow, bail, Context, Result};
+use log::debug;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use rusty_dbg::core::breakpoint::BreakpointKind;
+use rusty_dbg::core::breakpoint_helpers::BreakpointHelper;
+use rusty_dbg::core::disassembler::Disassembler;
+use rusty_dbg::core::memory::Memory;
+use rusty_dbg::core::process_control::ProcessControl;
+use rusty_dbg::core::registers::{general_registers, Registers};
+use rusty_dbg::core::signals::signal_text;
+use rusty_dbg::core::stepping::Stepping;
+use rusty_dbg::core::Debugger;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{self, Read};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::handshake::server::{ErrorResponse, Request as HttpRequest, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+// how long a connection waits for a request before sending queued messages
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// longest x86 instruction, for sizing disassembly reads
+const MAX_INSN_LEN: usize = 15;
+// largest `readMemory` and `disassemble` requests
+const MAX_READ_LEN: u64 = 1 << 20;
+const MAX_INSN_COUNT: u64 = 4096;
+
+// `--gui <[host]:port>`, JSON-RPC 2.0 over a WebSocket for the Svelte app in
+// gui/. Requests are answered in order on the debugger thread, stops are
+// pushed to every client as `stopped` notifications. Any web page can open
+// a WebSocket to localhost, so clients have to present the token printed at
+// startup as `?token=`.
+pub fn serve(debugger: &mut Debugger, address: &str) -> Result<()> {
+    let address = match address.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => address.to_string(),
+    };
+    let listener = TcpListener::bind(&address).with_context(|| format!("bind {}", address))?;
+    let token = session_token()?;
+    println!("GUI server listening on ws://{}", listener.local_addr()?);
+    println!("Open the GUI with ?token={}", token);
+    serve_listener(debugger, listener, token)
+}
+
+fn session_token() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+struct Request {
+    message: Value,
+    reply: Sender<String>,
+}
+
+#[derive(Clone, Default)]
+struct Shared {
+    // pid while the debugee runs, 0 while it is stopped
+    running: Arc<AtomicI32>,
+    paused: Arc<AtomicBool>,
+    clients: Arc<Mutex<Vec<Sender<String>>>>,
+}
+
+impl Shared {
+    fn notify(&self, method: &str, params: Value) {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params }).to_string();
+        // clients that went away are dropped here
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(message.clone()).is_ok());
+    }
+}
+
+fn serve_listener(debugger: &mut Debugger, listener: TcpListener, token: String) -> Result<()> {
+    let shared = Shared::default();
+    let token = Arc::new(token);
+    let (tx, rx) = mpsc::channel();
+    {
+        let shared = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let shared = shared.clone();
+                let tx = tx.clone();
+                let token = token.clone();
+                thread::spawn(move || {
+                    if let Err(e) = connection(stream, shared, tx, &token) {
+                        debug!("gui connection: {}", e);
+                    }
+                });
+            }
+        });
+    }
+
+    let mut session = Session {
+        debugger,
+        shared,
+        resumed: false,
+        done: false,
+    };
+    while !session.done {
+        let Ok(request) = rx.recv() else {
+            break;
+        };
+        let method = request.message["method"].as_str().unwrap_or_default();
+        let result = session.handle(method, &request.message["params"]);
+        // notifications have no id and get no response
+        if !request.message["id"].is_null() {
+            let mut response = json!({ "jsonrpc": "2.0", "id": request.message["id"] });
+            match result {
+                Ok(value) => response["result"] = value,
+                Err(e) => response["error"] = json!({ "code": -32000, "message": e.to_string() }),
+            }
+            let _ = request.reply.send(response.to_string());
+        }
+        if std::mem::take(&mut session.resumed) {
+            session.wait();
+        }
+    }
+    Ok(())
+}
+
+// pumps one WebSocket: requests go to the debugger thread, responses and
+// notifications come back through `outgoing`. `pause` is handled right here
+// as the debugger thread is blocked while the debugee runs.
+fn connection(
+    stream: TcpStream,
+    shared: Shared,
+    requests: Sender<Request>,
+    token: &str,
+) -> Result<()> {
+    // the error type is the one tungstenite's callback wants
+    #[allow(clippy::result_large_err)]
+    let check_token = |request: &HttpRequest, response: Response| {
+        let mut query = request.uri().query().unwrap_or_default().split('&');
+        if query.any(|pair| pair.strip_prefix("token=") == Some(token)) {
+            return Ok(response);
+        }
+        let mut error = ErrorResponse::new(Some("missing or wrong token".to_string()));
+        *error.status_mut() = StatusCode::FORBIDDEN;
+        Err(error)
+    };
+    let mut ws =
+        tungstenite::accept_hdr(stream, check_token).map_err(|e| anyhow!("handshake: {}", e))?;
+    ws.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    let (reply, outgoing): (Sender<String>, Receiver<String>) = mpsc::channel();
+    shared.clients.lock().unwrap().push(reply.clone());
+
+    loop {
+        while let Ok(message) = outgoing.try_recv() {
+            ws.send(Message::Text(message))?;
+        }
+        let text = match ws.read() {
+            Ok(Message::Text(text)) => text,
+            Ok(Message::Close(_)) => return Ok(()),
+            Ok(_) => continue,
+            Err(tungstenite::Error::Io(e))
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let message: Value = match serde_json::from_str(&text) {
+            Ok(message) => message,
+            Err(e) => {
+                let error = json!({ "code": -32700, "message": e.to_string() });
+                send(
+                    &mut ws,
+                    json!({ "jsonrpc": "2.0", "id": null, "error": error }),
+                )?;
+                continue;
+            }
+        };
+        if message["method"] == "pause" {
+            let mut response = json!({ "jsonrpc": "2.0", "id": message["id"] });
+            let pid = shared.running.load(Ordering::SeqCst);
+            if pid == 0 {
+                response["error"] =
+                    json!({ "code": -32000, "message": "The program is not running" });
+            } else {
+                shared.paused.store(true, Ordering::SeqCst);
+                let _ = kill(Pid::from_raw(pid), Signal::SIGINT);
+                response["result"] = Value::Null;
+            }
+            send(&mut ws, response)?;
+            continue;
+        }
+        let reply = reply.clone();
+        if requests.send(Request { message, reply }).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn send(ws: &mut WebSocket<TcpStream>, message: Value) -> Result<()> {
+    ws.send(Message::Text(message.to_string()))?;
+    Ok(())
+}
+
+struct Session<'a> {
+    debugger: &'a mut Debugger,
+    shared: Shared,
+    // set by the stepping methods, the stop is reported after the response
+    resumed: bool,
+    done: bool,
+}
+
+impl Session<'_> {
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Value> {
+        debug!("gui request {} {}", method, params);
+        let d = &mut *self.debugger;
+        match method {
+            "state" => Ok(self.state()),
+            "registers" => {
+                let regs = d.read_regs(d.process.tid)?;
+                Ok(general_registers(&regs)
+                    .iter()
+                    .map(|(name, value)| json!({ "name": name, "value": format!("{:#x}", value) }))
+                    .collect())
+            }
+            "setRegister" => {
+                let name = params["name"].as_str().context("missing name")?;
+                d.set_register(name, &value_string(&params["value"])?)?;
+                Ok(Value::Null)
+            }
+            "readMemory" => {
+                let addr = address(d, &params["address"])?;
+                let length = params["length"].as_u64().unwrap_or(256);
+                if length > MAX_READ_LEN {
+                    bail!("length must not exceed {}", MAX_READ_LEN);
+                }
+                Ok(json!({ "address": addr, "bytes": d.read_mapped(addr, length as usize) }))
+            }
+            "writeMemory" => {
+                let addr = address(d, &params["address"])?;
+                let bytes: Vec<u8> = params["bytes"]
+                    .as_array()
+                    .context("missing bytes")?
+                    .iter()
+                    .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                    .collect::<Option<_>>()
+                    .context("bytes must be numbers from 0 to 255")?;
+                write_memory(d, addr, &bytes)?;
+                Ok(Value::Null)
+            }
+            "disassemble" => {
+                let addr = match &params["address"] {
+                    Value::Null => d.read_regs(d.process.tid)?.rip,
+                    value => address(d, value)?,
+                };
+                let count = params["count"].as_u64().unwrap_or(32);
+                if count > MAX_INSN_COUNT {
+                    bail!("count must not exceed {}", MAX_INSN_COUNT);
+                }
+                let count = count as usize;
+                let mut lines = d.disassemble_bytes(addr, count * MAX_INSN_LEN)?;
+                lines.truncate(count);
+                Ok(lines
+                    .into_iter()
+                    .map(|(address, bytes, text)| {
+                        json!({
+                            "address": address,
+                            "bytes": bytes,
+                            "command": text.trim_end().replace('\t', " "),
+                            "location": d.describe_location(address),
+                        })
+                    })
+                    .collect())
+            }
+            "breakpoints" => Ok(d
+                .breakpoint
+                .user_breakpoints()
+                .map(|bp| {
+                    json!({
+                        "id": bp.id,
+                        "address": bp.addr,
+                        "location": d.describe_location(bp.addr),
+                        "enabled": bp.enabled,
+                        "temporary": bp.kind == BreakpointKind::Temporary,
+                        "hits": bp.hit_count,
+                        "condition": bp.condition,
+                    })
+                })
+                .collect()),
+            "setBreakpoint" => {
+                let location = value_string(&params["location"])?;
+                let set = d.set_breakpoint_by_input(&location)?;
+                if let Some(condition) = params["condition"].as_str() {
+                    for (id, _) in &set {
+                        d.breakpoint
+                            .set_condition(*id, Some(condition.to_string()))?;
+                    }
+                }
+                Ok(set
+                    .iter()
+                    .map(|(id, addr)| json!({ "id": id, "address": addr }))
+                    .collect())
+            }
+            "removeBreakpoint" => {
+                let id = params["id"].as_u64().context("missing id")? as usize;
+                d.breakpoint.remove_by_id(id, d.target.as_mut())?;
+                Ok(Value::Null)
+            }
+            "enableBreakpoint" => {
+                let id = params["id"].as_u64().context("missing id")? as usize;
+                if params["enabled"].as_bool().unwrap_or(true) {
+                    d.breakpoint.enable(id, d.target.as_mut())?;
+                } else {
+                    d.breakpoint.disable(id, d.target.as_mut())?;
+                }
+                Ok(Value::Null)
+            }
+            "continue" | "step" | "next" | "stepInstruction" | "nextInstruction" | "finish" => {
+                self.resume(method)?;
+                Ok(Value::Null)
+            }
+            "kill" => {
+                d.process.kill();
+                self.done = true;
+                Ok(Value::Null)
+            }
+            "detach" => {
+                d.detach()?;
+                self.done = true;
+                Ok(Value::Null)
+            }
+            _ => bail!("Unknown method {}", method),
+        }
+    }
+
+    fn resume(&mut self, method: &str) -> Result<()> {
+        let d = &mut *self.debugger;
+        d.last_status = None;
+        d.last_watch_hit = None;
+        match method {
+            "continue" => d.cont()?,
+            "step" => d.step_line(false)?,
+            "next" => d.step_line(true)?,
+            "stepInstruction" => d.single_step()?,
+            "nextInstruction" => d.step_over()?,
+            _ => d.finish()?,
+        }
+        self.resumed = true;
+        Ok(())
+    }
+
+    fn wait(&mut self) {
+        let d = &mut *self.debugger;
+        self.shared
+            .running
+            .store(d.process.pid.as_raw(), Ordering::SeqCst);
+        self.shared.notify("running", Value::Null);
+        d.wait_for_stop();
+        self.shared.running.store(0, Ordering::SeqCst);
+        self.report_stop();
+    }
+
+    fn report_stop(&mut self) {
+        let d = &*self.debugger;
+        let mut params = self.state();
+        params["reason"] = json!(if d.process.threads.is_empty() {
+            match d.last_status {
+                Some(WaitStatus::Signaled(_, signal, _)) => {
+                    params["signal"] = json!(signal.as_str());
+                }
+                Some(WaitStatus::Exited(_, code)) => params["exitCode"] = json!(code),
+                _ => {}
+            }
+            "exited"
+        } else if self.shared.paused.swap(false, Ordering::SeqCst) {
+            "pause"
+        } else if d.last_watch_hit.is_some() {
+            "watchpoint"
+        } else if let Some(WaitStatus::Stopped(_, signal)) = d.last_status {
+            if signal == Signal::SIGTRAP {
+                let rip = d.read_regs(d.process.tid).map(|r| r.rip).unwrap_or(0);
+                match d.breakpoint.get(rip) {
+                    Some(bp) if bp.kind != BreakpointKind::Internal => {
+                        params["breakpoint"] = json!(bp.id);
+                        "breakpoint"
+                    }
+                    _ => "step",
+                }
+            } else {
+                params["signal"] = json!(signal.as_str());
+                params["description"] = json!(signal_text(signal));
+                "signal"
+            }
+        } else {
+            "step"
+        });
+        self.shared.notify("stopped", params);
+    }
+
+    fn state(&self) -> Value {
+        let d = &*self.debugger;
+        if d.process.threads.is_empty() {
+            return json!({ "status": "exited", "pid": d.process.pid.as_raw() });
+        }
+        let tid = d.process.tid;
+        let rip = d.read_regs(tid).map(|regs| regs.rip).ok();
+        json!({
+            "status": "stopped",
+            "pid": d.process.pid.as_raw(),
+            "tid": tid.as_raw(),
+            "threads": d.process.thread_ids().iter().map(|t| t.as_raw()).collect::<Vec<_>>(),
+            "rip": rip,
+            "location": rip.map(|rip| d.describe_location(rip)),
+        })
+    }
+}
+
+// addresses are numbers or expressions like "$rsp+8" or "main"
+fn address(d: &Debugger, value: &Value) -> Result<u64> {
+    match value {
+        Value::Number(n) => n.as_u64().context("address out of range"),
+        Value::String(s) => d.parse_address(s),
+        _ => bail!("missing address"),
+    }
+}
+
+fn value_string(value: &Value) -> Result<String> {
+    match value {
+        Value::Number(n) => Ok(n.to_string()),
+        Value::String(s) => Ok(s.clone()),
+        _ => bail!("expected a number or an expression"),
+    }
+}
+
+// bytes under an inserted breakpoint go to its saved original byte, the
+// 0xCC stays in place
+fn write_memory(d: &mut Debugger, addr: u64, bytes: &[u8]) -> Result<()> {
+    d.ensure_live()?;
+    let mut current = vec![0u8; bytes.len()];
+    d.read_memory(addr, &mut current)?;
+    for (i, byte) in bytes.iter().enumerate() {
+        match d.breakpoint.get_mut(addr + i as u64) {
+            Some(bp) if bp.enabled => bp.original_byte = *byte,
+            _ => current[i] = *byte,
+        }
+    }
+    d.write_memory(addr, &current)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tungstenite::client::client;
+
+    fn call(ws: &mut WebSocket<TcpStream>, id: u64, method: &str, params: Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        ws.send(Message::Text(request.to_string())).unwrap();
+        loop {
+            let Message::Text(text) = ws.read().unwrap() else {
+                continue;
+            };
+            let message: Value = serde_json::from_str(&text).unwrap();
+            if message["id"] == id {
+                return message;
+            }
+        }
+    }
+
+    #[test]
+    fn test_headless_client() {
+        let mut debugger =
+            Debugger::new("/bin/ls".to_string(), "".to_string(), Vec::new()).unwrap();
+        debugger.process.get_base_addr_from_map();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let gui = thread::spawn(move || {
+            // pages that do not know the token are turned away
+            for query in ["", "?token=guess"] {
+                let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+                assert!(client(format!("ws://127.0.0.1:{}/{}", port, query), stream).is_err());
+            }
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            let url = format!("ws://127.0.0.1:{}/?token=secret", port);
+            let (mut ws, _) = client(url, stream).unwrap();
+
+            let state = call(&mut ws, 1, "state", Value::Null)["result"].clone();
+            assert_eq!(state["status"], "stopped");
+            let rip = state["rip"].as_u64().unwrap();
+
+            let registers = call(&mut ws, 2, "registers", Value::Null);
+            let rip_reg = registers["result"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|r| r["name"] == "rip")
+                .unwrap()
+                .clone();
+            assert_eq!(rip_reg["value"], format!("{:#x}", rip));
+
+            let memory = call(
+                &mut ws,
+                3,
+                "readMemory",
+                json!({ "address": "$rip", "length": 4 }),
+            );
+            assert_eq!(memory["result"]["address"], rip);
+            assert_eq!(memory["result"]["bytes"].as_array().unwrap().len(), 4);
+
+            let disasm = call(&mut ws, 4, "disassemble", json!({ "count": 2 }));
+            let lines = disasm["result"].as_array().unwrap();
+            assert_eq!(lines.len(), 2);
+            assert_eq!(lines[0]["address"], rip);
+            assert!(!lines[0]["bytes"].as_array().unwrap().is_empty());
+
+            // the breakpoint is shadowed in the disassembly
+            let set = call(&mut ws, 5, "setBreakpoint", json!({ "location": rip + 1 }));
+            let id = set["result"][0]["id"].clone();
+            assert_eq!(
+                call(&mut ws, 6, "breakpoints", Value::Null)["result"][0]["id"],
+                id
+            );
+            let again = call(
+                &mut ws,
+                7,
+                "disassemble",
+                json!({ "address": rip, "count": 2 }),
+            );
+            assert_eq!(again["result"], disasm["result"]);
+            call(&mut ws, 8, "removeBreakpoint", json!({ "id": id }));
+
+            let huge = json!({ "address": "$rip", "length": u64::MAX });
+            assert!(call(&mut ws, 9, "readMemory", huge)["error"].is_object());
+            let huge = json!({ "count": u64::MAX });
+            assert!(call(&mut ws, 10, "disassemble", huge)["error"].is_object());
+
+            let error = call(&mut ws, 11, "bogus", Value::Null);
+            assert!(error["error"]["message"]
+                .as_str()
+                .unwrap()
+                .contains("bogus"));
+            call(&mut ws, 12, "kill", Value::Null);
+        });
+        serve_listener(&mut debugger, listener, "secret".to_string()).unwrap();
+        gui.join().unwrap();
+    }
+}
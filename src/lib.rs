@@ -0,0 +1,58 @@
+//! The rusty-dbg engine as a library.
+//!
+//! [`Debugger`] owns a traced process (or a core file, or a remote gdbstub)
+//! together with its symbols, breakpoints and watchpoints. Execution control,
+//! registers and memory live in traits implemented on it, import them from
+//! [`prelude`]. The `rusty-dbg` binary and its REPL, DAP, gdbserver and GUI
+//! frontends are built on the same API.
+//!
+//! ```no_run
+//! use rusty_dbg::prelude::*;
+//! use rusty_dbg::Debugger;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let mut dbg = Debugger::new("./target".into(), "harness".into(), vec!["input".into()])?;
+//! dbg.process.get_base_addr_from_map();
+//! dbg.context.set("all", false)?;
+//! dbg.set_breakpoint_by_input("main")?;
+//!
+//! // resuming only starts the debugee, resume_and_wait handles its events
+//! dbg.cont()?;
+//! dbg.wait_for_stop();
+//! println!("rip = {:#x}", dbg.get_register_value("rip")?);
+//! dbg.patch("(u32*)$rdi", "0x41414141")?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Errors are `anyhow::Error`, the failures worth handling separately, such
+//! as an invalid target or a process that already exited, carry a
+//! [`DebuggerError`] that can be recovered with `downcast_ref`.
+//!
+//! The engine does not parse commands itself, breakpoint command lists and
+//! `cmd()` in scripts only run once `command_runner` is set, e.g. to a
+//! [`CommandRouter`].
+
+pub mod commands;
+pub mod core;
+
+pub use crate::commands::CommandRouter;
+pub use crate::core::breakpoint::{Breakpoint, BreakpointEntry, BreakpointKind};
+pub use crate::core::error::DebuggerError;
+pub use crate::core::map::Map;
+pub use crate::core::process::{Process, Thread};
+pub use crate::core::symbols::{DwarfContext, FunctionInfo};
+pub use crate::core::target::Target;
+pub use crate::core::{CommandRunner, Debugger, DebuggerState};
+
+/// The traits with the operations on a [`Debugger`].
+pub mod prelude {
+    pub use crate::core::backtrace::Backtrace;
+    pub use crate::core::breakpoint_helpers::BreakpointHelper;
+    pub use crate::core::disassembler::Disassembler;
+    pub use crate::core::memory::Memory;
+    pub use crate::core::process_control::ProcessControl;
+    pub use crate::core::registers::Registers;
+    pub use crate::core::stepping::Stepping;
+    pub use crate::core::threads::ThreadControl;
+}
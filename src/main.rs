@@ -1,26 +1,227 @@
-mod commands;
-mod core;
+mod dap;
+mod gui;
 mod repl;
 
+use nix::sys::wait::WaitStatus;
 use repl::Repl;
+use rusty_dbg::commands::source::{init_files, source_file};
+use rusty_dbg::commands::CommandRouter;
+use rusty_dbg::core::gdbserver::GdbServer;
+use rusty_dbg::core::process_control::ProcessControl;
+use rusty_dbg::Debugger;
 use std::env;
+use std::rc::Rc;
+
+// `-x <file>` and `-ex <cmd>`, run in the order given after the init files
+enum Startup {
+    File(String),
+    Command(String),
+}
 
 fn main() {
     env_logger::init();
 
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    let mut startup = Vec::new();
+    let mut batch = false;
+    // the options come before the debugee, whose arguments stay untouched
+    while args.len() > 1 {
+        match (args[1].as_str(), args.get(2)) {
+            ("-x", Some(file)) => startup.push(Startup::File(file.clone())),
+            ("-ex", Some(command)) => startup.push(Startup::Command(command.clone())),
+            ("--batch", _) => {
+                batch = true;
+                args.remove(1);
+                continue;
+            }
+            _ => break,
+        }
+        args.drain(1..3);
+    }
     if args.len() < 2 {
-        println!("Usage: {} <pid|path>", args[0]);
+        println!("Usage: {} [options] <pid|path> [-- args...]", args[0]);
+        println!("       {} --core <core> <path>", args[0]);
+        println!("       {} --remote <host:port|socket> <path>", args[0]);
+        println!(
+            "       {} --gdbserver <[host]:port|socket> <pid|path> [-- args...]",
+            args[0]
+        );
+        println!("       {} --dap", args[0]);
+        println!(
+            "       {} --gui <[host]:port> <pid|path> [-- args...]",
+            args[0]
+        );
+        println!("Options: -x <file>, -ex <command>, --batch");
         return;
     }
     let debuger_process_name: &_ = &args[0].rsplit('/').next().unwrap_or("unknown");
-    let debugee_pid_path: &_ = &args[1];
 
-    let mut dbg = core::Debugger::new(
+    if args[1] == "--dap" {
+        if let Err(e) = dap::serve() {
+            eprintln!("dap: {}", e);
+        }
+        return;
+    }
+    if args[1] == "--core" {
+        let (Some(core), Some(binary)) = (args.get(2), args.get(3)) else {
+            println!("Usage: {} --core <core> <path>", args[0]);
+            return;
+        };
+        let mut dbg = match Debugger::from_core(core, binary, debuger_process_name.to_string()) {
+            Ok(dbg) => dbg,
+            Err(e) => {
+                println!("Failed to open core file: {}", e);
+                return;
+            }
+        };
+        dbg.print_core_summary();
+        start(&mut dbg, &startup, batch);
+        return;
+    }
+    if args[1] == "--remote" {
+        let (Some(address), Some(binary)) = (args.get(2), args.get(3)) else {
+            println!("Usage: {} --remote <host:port|socket> <path>", args[0]);
+            return;
+        };
+        let mut dbg =
+            match Debugger::connect_remote(address, binary, debuger_process_name.to_string()) {
+                Ok(dbg) => dbg,
+                Err(e) => {
+                    println!("Failed to connect to {}: {}", address, e);
+                    return;
+                }
+            };
+        start(&mut dbg, &startup, batch);
+        return;
+    }
+    // the debugee and its arguments follow the listen address
+    let (server, first) = match args[1].as_str() {
+        "--gdbserver" | "--gui" if args.len() > 3 => (Some((&args[1], &args[2])), 3),
+        "--gdbserver" => {
+            println!(
+                "Usage: {} --gdbserver <[host]:port|socket> <pid|path>",
+                args[0]
+            );
+            return;
+        }
+        "--gui" => {
+            println!("Usage: {} --gui <[host]:port> <pid|path>", args[0]);
+            return;
+        }
+        _ => (None, 1),
+    };
+    let debugee_pid_path: &_ = &args[first];
+    let program_args: Vec<String> = match args.get(first + 1).map(String::as_str) {
+        Some("--") => args[first + 2..].to_vec(),
+        _ => args[first + 1..].to_vec(),
+    };
+
+    let mut dbg = match Debugger::new(
         debugee_pid_path.to_string(),
         debuger_process_name.to_string(),
-    );
+        program_args,
+    ) {
+        Ok(dbg) => dbg,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
     dbg.process.get_base_addr_from_map();
-    let mut repl = Repl { debugger: &mut dbg };
+    match server {
+        Some((mode, address)) if mode == "--gui" => {
+            if let Err(e) = gui::serve(&mut dbg, address) {
+                println!("gui: {}", e);
+            }
+            return;
+        }
+        Some((_, address)) => {
+            if let Err(e) = dbg.serve_gdb(address) {
+                println!("gdbserver: {}", e);
+            }
+            return;
+        }
+        None => {}
+    }
+    start(&mut dbg, &startup, batch);
+}
+
+// runs the init files and the startup commands, then gives the prompt or in
+// batch mode exits with the status of the debugee
+fn start(dbg: &mut Debugger, startup: &[Startup], batch: bool) {
+    dbg.command_runner = Some(Rc::new(CommandRouter::new()));
+    for path in init_files() {
+        if let Err(e) = source_file(&path.to_string_lossy(), dbg) {
+            println!("Error: {}", e);
+        }
+    }
+    run_startup(dbg, startup);
+    if batch {
+        // a debugee that is still alive is killed, or detached if attached
+        let status = batch_status(dbg);
+        dbg.release();
+        std::process::exit(status);
+    }
+    let mut repl = Repl { debugger: dbg };
     repl.run();
 }
+
+fn run_startup(dbg: &mut Debugger, startup: &[Startup]) {
+    let router = CommandRouter::new();
+    for item in startup {
+        let result = match item {
+            Startup::File(path) => source_file(path, dbg),
+            Startup::Command(command) => router.execute(command, dbg),
+        };
+        if let Err(e) = result {
+            println!("Error: {}", e);
+        }
+        dbg.wait_for_stop();
+    }
+}
+
+// exit status of the debugee, 0 while it is still alive
+fn batch_status(dbg: &Debugger) -> i32 {
+    match dbg.last_status {
+        _ if !dbg.process.threads.is_empty() => 0,
+        Some(WaitStatus::Exited(_, code)) => code,
+        Some(WaitStatus::Signaled(_, signal, _)) => 128 + signal as i32,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell(script: &str) -> Debugger {
+        let args = vec!["-c".to_string(), script.to_string()];
+        let mut dbg = Debugger::new("/bin/sh".to_string(), "".to_string(), args).unwrap();
+        dbg.process.get_base_addr_from_map();
+        dbg.command_runner = Some(Rc::new(CommandRouter::new()));
+        dbg
+    }
+
+    #[test]
+    fn test_batch_exit_status() {
+        let mut dbg = shell("exit 3");
+        let startup = [
+            Startup::Command("set context all off".to_string()),
+            Startup::Command("c".to_string()),
+        ];
+        run_startup(&mut dbg, &startup);
+        assert_eq!(batch_status(&dbg), 3);
+
+        let mut dbg = shell("kill -SEGV $$");
+        run_startup(&mut dbg, &[Startup::Command("c".to_string())]);
+        // the signal stops the debugee first, the next continue delivers it
+        run_startup(&mut dbg, &[Startup::Command("c".to_string())]);
+        assert_eq!(batch_status(&dbg), 128 + 11);
+
+        // still running, killed on release
+        let mut dbg = shell("exit 3");
+        assert_eq!(batch_status(&dbg), 0);
+        dbg.release();
+        assert!(dbg.process.threads.is_empty());
+    }
+}
@@ -1,8 +1,11 @@
-use crate::commands::CommandRouter;
-use crate::core::process_control::ProcessControl;
-use crate::core::{Debugger, DebuggerState};
 use log::info;
+use rusty_dbg::commands::CommandRouter;
+use rusty_dbg::core::process_control::ProcessControl;
+use rusty_dbg::core::{Debugger, DebuggerState};
 use rustyline::{error::ReadlineError, DefaultEditor};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
 
 pub struct Repl<'a> {
     pub debugger: &'a mut Debugger,
@@ -11,7 +14,10 @@ pub struct Repl<'a> {
 impl<'a> Repl<'a> {
     pub fn run(&mut self) {
         let mut rl = DefaultEditor::new().unwrap();
-        let _ = rl.load_history(".history");
+        let history = history_file();
+        if let Some(path) = &history {
+            let _ = rl.load_history(path);
+        }
 
         loop {
             let state = self.debugger.state.clone();
@@ -20,11 +26,14 @@ impl<'a> Repl<'a> {
                 DebuggerState::Interactive => match rl.readline("rusty-dbg> ") {
                     Ok(line) => {
                         let _ = rl.add_history_entry(&line);
-                        let _ = rl.save_history(".history");
+                        if let Some(path) = &history {
+                            let _ = rl.save_history(path);
+                        }
                         self.handle_command(&line);
                     }
                     Err(ReadlineError::Interrupted) => {
                         println!("^C");
+                        self.debugger.release();
                         std::process::exit(0);
                     }
                     Err(err) => {
@@ -43,3 +52,14 @@ impl<'a> Repl<'a> {
         router.handle(command, self.debugger);
     }
 }
+
+// $XDG_DATA_HOME/rusty-dbg/history, ~/.local/share/rusty-dbg/history by default
+fn history_file() -> Option<PathBuf> {
+    let data = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    let dir = data.join("rusty-dbg");
+    fs::create_dir_all(&dir).ok()?;
+    Some(dir.join("history"))
+}
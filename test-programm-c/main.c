@@ -24,6 +24,7 @@ void third(){
 void second(){
     printf("second");
     third();
+    third();
 }
 
 int first()